@@ -4,7 +4,8 @@ use std::collections::HashMap;
 use std::fs;
 use std::fs::File;
 use std::io::{prelude::*, BufReader};
-use std::path::{Path}; 
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 /// Parse assets from a specified filename into a HashMap.
 /// # Format
@@ -39,20 +40,24 @@ use std::path::{Path};
 /// The paths provided will be used randomly whenever a new intent
 /// is read.
 ///
-/// # Panic
-/// Each intent needs to have, at the very least, one path entry, or else
-/// it will result in a panic.
+/// # Errors
+/// Besides the file not being readable, paths above the first intent and
+/// lines which aren't valid UTF-8 result in an InvalidData error.
 pub fn parse_assets(filename : &str) -> Result<HashMap<String, Vec<String>>, std::io::Error> {
+    let file = File::open(filename)?;
+
+    read_assets(BufReader::new(file), filename)
+}
+
+// Same as parse_assets, reading from any reader (named as the given filename in the errors)
+fn read_assets(reader : impl BufRead, filename : &str) -> Result<HashMap<String, Vec<String>>, std::io::Error> {
     let mut intents : HashMap<String, Vec<String>> = HashMap::new(); 
     let mut files : Vec<String> = Vec::new();
 
-    let file = File::open(filename)?;
-    let reader = BufReader::new(file);
-
     let mut entry = String::new();
 
     for line in reader.lines() {
-        let line = line.unwrap();
+        let line = line?;
 
         if ! line.is_empty() { // ignore blank lines
             if line.starts_with("[") { // new intent
@@ -62,8 +67,10 @@ pub fn parse_assets(filename : &str) -> Result<HashMap<String, Vec<String>>, std
                 entry = line.replace("[", "").replace("]", "").trim().to_owned();
                 //println!("new entry: '{}'", entry);
             } else { // new entry for the current intent
-                assert_ne!(entry, "", "Tried to parse a file entry without an associated intent ([intent_name]...) above.");
-                
+                if entry.is_empty() {
+                    return Err(orphan_entry(filename, &line));
+                }
+
                 files.push(line);
             }
         }
@@ -74,6 +81,18 @@ pub fn parse_assets(filename : &str) -> Result<HashMap<String, Vec<String>>, std
     Ok(intents)
 }
 
+// Error for a line which comes before any [intent_name]
+fn orphan_entry(filename : &str, line : &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData,
+                        format!("{}: '{}' has no associated intent ([intent_name]...) above", filename, line))
+}
+
+// Error for a line of an intent which can't be parsed
+fn invalid_entry(filename : &str, entry : &str, line : &str, expected : &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData,
+                        format!("{}: couldn't parse '{}' for {}, please ensure that it's {}", filename, line, entry, expected))
+}
+
 /// Parse time limits from a specified filename into a HashMap.
 /// # Format
 /// Each intent name needs to be enclosed between [...], and have
@@ -101,9 +120,9 @@ pub fn parse_assets(filename : &str) -> Result<HashMap<String, Vec<String>>, std
 /// The HashMap will have an entry for each intent read, with
 /// a time limit associated to it.
 /// 
-/// # Panic
-/// Each intent needs to have, at the very least, one time limit entry, or else
-/// it will result in a panic.
+/// # Errors
+/// Besides the file not being readable, time limits which aren't numbers and
+/// the ones above the first intent result in an InvalidData error.
 pub fn parse_timings(filename : &str) -> Result<HashMap<String, u64>, std::io::Error> {
     let mut intents : HashMap<String, u64> = HashMap::new(); 
 
@@ -114,15 +133,17 @@ pub fn parse_timings(filename : &str) -> Result<HashMap<String, u64>, std::io::E
     let mut val = 0;
 
     for line in reader.lines() {
-        let line = line.unwrap();
+        let line = line?;
 
         if ! line.is_empty() { // ignore blank lines
             if line.starts_with("[") { // new intent
                 entry = line.replace("[", "").replace("]", "").trim().to_owned();
                 //println!("new entry: '{}'", entry);
             } else { // new entry for the current intent
-                assert_ne!(entry, "", "Tried to parse a file entry without an associated intent ([intent_name]...) above.");
-                val = line.parse::<u64>().expect("Couldn't parse one of the timings, please ensure that it's a valid number (200, 3400...)");
+                if entry.is_empty() {
+                    return Err(orphan_entry(filename, &line));
+                }
+                val = line.parse::<u64>().map_err(|_| invalid_entry(filename, &entry, &line, "a valid number (200, 3400...)"))?;
 
                 intents.insert(entry.clone(), val);
             }
@@ -155,3 +176,356 @@ pub fn load_assets(assets : &HashMap<String, Vec<String>>) -> Result<HashMap<Str
 
     Ok(result)
 }
+
+/// Parse the chronometer step intents from a specified filename into a HashMap.
+/// # Format
+/// Each intent name needs to be enclosed between [...], and have
+/// the amount of time it adds to the chronometer in a new line below it.
+/// Prefixing the amount with a '-' will subtract it instead.
+/// The amount accepts any format understood by parse_duration.
+/// Empty lines are ignored. Multiple amount lines will result
+/// in only the latest one being taken into account.
+///
+/// The file is optional. If it's not present, default_steps is used instead.
+///
+/// # Example file
+/// [5more]
+///
+/// 5m
+/// 
+/// [5less]
+///
+/// -5m
+///
+/// [30secmore]
+///
+/// 30 seconds
+///
+/// # Result
+/// The HashMap will have an entry for each intent read, with
+/// a signed amount of seconds associated to it.
+/// 
+/// # Errors
+/// Besides the file not being readable, amounts which can't be parsed as a
+/// duration and the ones above the first intent result in an InvalidData error.
+pub fn parse_steps(filename : &str) -> Result<HashMap<String, i64>, std::io::Error> {
+    let mut steps : HashMap<String, i64> = HashMap::new(); 
+
+    let file = File::open(filename)?;
+    let reader = BufReader::new(file);
+
+    let mut entry = String::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+
+        if ! line.is_empty() { // ignore blank lines
+            if line.starts_with('[') { // new intent
+                entry = line.replace("[", "").replace("]", "").trim().to_owned();
+            } else { // new entry for the current intent
+                if entry.is_empty() {
+                    return Err(orphan_entry(filename, line));
+                }
+
+                let (sign, amount) = match line.strip_prefix('-') {
+                    Some(amount) => (-1, amount),
+                    None => (1, line.trim_start_matches('+')),
+                };
+
+                let amount = parse_duration(amount).ok_or_else(|| invalid_entry(filename, &entry, line, "a valid duration (5m, 30s, 1:00...)"))?;
+
+                steps.insert(entry.clone(), sign * amount.as_secs() as i64);
+            }
+        }
+    }
+
+    Ok(steps)
+}
+
+/// Chronometer step intents used when no steps file is provided:
+/// 5more, 10more and 20more add 5, 10 and 20 minutes, and 5less, 10less 
+/// and 20less subtract them.
+pub fn default_steps() -> HashMap<String, i64> {
+    let mut steps : HashMap<String, i64> = HashMap::new();
+
+    for minutes in [5, 10, 20].iter() {
+        steps.insert(format!("{}more", minutes), minutes * 60);
+        steps.insert(format!("{}less", minutes), -minutes * 60);
+    }
+
+    steps
+}
+
+/// Longest duration parse_duration accepts (a year), so that whatever it's added to can't
+/// overflow
+pub const MAX_DURATION : Duration = Duration::from_secs(366 * 24 * 60 * 60);
+
+/// Parse a human-readable duration.
+/// # Format
+/// The following formats are accepted:
+/// - Clock-like, with colons: "1:15:00" (hh:mm:ss) or "7:30" (mm:ss).
+/// - Amounts followed by a unit, optionally separated by spaces: "7m30s",
+///   "1h 15m", "90 seconds", "2 hours 5 minutes". Units can be written as
+///   h/hr/hrs/hour/hours, m/min/mins/minute/minutes or s/sec/secs/second/seconds.
+/// - A bare number, which is read as seconds: "90".
+///
+/// # Result
+/// None if the text is empty or can't be parsed, or if the duration is zero or longer than
+/// MAX_DURATION.
+pub fn parse_duration(text : &str) -> Option<Duration> {
+    parse_length(text).filter(|duration| ! duration.is_zero())
+}
+
+// Same as parse_duration, but accepting zero, which the settings use to turn things off
+fn parse_length(text : &str) -> Option<Duration> {
+    let text = text.trim().to_lowercase();
+
+    if text.is_empty() {
+        return None;
+    }
+
+    let seconds = if text.contains(':') { // hh:mm:ss or mm:ss
+        let parts = text.split(':')
+                        .map(|part| part.trim().parse::<u64>().ok())
+                        .collect::<Option<Vec<u64>>>()?;
+
+        match parts[..] {
+            [minutes, seconds] => minutes.checked_mul(60)?.checked_add(seconds)?,
+            [hours, minutes, seconds] => hours.checked_mul(3600)?.checked_add(minutes.checked_mul(60)?)?.checked_add(seconds)?,
+            _ => return None,
+        }
+    } else {
+        let mut total : u64 = 0;
+        let mut chars = text.chars().peekable();
+
+        while chars.peek().is_some() {
+            // Read the amount
+            let mut amount = String::new();
+            while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+                amount.push(c);
+            }
+            let amount = amount.parse::<u64>().ok()?;
+
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+            // And its unit, if there is any
+            let mut unit = String::new();
+            while let Some(c) = chars.next_if(|c| c.is_alphabetic()) {
+                unit.push(c);
+            }
+
+            let multiplier = match unit.as_str() {
+                "h" | "hr" | "hrs" | "hour" | "hours" => 3600,
+                "m" | "min" | "mins" | "minute" | "minutes" => 60,
+                "" | "s" | "sec" | "secs" | "second" | "seconds" => 1,
+                _ => return None,
+            };
+
+            total = total.checked_add(amount.checked_mul(multiplier)?)?;
+
+            while chars.next_if(|c| c.is_whitespace() || *c == ',').is_some() {}
+        }
+
+        total
+    };
+
+    Some(Duration::from_secs(seconds)).filter(|duration| *duration <= MAX_DURATION)
+}
+
+/// Optional settings, such as the paths of the alarm's assets or how long it rings.
+#[derive(Default)]
+pub struct Settings {
+    entries : HashMap<String, Vec<String>>,
+}
+
+impl Settings {
+    /// Parse the settings from a specified filename.
+    /// # Format
+    /// The same one as parse_assets: each setting's name needs to be enclosed between [...],
+    /// and have its value (or values, for the settings which are lists) in new lines below it.
+    /// Empty lines are ignored.
+    ///
+    /// Settings which aren't present take their default value, so the file can be empty.
+    ///
+    /// # Example file
+    /// [alarm_sound]
+    ///
+    /// /home/whoever/bmOS_server/assets/audio/alarm.ogg
+    ///
+    /// [alarm_timeout]
+    ///
+    /// 10m
+    pub fn parse(filename : &str) -> Result<Settings, std::io::Error> {
+        let file = File::open(filename)?;
+
+        Settings::from_reader(BufReader::new(file), filename)
+    }
+
+    /// Parse the settings from any reader, in the same format as parse (such as a string's
+    /// bytes). The errors name it as the given filename.
+    pub fn from_reader(reader : impl BufRead, filename : &str) -> Result<Settings, std::io::Error> {
+        let mut entries = read_assets(reader, filename)?;
+        entries.remove(""); // An empty file results in an empty entry
+
+        Ok(Settings { entries })
+    }
+
+    /// Names of every setting present
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(|key| key.as_str())
+    }
+
+    /// First value of a setting, if present
+    pub fn get(&self, key : &str) -> Option<&str> {
+        self.entries.get(key).and_then(|values| values.first()).map(|value| value.trim())
+    }
+
+    /// First value of a setting, or the provided default if it isn't present
+    pub fn get_or(&self, key : &str, default : &str) -> String {
+        self.get(key).unwrap_or(default).to_owned()
+    }
+
+    /// Every value of a setting, which is empty if it isn't present
+    pub fn get_list(&self, key : &str) -> Vec<String> {
+        self.entries.get(key).map_or(Vec::new(), |values| values.iter().map(|value| value.trim().to_owned()).collect())
+    }
+
+    /// A setting read with parse_duration (where zero is allowed too), or the provided default
+    /// if it isn't present. Values which can't be parsed as a duration are errors.
+    pub fn parse_duration_or(&self, key : &str, default : Duration) -> Result<Duration, String> {
+        match self.get(key) {
+            Some(value) => parse_length(value).ok_or(format!("Couldn't parse the {} setting, please ensure that it's a valid duration (5m, 30s, 1:00...)", key)),
+            None => Ok(default),
+        }
+    }
+}
+
+// What was loaded from an optional file, which is None if it isn't present
+fn optional<T>(loaded : Result<T, std::io::Error>, path : &str) -> Result<Option<T>, String> {
+    match loaded {
+        Ok(loaded) => Ok(Some(loaded)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("Couldn't load {}: {}", path, e)),
+    }
+}
+
+/// Every configuration file BMO needs, loaded (see the crate's documentation for what each one holds)
+pub struct Config {
+    pub folder : PathBuf, // Where the files were loaded from, so that they can be reloaded
+    pub intent_faces : HashMap<String, Vec<Vec<u8>>>, // faces.txt, with the faces already read
+    pub intent_audio : HashMap<String, Vec<Vec<u8>>>, // audio.txt, with the audio tracks already read
+    pub intent_timings : HashMap<String, u64>, // timings.txt
+    pub chronometer_steps : HashMap<String, i64>, // steps.txt, or default_steps if it's not present
+    pub settings : Settings, // settings.txt, or the default settings if it's not present
+}
+
+impl Config {
+    /// Load the configuration files from the given folder, reading every asset they point to.
+    /// steps.txt and settings.txt are optional, the rest are required to be present. Files
+    /// which are present but can't be parsed are errors, optional or not.
+    pub fn load(folder : impl AsRef<Path>) -> Result<Config, String> {
+        let file = |name : &str| folder.as_ref().join(name).to_string_lossy().into_owned();
+        let read = |name : &str| parse_assets(&file(name)).and_then(|assets| load_assets(&assets))
+                                                         .map_err(|e| format!("Couldn't load {}: {}", file(name), e));
+
+        Ok(Config {
+            folder : folder.as_ref().to_owned(),
+            intent_faces : read("faces.txt")?,
+            intent_audio : read("audio.txt")?,
+            intent_timings : parse_timings(&file("timings.txt")).map_err(|e| format!("Couldn't load {}: {}", file("timings.txt"), e))?,
+            chronometer_steps : optional(parse_steps(&file("steps.txt")), &file("steps.txt"))?.unwrap_or_else(default_steps),
+            settings : optional(Settings::parse(&file("settings.txt")), &file("settings.txt"))?.unwrap_or_default(),
+        })
+    }
+
+    /// Check that the configuration doesn't ask for anything BMO wasn't built with (see the
+    /// features module)
+    pub fn validate(&self) -> Result<(), String> {
+        crate::features::check_settings(&self.settings)?;
+        crate::main_loop::access::Access::from_settings(&self.settings)?;
+        crate::main_loop::limits::Limits::from_settings(&self.settings, std::sync::Arc::new(crate::main_loop::SystemClock))?;
+        crate::main_loop::alarms::AlarmSettings::from_settings(&self.settings)?;
+        #[cfg(feature = "text")]
+        crate::main_loop::clock::ClockSettings::from_settings(&self.settings)?;
+        #[cfg(feature = "weather")]
+        crate::main_loop::weather::WeatherSettings::from_settings(&self.settings)?;
+        #[cfg(feature = "weather")]
+        {
+            use crate::main_loop::weather_reactions::{WeatherReactions, WEATHER_REACTIONS_FILE};
+            WeatherReactions::load(WEATHER_REACTIONS_FILE, chrono::Local::now().naive_local())?;
+        }
+        if let Some(fps) = self.settings.get("record_fps") {
+            crate::main_loop::capture::frame_interval(fps).map_err(|e| format!("Couldn't parse the record_fps setting: {}", e))?;
+        }
+        #[cfg(feature = "http")]
+        crate::main_loop::http::HttpSettings::from_settings(&self.settings)?;
+        #[cfg(feature = "mqtt")]
+        crate::main_loop::mqtt::MqttSettings::from_settings(&self.settings)?;
+        #[cfg(feature = "discovery")]
+        crate::main_loop::discovery::DiscoverySettings::from_settings(&self.settings)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_are_parsed() {
+        assert_eq!(parse_duration("7m30s"), Some(Duration::from_secs(450)));
+        assert_eq!(parse_duration("1:15:00"), Some(Duration::from_secs(4500)));
+        assert_eq!(parse_duration("2 hours 5 minutes"), Some(Duration::from_secs(7500)));
+        assert_eq!(parse_duration("90"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("soon"), None);
+    }
+
+    #[test]
+    fn zero_and_huge_durations_are_refused() {
+        for text in ["0", "0:00", "0h 0m", "99999999999999999h", "18446744073709551615:59", "9000h"] {
+            assert_eq!(parse_duration(text), None, "{}", text);
+        }
+        assert_eq!(parse_length("0"), Some(Duration::ZERO)); // Settings can still be 0
+    }
+
+    #[test]
+    fn malformed_files_are_errors() {
+        let path = std::env::temp_dir().join(format!("bmos_malformed_{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        for contents in [&b"faces/hello.png\n[hello]\n"[..], b"[hello]\n\xff\n"] {
+            fs::write(path, contents).unwrap();
+            assert!(parse_assets(path).is_err(), "{:?}", contents);
+        }
+        for contents in ["200\n[hello]\n", "[hello]\nsoon\n"] {
+            fs::write(path, contents).unwrap();
+            assert!(parse_timings(path).is_err(), "{:?}", contents);
+            assert!(parse_steps(path).is_err(), "{:?}", contents);
+        }
+
+        fs::write(path, "[5more]\n5m\n[hello]\nsoon\n").unwrap();
+        assert_eq!(parse_steps(path).unwrap_err().to_string(),
+                   format!("{}: couldn't parse 'soon' for hello, please ensure that it's a valid duration (5m, 30s, 1:00...)", path));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn malformed_settings_are_refused() {
+        let mut keys = vec!["alarm_timeout", "alarm_ramp", "snooze"];
+        if cfg!(feature = "text") {
+            keys.extend(["clock_zones", "clock_rotation"]);
+        }
+        if cfg!(feature = "weather") {
+            keys.push("weather_refresh");
+        }
+
+        for key in keys {
+            let settings = Settings::from_reader(format!("[{}]\nsoon\n", key).as_bytes(), "settings.txt").unwrap();
+            let config = Config { folder : PathBuf::new(), intent_faces : HashMap::new(), intent_audio : HashMap::new(), intent_timings : HashMap::new(),
+                                  chronometer_steps : HashMap::new(), settings };
+            assert!(config.validate().unwrap_err().contains(key), "{}", key);
+        }
+    }
+}
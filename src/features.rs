@@ -0,0 +1,114 @@
+//! Optional subsystems, chosen with cargo features when building BMO:
+//! - **weather** : Weather reports, the weather reactions and the weather screen.
+//! - **audio-soloud** : Playing the audio tracks on the sound device (audio setting "soloud").
+//! - **text** : Every screen with text: the chronometer, stopwatch, clock and weather screens,
+//!   and the timers' and alarms' labels.
+//! - **tcp** : Receiving intents through a TCP socket.
+//! - **tls** : Encrypting that socket with TLS.
+//! - **discovery** : Announcing BMO on the network, so that clients can find its TCP socket.
+//! - **unix** : Receiving intents through a Unix domain socket (only on Unix systems).
+//! - **http** : The HTTP API for sending intents and looking at BMO's state.
+//! - **mqtt** : Receiving intents and publishing BMO's state through an MQTT broker.
+//!
+//! All of them are built by default. Whatever a configuration asks for from a feature which
+//! wasn't built is refused with an error when the server is built (see Config::validate), and
+//! the preset intents which need one are ignored.
+
+use crate::config::Settings;
+
+/// Every optional feature, along with whether BMO was built with it
+pub const FEATURES : [(&str, bool); 9] = [
+    ("weather", cfg!(feature = "weather")),
+    ("audio-soloud", cfg!(feature = "audio-soloud")),
+    ("text", cfg!(feature = "text")),
+    ("tcp", cfg!(feature = "tcp")),
+    ("tls", cfg!(feature = "tls")),
+    ("discovery", cfg!(feature = "discovery")),
+    ("unix", cfg!(all(feature = "unix", unix))),
+    ("http", cfg!(feature = "http")),
+    ("mqtt", cfg!(feature = "mqtt")),
+];
+
+// Settings which are only read by the subsystem behind a feature, by their name or its prefix
+const FEATURE_SETTINGS : [(&str, &str); 8] = [
+    ("weather_", "weather"),
+    ("clock_", "text"),
+    ("font", "text"),
+    ("tls_", "tls"),
+    ("discovery", "discovery"),
+    ("http_", "http"),
+    ("mqtt_", "mqtt"),
+    ("unix_socket_", "unix"),
+];
+
+// Preset intents, and the features they need to be shown
+const FEATURE_INTENTS : [(&str, &[&str]); 4] = [
+    ("chronometer", &["text"]),
+    ("stopwatch", &["text"]),
+    ("clock", &["text"]),
+    ("weather", &["weather", "text"]),
+];
+
+/// Was BMO built with the given feature?
+pub fn enabled(feature : &str) -> bool {
+    FEATURES.iter().any(|(name, enabled)| *name == feature && *enabled)
+}
+
+/// Fail with a clear error if BMO wasn't built with the given feature, which what needs
+pub fn require(feature : &str, what : &str) -> Result<(), String> {
+    if enabled(feature) {
+        Ok(())
+    } else {
+        Err(format!("{} needs the {} feature, but BMO was built without it (rebuild it with --features {})", what, feature, feature))
+    }
+}
+
+/// First feature a preset intent needs which BMO wasn't built with, if any
+pub fn missing_for_intent(intent : &str) -> Option<&'static str> {
+    FEATURE_INTENTS.iter()
+                   .filter(|(name, _)| *name == intent)
+                   .flat_map(|(_, features)| features.iter())
+                   .find(|feature| ! enabled(feature))
+                   .copied()
+}
+
+/// Check that the settings don't ask for anything from a feature BMO wasn't built with
+pub fn check_settings(settings : &Settings) -> Result<(), String> {
+    for key in settings.keys() {
+        for (prefix, feature) in FEATURE_SETTINGS.iter() {
+            if key.starts_with(prefix) {
+                require(feature, &format!("The {} setting", key))?;
+            }
+        }
+    }
+
+    if settings.get("audio") == Some("soloud") {
+        require("audio-soloud", "The soloud audio setting")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intents_need_every_feature_they_use() {
+        assert_eq!(missing_for_intent("hello"), None);
+
+        let weather = missing_for_intent("weather");
+        match (enabled("weather"), enabled("text")) {
+            (true, true) => assert_eq!(weather, None),
+            (false, _) => assert_eq!(weather, Some("weather")),
+            (true, false) => assert_eq!(weather, Some("text")),
+        }
+    }
+
+    #[test]
+    fn errors_name_the_missing_feature() {
+        assert!(require("tcp", "Listening on 127.0.0.1:2300").is_ok() == enabled("tcp"));
+        assert_eq!(require("missing", "Something").unwrap_err(),
+                   "Something needs the missing feature, but BMO was built without it (rebuild it with --features missing)");
+    }
+}
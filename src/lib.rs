@@ -0,0 +1,67 @@
+//! # bmOS_server
+//!
+//! bmOS_server is in charge of receiving intents and rendering their associated BMO-faces and
+//! playing audio tracks. It's both an executable and a library, so that BMO can be embedded in
+//! other launchers and test harnesses (see Embedding).
+//!
+//! ## Configuration files
+//! The following configuration files are required to be present in the same folder the executable is in:
+//! - **faces.txt** : Indicates the image files of BMO's faces to be shown for each intent. It's mandatory to have at least one entry for each intent which will be sent from the client, except for the weather and chronometer functionalities preset intents. Otherwise, the application will panic.
+//! - **audio.txt** : Indicates the audio tracks to be played for each intent. It can be empty
+//! - **timings.txt** : Indicates the time limits for each intent. It is mandatory to have one entry for each intent without an audio track, again excluding the preset intents.
+//! - **steps.txt** (optional) : Indicates the chronometer's step intents and how much time each one adds or subtracts. If it's not present, 5more/10more/20more and 5less/10less/20less are used.
+//! - **settings.txt** (optional) : Indicates optional settings, such as the paths of the alarm's assets. Settings which aren't present take their default values.
+//! 
+//! **Information about the syntax and contents needed in each of the configuration files is present in the documentation of the functions inside the config module.**
+//!
+//! ## Mandatory intents
+//! The following intents are mandatory to have faces defined in faces.txt:
+//! - **"default"**: In order to show BMO's default/fallback face.
+//!
+//! The following files are mandatory to be present in the executables folder, unless a different path is set for them in settings.txt:
+//! - **./assets/faces/alarm.jpg** (alarm_face) : Alarm face to be shown after a chronometer finishes.
+//! - **./assets/audio/alarm.wav** (alarm_sound) : Alarm audio track to be played after a chronometer finishes.
+//! - **./assets/font.ttf** (font) : Font to be used when showing text. I recommend [Video Terminal Screen](https://ttfonts.net/en/download/62485.htm)
+//! 
+//! ## Shutdown
+//! - bmOS_server listens for key inputs. If the escape key is pressed, the application will exit.
+//! - If it's running in a headless server, BMO keeps running when bmOS_client (or any other source sending intents to it) disconnects, so it has to be stopped like any other service. With the exit_on_disconnect setting on, closing the client shuts BMO down instead, as it originally did.
+//! ## Assumptions
+//! The following assumptions are made when running this application:
+//! - openAL, SDL2 and SDL2-ttf libraries are installed in the system (unless BMO was built without the
+//!   audio-soloud and text features, see the features module)
+//! - If the device running the bmOS_server is the audio (microphone) source, it's streaming it to the device running bmOS_client by some other means. bmOS_server does not record any audio, and only listens to strings received to its provided address.
+//!
+//! ## Recommendations
+//! - Since the paths for the mandatory files are pre-determined, I advice to have all assets in an "assets" folder wherever the executable is in.
+//! - Provide appropiate timings for each intent. For example, setting 100 for a given intent without an audio track will make it zoom past it and go back to the default state. A good timing that I found for such intents is 4500 (4'5 seconds).
+//!
+//! ## Embedding
+//! The bmos_server library runs BMO just like the executable does, put together with Server::builder:
+//! ```no_run
+//! use bmos_server::{Config, Server};
+//!
+//! Server::builder()
+//!     .config(Config::load("/home/whoever/bmOS_server")?) // Folder with faces.txt, audio.txt...
+//!     .bind("192.168.1.15", 2300)
+//!     .resolution(800, 600)
+//!     .location("Zaragoza", Some("ES"))
+//!     .run()?;
+//! # Ok::<(), String>(())
+//! ```
+//! Besides, the clock telling the time and the audio backend can be replaced, so that BMO can be driven
+//! without waiting in real time (ManualClock) and what it plays can be checked (CaptureAudio).
+//! The config module loads the configuration files, and the main_loop module holds the intent
+//! receiver, the renderer, the audio backends and every mode (chronometer, timers, weather...).
+
+pub mod config;
+pub use crate::config::{parse_assets, parse_timings, Config, Settings};
+
+pub mod main_loop;
+pub use crate::main_loop::{AudioBackend, AudioHandle, CaptureAudio, Clip, Clock, ClockHandle, Event, Events, EventsHandle, ManualClock, NullAudio,
+                           Stamped, SystemClock};
+
+pub mod features;
+
+mod server;
+pub use crate::server::{Server, ServerBuilder};
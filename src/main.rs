@@ -1,80 +1,88 @@
-//! # bmOS_server
-//!
-//! bmOS_server is an executable in charge of receiving intents and rendering their associated BMO-faces and 
-//! playing audio tracks.
-//!
-//! ## Configuration files
-//! The following configuration files are required to be present in the same folder the executable is in:
-//! - **faces.txt** : Indicates the image files of BMO's faces to be shown for each intent. It's mandatory to have at least one entry for each intent which will be sent from the client, except for the weather and chronometer functionalities preset intents. Otherwise, the application will panic.
-//! - **audio.txt** : Indicates the audio tracks to be played for each intent. It can be empty
-//! - **timings.txt** : Indicates the time limits for each intent. It is mandatory to have one entry for each intent without an audio track, again excluding the preset intents.
-//! 
-//! **Information about the syntax and contents needed in each of the configuration files is present in the documentation of the functions inside the config module.**
-//!
-//! ## Mandatory intents
-//! The following intents are mandatory to have faces defined in faces.txt:
-//! - **"default"**: In order to show BMO's default/fallback face.
-//!
-//! The following files are mandatory to be present in the executables folder:
-//! - **./assets/faces/alarm.jpg** : Alarm face to be shown after a chronometer finishes.
-//! - **./assets/audio/alarm.wav** : Alarm audio track to be played after a chronometer finishes.
-//! - **./assets/font.ttf** : Font to be used when showing text. I recommend [Video Terminal Screen](https://ttfonts.net/en/download/62485.htm)
-//! 
-//! ## Shutdown
-//! - bmOS_server listens for key inputs. If the escape key is pressed, the application will exit.
-//! - If it's running in a headless server, the appropiate way of exiting is to close bmOS_client (or any other source) which is sending intents to it. This will trigger a safe shutdown.
-//! ## Assumptions
-//! The following assumptions are made when running this application:
-//! - openAL, SDL2 and SDL2-ttf libraries are installed in the system
-//! - If the device running the bmOS_server is the audio (microphone) source, it's streaming it to the device running bmOS_client by some other means. bmOS_server does not record any audio, and only listens to strings received to its provided address.
-//!
-//! ## Recommendations
-//! - Since the paths for the mandatory files are pre-determined, I advice to have all assets in an "assets" folder wherever the executable is in.
-//! - Provide appropiate timings for each intent. For example, setting 100 for a given intent without an audio track will make it zoom past it and go back to the default state. A good timing that I found for such intents is 4500 (4'5 seconds).
-
-
+//! bmOS_server's executable, running BMO with the configuration files in its folder.
+//! See the bmos_server library for what they hold.
 
 use std::env;
 
 use std::error::Error;
 
-mod config;
-pub use crate::config::{parse_assets, parse_timings};
-
-mod main_loop;
-pub use crate::main_loop::run;
+use bmos_server::{features, Config, Server};
 
 fn main() -> Result<(), Box<dyn Error>> {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() < 5 {
+    // Look for BMOs on the network instead ("./server discover [port]")
+    #[cfg(feature = "discovery")]
+    if args.get(1).map(|arg| arg.as_str()) == Some("discover") {
+        return discover(args.get(2));
+    }
+
+    // An address and a port, the path of a Unix socket ("unix:/run/bmo/bmo.sock"), or both
+    let address = args.get(1).filter(|arg| ! arg.starts_with("unix:")).zip(args.get(2));
+    let mut rest = if address.is_some() { 3 } else { 1 }; // Where the arguments after them start
+    let unix_socket = args.get(rest).and_then(|arg| arg.strip_prefix("unix:")).map(|path| path.to_owned());
+    if unix_socket.is_some() {
+        rest += 1;
+    }
+
+    if args.len() < rest + 2 {
         println!("Incorrect arguments.\n
-                    Usage: ./server own_address own_port resolution_width resolution_height [OpenWeather_API_KEY] [Location (city...)] [Country code]\n
-                    Example: ./server 192.168.1.15 2300 800 600 f07[...]b42 Zaragoza ES");
+                    Usage: ./server [own_address own_port] [unix:socket_path] resolution_width resolution_height [OpenWeather_API_KEY] [Location (city...)] [Country code]\n
+                    Usage: ./server discover [discovery_port]\n
+                    Example: ./server 192.168.1.15 2300 800 600 f07[...]b42 Zaragoza ES\n
+                    Example: ./server unix:/run/bmo/bmo.sock 800 600\n
+                    Example: ./server 192.168.1.15 2300 unix:/run/bmo/bmo.sock 800 600");
         std::process::exit(-1);
     }
 
-    let intent_faces = config::load_assets(&config::parse_assets("faces.txt").unwrap()).unwrap();
-    let intent_audio = config::load_assets(&config::parse_assets("audio.txt").unwrap()).unwrap();
-    let intent_timings = config::parse_timings("timings.txt").unwrap();
+    let config = Config::load(".")?;
 
+    println!("Asset locations parsed successfully, starting...");
 
+    let res_width = args[rest].parse::<u32>()?;
+    let res_height = args[rest + 1].parse::<u32>()?;
 
-    println!("Asset locations parsed successfully, starting...");
+    let mut server = Server::builder().config(config).resolution(res_width, res_height);
 
-    let res_width = args[3].clone().parse::<u32>().unwrap();
-    let res_height = args[4].clone().parse::<u32>().unwrap();
+    if let Some((address, port)) = address {
+        let port = port.parse::<u16>()?;
 
-    if args.len() == 6 {
-        main_loop::run(args[1].clone(), args[2].clone(), res_width, res_height, 
-                        Some(args[5].clone()), Some(args[6].clone()), 
-                        Some(args[7].clone()), intent_faces, intent_audio, intent_timings).unwrap();
-    } else {
-        main_loop::run(args[1].clone(), args[2].clone(), res_width, res_height, 
-                        None, None, None, intent_faces, intent_audio, intent_timings).unwrap();
+        if features::enabled("tcp") {
+            server = server.bind(address, port);
+        } else {
+            println!("Built without the tcp feature, not listening on {}:{}", address, port);
+        }
+    }
+    if let Some(path) = unix_socket {
+        server = server.unix_socket(path);
     }
 
-    
+    if let Some(api_key) = args.get(rest + 2) {
+        server = server.api_key(api_key);
+    }
+    if let Some(location) = args.get(rest + 3) {
+        server = server.location(location, args.get(rest + 4).map(|country| country.as_str()));
+    }
+
+    server.run()?;
+
+    Ok(())
+}
+
+#[cfg(feature = "discovery")]
+fn discover(port : Option<&String>) -> Result<(), Box<dyn Error>> {
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+    use bmos_server::main_loop::discovery::{self, DISCOVERY_PORT};
+
+    let port = port.map_or(Ok(DISCOVERY_PORT), |port| port.parse::<u16>())?;
+    let found = discovery::discover((Ipv4Addr::BROADCAST, port).into(), Duration::from_secs(2))?;
+
+    if found.is_empty() {
+        println!("No BMO answered on UDP port {}", port);
+    }
+    for (address, announcement) in found {
+        println!("{} : {} (protocol version {})", address, announcement.name, announcement.version);
+    }
 
     Ok(())
 }
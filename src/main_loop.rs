@@ -2,52 +2,211 @@ extern crate random_number;
 extern crate sdl2;
 
 // SDL libs
-use sdl2::event::Event;
+use sdl2::event::Event as SdlEvent;
 use sdl2::image::{InitFlag, LoadTexture};
 use sdl2::keyboard::Keycode;
+use sdl2::render::{Canvas, TextureCreator};
 
-use std::path::{Path}; // for providing paths to SDL
-use std::time::Duration; // Sleeping
-use std::collections::HashMap;
+use std::time::{Duration, Instant}; // Sleeping and timings
+use std::cell::Cell;
+use std::collections::VecDeque;
 
 // Threads and synchronization for audio and communications with the server
 use std::sync::{Arc, Mutex, Condvar}; 
+#[cfg(any(feature = "tcp", all(feature = "unix", unix)))]
 use std::thread;
 
-// Wrapper around rodio
-mod audio_player;
-pub use crate::main_loop::audio_player::{play_sound, play_sound_blocking};
+// Playing audio tracks in the background
+pub mod audio_player;
+pub use crate::main_loop::audio_player::{play_sound, play_sound_looping};
+
+// Whatever plays the audio tracks (a sound device, or nothing at all)
+pub mod audio_backend;
+pub use crate::main_loop::audio_backend::{AudioBackend, AudioHandle, Clip, NullAudio};
+#[cfg(feature = "audio-soloud")]
+pub use crate::main_loop::audio_backend::SoloudAudio;
+
+// Audio tracks written down instead of played
+pub mod audio_capture;
+pub use crate::main_loop::audio_capture::{CaptureAudio, Played};
 
 // Communications with the server, where we will receive new intents
-mod intent_receiver;
+pub mod intent_receiver;
+// Who may send them: allowed peers, token and TLS
+pub mod access;
+// What they're held to: line length, rate, idle timeout and intent names
+pub mod limits;
+// Announcing BMO on the network, so that clients can find it
+#[cfg(feature = "discovery")]
+pub mod discovery;
+pub use crate::main_loop::intent_receiver::Pipeline;
+#[cfg(feature = "tcp")]
 pub use crate::main_loop::intent_receiver::listen;
+#[cfg(all(feature = "unix", unix))]
+pub use crate::main_loop::intent_receiver::listen_unix;
+
+// HTTP API for sending intents and looking at BMO's state
+#[cfg(feature = "http")]
+pub mod http;
+
+// MQTT client for home automation
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+
+// What BMO does, for whoever wants to follow it
+pub mod events;
+pub use crate::main_loop::events::{Event, Events, EventsHandle, ObservedAudio, Stamped};
+
+// Intents available, and reloading the configuration files
+pub mod catalog;
+use crate::main_loop::catalog::{Catalog, CatalogMutex};
 
 // Chronometer functions
-mod chronometer;
-pub use crate::main_loop::chronometer::{get_time, display_chronometer};
+pub mod chronometer;
+pub use crate::main_loop::chronometer::{as_string, get_centered_rect};
+#[cfg(feature = "text")]
+pub use crate::main_loop::chronometer::{get_time, display_chronometer, draw_text};
+
+// Wall-clock alarms and reminders
+pub mod alarms;
+use crate::main_loop::alarms::{Alarms, AlarmsMutex, AlarmSettings, ALARMS_FILE, Ringer};
+
+// Stopwatch (count-up chronometer)
+pub mod stopwatch;
+use crate::main_loop::stopwatch::{Stopwatch, StopwatchMutex};
+#[cfg(feature = "text")]
+use crate::main_loop::stopwatch::show_stopwatch;
+
+// Named timers running in the background
+pub mod timers;
+use crate::main_loop::timers::{Timers, TimersMutex};
+#[cfg(feature = "text")]
+use crate::main_loop::timers::draw_overlay;
+
+#[cfg(feature = "text")]
+use crate::config::parse_duration;
+use crate::config::Config;
+use crate::features;
+use crate::server::Server;
+
+// Clock and world clock
+#[cfg(feature = "text")]
+pub mod clock;
+#[cfg(feature = "text")]
+use crate::main_loop::clock::{ClockSettings, show_clock};
 
 // Weather function
-mod weather;
+#[cfg(feature = "weather")]
+pub mod weather;
+#[cfg(feature = "weather")]
+use crate::main_loop::weather::WeatherSettings;
+#[cfg(all(feature = "weather", feature = "text"))]
 use crate::main_loop::weather::show_weather;
 
+// Sources of the weather reports
+#[cfg(feature = "weather")]
+pub mod weather_provider;
+
+// Weather reports refreshed in the background
+#[cfg(feature = "weather")]
+pub mod weather_cache;
+#[cfg(feature = "weather")]
+use crate::main_loop::weather_cache::{WeatherCache, WeatherCacheMutex, spawn_refresher};
+
+// Places looked up for the weather
+#[cfg(feature = "weather")]
+pub mod places;
+#[cfg(feature = "weather")]
+use crate::main_loop::places::{Places, PLACES_FILE};
+
+// Faces and announcements reacting to the weather
+#[cfg(feature = "weather")]
+pub mod weather_reactions;
+#[cfg(feature = "weather")]
+use crate::main_loop::weather_reactions::{WeatherReactions, WEATHER_REACTIONS_FILE};
+
+// Screenshots and frame recordings
+pub mod capture;
+pub use crate::main_loop::capture::{Capture, CaptureMutex, present};
+
+// Where BMO is drawn
+pub mod display;
+pub use crate::main_loop::display::{Backend, window_canvas, offscreen_canvas};
+
+// Real and virtual time
+pub mod timekeeping;
+pub use crate::main_loop::timekeeping::{Clock, ClockHandle, SystemClock, ManualClock};
+
 /// State of BMO's current face and audio track
 pub struct State {
     pub current_intent : String, // Current intent, updated on listen()
+    pub current_args : String, // Arguments sent along with the current intent (if any), updated on listen()
+    pub shown : String, // Intent being shown, which might not be the last one received, updated on the main loop
     pub audio_finished : bool, // Did the audio track (if played) finish already?, updated on play_sound()
     pub new_intent : bool // Is there a new intent available?, updated on listen()
 }
 
 impl State {
     pub fn new() -> State {
-        State { current_intent : "default".to_owned(), audio_finished : false, new_intent : false }
+        State { current_intent : "default".to_owned(), current_args : "".to_owned(), shown : "default".to_owned(), audio_finished : false, new_intent : false }
+    }
+}
+
+impl Default for State {
+    fn default() -> State {
+        State::new()
     }
 }
 
 // Thread-safe State instance
 pub type StateMutex = Arc<Mutex<State>>;
 
+/// Whatever BMO draws text with, which is nothing if it was built without the text feature
+#[cfg(feature = "text")]
+pub type TextContext = sdl2::ttf::Sdl2TtfContext;
+#[cfg(not(feature = "text"))]
+pub struct TextContext;
+
+/// What BMO is drawn with, handed to every mode (chronometer, stopwatch, clock, weather) so
+/// that they draw the same way the main loop does: the canvas and its texture creator, the
+/// resolution, the text context and the font, the capture of the frames presented, the clock
+/// timing them and the Ringer firing the alarms due meanwhile (see ring_due).
+pub struct Screen<'a, T : sdl2::render::RenderTarget, U> {
+    pub res_width : u32,
+    pub res_height : u32,
+    pub canvas : Canvas<T>,
+    pub texture_creator : TextureCreator<U>,
+    pub ttf_context : &'a TextContext,
+    pub font_path : &'a str,
+    pub capture : &'a CaptureMutex,
+    pub clock : &'a dyn Clock,
+    pub ringer : &'a Ringer<'a>,
+}
+
+impl<T : sdl2::render::RenderTarget, U> Screen<'_, T, U> {
+    /// Present the canvas (see capture::present)
+    pub fn present(&mut self) {
+        present(&mut self.canvas, self.capture);
+    }
+
+    /// Fire the alarms due, right after presenting a frame (see Ringer::ring_due). Whoever
+    /// draws has to return once the ringer is told to quit.
+    pub fn ring_due(&mut self) -> Result<(), String> {
+        let ringer = self.ringer;
+        ringer.ring_due(self)
+    }
+
+    /// Fire the alarm right away, with an optional label (see Ringer::ring)
+    pub fn ring(&mut self, label : Option<&str>) -> Result<(), String> {
+        let ringer = self.ringer;
+        ringer.ring(self, label)
+    }
+}
+
 const CHRONOMETER_STATE : &str = "chronometer";
 const WEATHER_STATE : &str = "weather";
+const STOPWATCH_STATE : &str = "stopwatch";
+const CLOCK_STATE : &str = "clock";
 //const RES_WIDTH : u32 = 320;
 //const RES_HEIGHT : u32 = 240;
 
@@ -61,73 +220,225 @@ const WEATHER_STATE : &str = "weather";
 /// audio track (if any) stops playing. If it doesn't have any audio track, the
 /// time limit will dictate how many milliseconds will the intent stay before changing.
 ///
-/// The main loop ends upong pressing Escape.
-pub fn run( address : String, port : String, 
-            res_width : u32, res_height : u32,
-            api_key : Option<String>, location : Option<String>, 
-            country : Option<String>, 
-            intent_faces : HashMap<String, Vec<Vec<u8>>>, 
-            intent_audio : HashMap<String, Vec<Vec<u8>>>,
-            intent_timings : HashMap<String, u64> ) -> Result<(), String> {
+/// The chronometer's step intents (5more, 5less...) and the amount of seconds
+/// they add or subtract are provided by chronometer_steps.
+///
+/// Named timers run in the background while the faces are shown, with their
+/// remaining time drawn on top of them. Whenever one finishes, its alarm is fired.
+/// The same goes for the wall-clock alarms and reminders, which are stored on ALARMS_FILE.
+///
+/// The main loop ends upong pressing Escape, and F12 takes a screenshot (see Capture).
+///
+/// BMO is drawn on a window, or on an offscreen surface if the display setting is "offscreen"
+/// (see Backend), so that it can run without a display.
+///
+/// Everything is timed with the server's clock (SystemClock unless another one was provided),
+/// and the audio tracks are played with its audio backend (see audio_backend::from_settings).
+///
+/// Every transition (intents shown, modes entered and exited, timers and alarms fired, audio
+/// tracks played...) is published to the server's events (see Event).
+///
+/// Intents are received through TCP and/or a Unix socket, through the HTTP API if the
+/// http_address setting is present (see http::spawn), and through an MQTT broker if the
+/// mqtt_host setting is present (see mqtt::spawn). The configuration files reloaded meanwhile
+/// (see Catalog::reload) are picked up on the next iteration.
+pub fn run(server : Server) -> Result<(), String> {
     // SDL initialization
     let sdl_context = sdl2::init()?;
-    let video_subsystem = sdl_context.video()?;
     let _image_context = sdl2::image::init(InitFlag::PNG | InitFlag::JPG)?;
-    let window = video_subsystem
-        .window("rust-sdl2 demo: Video", res_width, res_height)
-        .position_centered()
-        .opengl()
-        .build()
-        .map_err(|e| e.to_string())?;
-
-    let mut canvas = window
-        .into_canvas()
-        //.software()
-        .build()
-        .map_err(|e| e.to_string())?;
-    let texture_creator = canvas.texture_creator();    
 
+    match Backend::from_settings(&server.config.settings) {
+        Backend::Window => {
+            let canvas = window_canvas(&sdl_context, server.res_width, server.res_height)?;
+            let texture_creator = canvas.texture_creator();
+            run_on(canvas, texture_creator, &sdl_context, server)
+        }
+        Backend::Offscreen => {
+            let canvas = offscreen_canvas(server.res_width, server.res_height)?;
+            let texture_creator = canvas.texture_creator();
+            run_on(canvas, texture_creator, &sdl_context, server)
+        }
+    }
+}
+
+// Main loop drawing on the given canvas, whichever its backend is
+fn run_on<T: sdl2::render::RenderTarget, U>(canvas : Canvas<T>, texture_creator : TextureCreator<U>,
+                                            sdl_context : &sdl2::Sdl, server : Server) -> Result<(), String> {
+    let Server { config, address, unix_socket, res_width, res_height, api_key, location, country, clock, audio, events } = server;
+    let catalog : CatalogMutex = Arc::new(Mutex::new(Catalog::new(&config)));
+    let Config { folder : _, mut intent_faces, mut intent_audio, mut intent_timings, mut chronometer_steps, settings } = config;
+
+    #[cfg(feature = "text")]
     let ttf_context = sdl2::ttf::init().map_err(|e| e.to_string())?;
+    #[cfg(not(feature = "text"))]
+    let ttf_context = TextContext;
 
     let state : StateMutex = Arc::new(Mutex::new(State::new()));
+    let timers : TimersMutex = Arc::new(Mutex::new(Timers::new(Arc::clone(&clock))));
+    let stopwatch : StopwatchMutex = Arc::new(Mutex::new(Stopwatch::new(Arc::clone(&clock))));
+    let alarm_settings = AlarmSettings::from_settings(&settings)?;
+    let alarms : AlarmsMutex = Arc::new(Mutex::new(Alarms::load(ALARMS_FILE, alarm_settings.snooze, Arc::clone(&clock))));
+    let capture : CaptureMutex = Arc::new(Mutex::new(Capture::from_settings(&settings, Arc::clone(&clock))));
+    let audio : AudioHandle = Arc::new(ObservedAudio::new(audio.unwrap_or_else(|| audio_backend::from_settings(&settings, Arc::clone(&clock))),
+                                                          Arc::clone(&events)));
+    let font_path = settings.get_or("font", "assets/font.ttf");
+    #[cfg(feature = "text")]
+    let clock_settings = ClockSettings::from_settings(&settings)?;
+
+    #[cfg(feature = "weather")]
+    let weather_settings = WeatherSettings::from_settings(&settings)?;
+    #[cfg(feature = "weather")]
+    let weather_location = match (location, country) {
+        (Some(location), Some(country)) => format!("{},{}", location, country),
+        (Some(location), None) => location,
+        _ => "".to_owned(),
+    };
+    #[cfg(not(feature = "weather"))]
+    let _ = (api_key, location, country); // Refused by Server::build anyway
+
+    // Keep the weather up to date in the background, so that it can be shown right away
+    #[cfg(feature = "weather")]
+    let weather_cache : Option<WeatherCacheMutex> = weather_provider::from_settings(&settings, api_key).map(|provider| {
+        let cache : WeatherCacheMutex = Arc::new(Mutex::new(WeatherCache::new(&weather_location, &weather_settings.favourites)));
+        spawn_refresher(provider, Places::load(PLACES_FILE), weather_settings.refresh, Arc::clone(&clock), Arc::clone(&cache));
+        cache
+    });
+    #[cfg(feature = "weather")]
+    let mut weather_reactions = WeatherReactions::load(WEATHER_REACTIONS_FILE, clock.local_now().naive_local())?;
+    let mut announcements : VecDeque<String> = VecDeque::new(); // Weather intents waiting for BMO to be idle
    
     let new_intent_available = Arc::new((Mutex::new(false), Condvar::new())); // Condvar for auxiliary functions (chronometer...)
     
-    let new_intent_available_clone = Arc::clone(&new_intent_available);
-    // The thread closure captures the parameters, so we need to declare them cloned beforehand, and then move them inside
-    let state_clone = Arc::clone(&state);
+    // Everything the intents are received with shares the same pipeline
+    let pipeline = Pipeline {
+        state : Arc::clone(&state),
+        new_intent_available : Arc::clone(&new_intent_available),
+        timers : Arc::clone(&timers),
+        stopwatch : Arc::clone(&stopwatch),
+        alarms : Arc::clone(&alarms),
+        capture : Arc::clone(&capture),
+        audio : Arc::clone(&audio),
+        catalog : Arc::clone(&catalog),
+        events : Arc::clone(&events),
+    };
+
+    #[cfg(feature = "http")]
+    if let Some(http_settings) = http::HttpSettings::from_settings(&settings)? {
+        http::spawn(http_settings, pipeline.clone(), limits::Limits::from_settings(&settings, Arc::clone(&clock))?)?;
+    }
+    #[cfg(feature = "mqtt")]
+    if let Some(mqtt_settings) = mqtt::MqttSettings::from_settings(&settings)? {
+        mqtt::spawn(mqtt_settings, pipeline.clone());
+    }
 
-    thread::spawn(move || {intent_receiver::listen(address, port, state_clone, new_intent_available_clone).unwrap(); });
+    #[cfg(all(feature = "unix", unix))]
+    if let Some(path) = unix_socket {
+        let listener = intent_receiver::bind_unix(&path, intent_receiver::unix_socket_mode(&settings)?)?;
+        let pipeline = pipeline.clone();
+        println!("Listening on {}", path.display());
+        let access = access::Access::from_settings(&settings)?;
+        let limits = limits::Limits::from_settings(&settings, Arc::clone(&clock))?;
+        let exit_on_disconnect = intent_receiver::exit_on_disconnect(&settings)?;
+        thread::spawn(move || intent_receiver::listen_unix(listener, pipeline, access, limits, exit_on_disconnect));
+    }
+    #[cfg(not(all(feature = "unix", unix)))]
+    let _ = unix_socket; // Refused by Server::build anyway
+
+    #[cfg(feature = "tcp")]
+    if let Some((address, port)) = address {
+        let access = access::Access::from_settings(&settings)?;
+        let limits = limits::Limits::from_settings(&settings, Arc::clone(&clock))?;
+        let exit_on_disconnect = intent_receiver::exit_on_disconnect(&settings)?;
+        #[cfg(feature = "discovery")]
+        if let Some(discovery_settings) = discovery::DiscoverySettings::from_settings(&settings)? {
+            if let Err(e) = discovery::spawn(discovery_settings, &address, port) {
+                eprintln!("{}, BMO won't be announced on the network", e); // It can still be reached
+            }
+        }
+        thread::spawn(move || {
+            if let Err(e) = intent_receiver::listen(address.clone(), port.to_string(), pipeline, access, limits, exit_on_disconnect) {
+                eprintln!("Couldn't listen on {}:{}, no intents will be received through TCP: {}", address, port, e);
+            }
+        });
+    }
+    #[cfg(not(feature = "tcp"))]
+    {
+        let _ = (address, pipeline);
+        println!("Built without the tcp feature, no intents will be received through TCP");
+    }
+
+    // Font for the timers overlay
+    #[cfg(feature = "text")]
+    let mut overlay_font = ttf_context.load_font(&font_path, 24)?;
+    #[cfg(feature = "text")]
+    overlay_font.set_style(sdl2::ttf::FontStyle::BOLD);
+
+    // Fires the timers and alarms as they're due, whichever mode is shown
+    let ringer = Ringer { sdl_context, settings : &alarm_settings, alarms : &alarms, timers : &timers, audio : &audio, events : &events,
+                          quit : Cell::new(false) };
+    let mut screen = Screen { res_width, res_height, canvas, texture_creator, ttf_context : &ttf_context, font_path : &font_path, capture : &capture,
+                              clock : clock.as_ref(), ringer : &ringer };
 
     // Status variables
     let sleep_time = 100; // milliseconds between each iteration
     
     let mut time_limit = 0; // Time limit for the intent, dictated on "timings.txt"
-    let mut time_slept = u64::MAX; // Counts how many milliseconds BMO has slept so far (I really hope nobody tells it to sleep 18446744073709551615 milliseconds)
+    let mut shown_since : Option<Instant> = None; // When the current intent started being shown, None to switch right away
     let mut audio_available = false; // Is there an audio track for the current intent?
     let mut played_audio = false; // Has the audio track, if present, been played?
     let mut loaded_face = false; // Has the intent's face been presented?
     let mut time_limit_loaded = false;
 
-    let mut current_face : &Vec<u8> = &Vec::new(); // Current face's texture
+    let mut current_face : Vec<u8> = Vec::new(); // Current face's texture
     let mut current_intent_clone = "default".to_owned(); // In order to prevent changing the intents mid-iteration, we keep a local copy
+    let mut current_args_clone = "".to_owned(); // Along with its arguments
 
     'mainloop: loop {       
+        // Pick up the configuration files reloaded since the last iteration, starting over with
+        // the current intent
+        if let Some(reloaded) = catalog.lock().unwrap().take_reloaded() {
+            intent_faces = reloaded.intent_faces;
+            intent_audio = reloaded.intent_audio;
+            intent_timings = reloaded.intent_timings;
+            chronometer_steps = reloaded.chronometer_steps;
+            if ! intent_faces.contains_key(&current_intent_clone) {
+                current_intent_clone = "default".to_owned();
+            }
+            loaded_face = false;
+            time_limit_loaded = false;
+            println!("Configuration files reloaded");
+            events.publish(Event::Reloaded { intents : intent_faces.len() });
+        }
+
         if let Ok(mut state) = state.lock() { // Lock the state struct
+            let mut received = false; // Was a new intent received this iteration?
+
             // If there is audio available and it has been played already, 
             // or there was no audio and the time limit has been reached, 
             // get a new intent (or switch to the default one)
             if audio_available && played_audio && state.audio_finished || 
-                !audio_available && time_slept > time_limit {
-                //println!("audio_available: {}, played_audio: {}, audio_finished: {}, time_slept: {},", audio_available, played_audio, *audio_finished.lock().unwrap(), time_slept);
+                !audio_available && shown_since.map_or(true, |since| clock.now() - since > Duration::from_millis(time_limit)) {
+                //println!("audio_available: {}, played_audio: {}, audio_finished: {}, shown_since: {:?},", audio_available, played_audio, *audio_finished.lock().unwrap(), shown_since);
                 if ! state.new_intent {
-                    current_intent_clone  = "default".to_owned();
+                    // Announce the weather changes, or show the face the current weather calls for
+                    #[cfg(feature = "weather")]
+                    let idle_intent = weather_reactions.default_intent().filter(|intent| intent_faces.contains_key(*intent))
+                                                                        .unwrap_or("default");
+                    #[cfg(not(feature = "weather"))]
+                    let idle_intent = "default";
+
+                    current_intent_clone = announcements.pop_front().unwrap_or_else(|| idle_intent.to_owned());
                     //println!("---------------------------");
                     //println!("Changing to default intent");
                     //println!("---------------------------");
                 } else {
-                    if state.current_intent == CHRONOMETER_STATE || state.current_intent == WEATHER_STATE {
+                    if let Some(feature) = features::missing_for_intent(&state.current_intent) {
+                        eprintln!("Asked for {}, but BMO was built without the {} feature: ignoring", state.current_intent, feature);
+                        current_intent_clone = "default".to_owned();
+                    } else if state.current_intent == CHRONOMETER_STATE || state.current_intent == WEATHER_STATE ||
+                        state.current_intent == STOPWATCH_STATE || state.current_intent == CLOCK_STATE {
                         current_intent_clone = state.current_intent.to_owned(); 
+                        current_args_clone = state.current_args.to_owned();
                     } else {
                         // Switch to a new state. If it's a preset one or it doesn't exist, skip it.
                         match intent_faces.get(&state.current_intent) {
@@ -139,6 +450,7 @@ pub fn run( address : String, port : String,
                     }
 
                     state.new_intent = false;
+                    received = true;
 
                     // Since we also captured the state here, set to false its availability
                     // to auxiliary functions
@@ -152,7 +464,7 @@ pub fn run( address : String, port : String,
                 }
             
                 // Reset the local status variables
-                time_slept = 0;
+                shown_since = Some(clock.now());
                 audio_available = false;
                 played_audio = false;
                 loaded_face = false;
@@ -160,47 +472,105 @@ pub fn run( address : String, port : String,
                 
                 state.audio_finished = false;
             }
+
+            if received || state.shown != current_intent_clone {
+                events.publish(Event::IntentShown { intent : current_intent_clone.clone() });
+                state.shown.clone_from(&current_intent_clone);
+            }
         }
 
-        if current_intent_clone == CHRONOMETER_STATE { // Hijack the canvas and display a chronometer
-            let duration = get_time(res_width, res_height, &mut canvas, &ttf_context, &texture_creator, Arc::clone(&state),            
-                                    Arc::clone(&new_intent_available))?; 
-            display_chronometer(res_width, res_height, &mut canvas, &ttf_context, &texture_creator, duration)?;
-            current_intent_clone  = "default".to_owned(); // Switch to the default state, we have finished here
-        } else if current_intent_clone == WEATHER_STATE { // Hijack the canvas and display a chronometer
-            // If the optional parameters were provided
-            if let Some(ref key) = api_key {
-                if let Some(ref location) = location {
-                    if let Some(ref country) = country {
-                        show_weather(res_width, res_height, &key, &location, &country, &mut canvas, 
-                                        &ttf_context, &texture_creator, Arc::clone(&state),             
-                                        Arc::clone(&new_intent_available))?; 
+        // Hijack the canvas for the preset intents, switching to the default state once they finish
+        let mode = [CHRONOMETER_STATE, STOPWATCH_STATE, CLOCK_STATE, WEATHER_STATE].contains(&current_intent_clone.as_str())
+                                                                                   .then(|| current_intent_clone.clone());
+        if let Some(ref mode) = mode {
+            events.publish(Event::ModeEntered { mode : mode.clone() });
+        }
+        match current_intent_clone.as_str() {
+            #[cfg(feature = "text")]
+            CHRONOMETER_STATE => { // Display a chronometer
+                // A duration sent along with the intent ("chronometer 7m30s") starts it right away
+                let duration = match parse_duration(&current_args_clone) {
+                    Some(duration) => duration,
+                    None => get_time(&mut screen, Arc::clone(&state), Arc::clone(&new_intent_available), &chronometer_steps)?,
+                };
+                display_chronometer(&mut screen, duration)?;
+                screen.ring(None)?;
+                current_intent_clone  = "default".to_owned();
+            }
+            #[cfg(feature = "text")]
+            STOPWATCH_STATE => { // Display the stopwatch
+                show_stopwatch(&mut screen, Arc::clone(&state), Arc::clone(&new_intent_available), Arc::clone(&stopwatch))?;
+                current_intent_clone  = "default".to_owned();
+            }
+            #[cfg(feature = "text")]
+            CLOCK_STATE => { // Display the clock
+                show_clock(&mut screen, &clock_settings, &current_args_clone, Arc::clone(&state), Arc::clone(&new_intent_available))?;
+                current_intent_clone  = "default".to_owned();
+            }
+            #[cfg(all(feature = "weather", feature = "text"))]
+            WEATHER_STATE => { // Display the weather
+                // If the optional parameters were provided
+                if let Some(ref cache) = weather_cache {
+                    // A broken weather screen isn't worth stopping BMO for
+                    if let Err(e) = show_weather(&mut screen, cache, &weather_settings, &current_args_clone, &intent_faces, Arc::clone(&state),
+                                                 Arc::clone(&new_intent_available)) {
+                        eprintln!("Couldn't show the weather: {}", e);
                     }
+                } else {
+                    eprintln!("Asked for weather, but didn't provide enough arguments at launch: ignoring");
                 }
-            } else {
-                eprintln!("Asked for weather, but didn't provide enough arguments at launch: ignoring");
+                current_intent_clone  = "default".to_owned();
             }
-            current_intent_clone  = "default".to_owned(); // Switch to the default state, we have finished here
+            _ => { }
         }
+        if let Some(mode) = mode {
+            events.publish(Event::ModeExited { mode });
+        }
+        #[cfg(not(feature = "text"))]
+        let _ = (&current_args_clone, &chronometer_steps); // Only the preset intents take any arguments, and the chronometer can't be shown anyway
 
 
+        // Fire the alarm of every timer which has finished since the last iteration, and of every 
+        // wall-clock alarm which is due (the modes above do it on their own while they're shown)
+        screen.ring_due()?;
+        if ringer.quit.get() { // Escape was pressed while an alarm was ringing
+            break 'mainloop;
+        }
+
+        // Queue the weather announcements, which are shown once BMO has nothing else to do
+        #[cfg(feature = "weather")]
+        if let Some(ref cache) = weather_cache {
+            let cache = cache.lock().unwrap();
+            for intent in weather_reactions.update(cache.report(cache.home()), clock.local_now().naive_local()) {
+                if intent_faces.contains_key(&intent) {
+                    announcements.push_back(intent);
+                } else {
+                    eprintln!("No faces found for weather reaction {}: ignoring", intent);
+                }
+            }
+        }
+
         for event in sdl_context.event_pump()?.poll_iter() {
             match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
+                SdlEvent::Quit { .. }
+                | SdlEvent::KeyDown {
                     keycode: Option::Some(Keycode::Escape),
                     ..
                 } => break 'mainloop,
+                SdlEvent::KeyDown {
+                    keycode: Option::Some(Keycode::F12),
+                    ..
+                } => { capture.lock().unwrap().screenshot(None); },
                 _ => { }
             }
         }
 
-        canvas.clear();
+        screen.canvas.clear();
         
         // Look for a face to load
         if ! loaded_face || audio_available { // If a face hasn't been loaded yet, OR it's an intent where BMO talks
             current_face = match intent_faces.get(&current_intent_clone) {
-                Some(x) => &x[random_number::random!(0, x.len() - 1)],
+                Some(x) => x[random_number::random!(0, x.len() - 1)].clone(),
                 None => panic!("No faces found for intent {}.", current_intent_clone),
             };
             
@@ -221,7 +591,9 @@ pub fn run( address : String, port : String,
         if ! played_audio {
             if let Some(current_audio) = intent_audio.get(&current_intent_clone) {      
                 let state_clone = Arc::clone(&state);
-                play_sound(current_audio[random_number::random!(0, current_audio.len() - 1)].clone(), state_clone);
+                let index = random_number::random!(0, current_audio.len() - 1);
+                let clip = Clip { id : format!("{}/{}", current_intent_clone, index + 1), data : current_audio[index].clone() };
+                play_sound(Arc::clone(&audio), clip, state_clone);
                 
                 played_audio = true;
                 audio_available = true;
@@ -233,14 +605,18 @@ pub fn run( address : String, port : String,
 
         // Update the canvas
         //let image = Path::new(current_face);
-        let texture = texture_creator.load_texture_bytes(current_face)?;
-        canvas.copy(&texture, None, None)?;
-        canvas.present();
+        let texture = screen.texture_creator.load_texture_bytes(&current_face)?;
+        screen.canvas.copy(&texture, None, None)?;
+        #[cfg(feature = "text")]
+        draw_overlay(&mut screen.canvas, &overlay_font, &screen.texture_creator, &timers.lock().unwrap().remaining())?;
+        drop(texture);
+        screen.present();
 
         // Sleep for a short time, since we don't want to the poor raspberry to catch in flames
-        ::std::thread::sleep(Duration::from_millis(sleep_time));
-        time_slept += sleep_time;
+        clock.sleep(Duration::from_millis(sleep_time));
     } 
 
+    events.publish(Event::Stopped);
+
     Ok(())
 }
@@ -0,0 +1,275 @@
+#[cfg(feature = "tls")]
+extern crate rustls;
+#[cfg(feature = "tls")]
+extern crate rustls_pemfile;
+
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+#[cfg(feature = "tls")]
+use std::{fs::File, io::BufReader, sync::Arc};
+
+#[cfg(feature = "tls")]
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+
+use crate::config::Settings;
+
+/// How long clients have to finish the TLS handshake and send the token before being dropped,
+/// so that nobody can hold the socket without ever authenticating
+pub const HANDSHAKE_TIMEOUT : Duration = Duration::from_secs(10);
+
+/// A connection intents are received through, whichever its transport (TCP, TLS or a Unix
+/// socket)
+pub trait Connection : Read + Write + Send {
+    fn set_read_timeout(&self, timeout : Option<Duration>) -> io::Result<()>;
+}
+
+impl Connection for TcpStream {
+    fn set_read_timeout(&self, timeout : Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl Connection for UnixStream {
+    fn set_read_timeout(&self, timeout : Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+#[cfg(feature = "tls")]
+impl Connection for StreamOwned<ServerConnection, TcpStream> {
+    fn set_read_timeout(&self, timeout : Option<Duration>) -> io::Result<()> {
+        self.sock.set_read_timeout(timeout)
+    }
+}
+
+impl Connection for Box<dyn Connection> {
+    fn set_read_timeout(&self, timeout : Option<Duration>) -> io::Result<()> {
+        (**self).set_read_timeout(timeout)
+    }
+}
+
+/// Who may send intents, and how they have to connect. Read from these settings:
+/// - **allowed_peers** : Addresses (192.168.1.20) or networks (192.168.1.0/24) of the TCP
+///   clients allowed to connect, one per line. Anyone can if there are none.
+/// - **intent_token** : Token the clients have to send, as "auth token", before any intent.
+/// - **tls_certificate** and **tls_key** : PEM files of the certificate (along with its chain)
+///   and the private key TCP connections are encrypted with. Plain TCP is used without them.
+pub struct Access {
+    allowed_peers : Vec<(IpAddr, u8)>, // Networks, as their address and the length of their prefix
+    token : Option<String>,
+    #[cfg(feature = "tls")]
+    tls : Option<Arc<ServerConfig>>,
+}
+
+impl Access {
+    pub fn from_settings(settings : &Settings) -> Result<Access, String> {
+        let allowed_peers = settings.get_list("allowed_peers").iter()
+                                    .map(|peer| parse_network(peer).ok_or(format!("Couldn't parse the allowed_peers setting, please ensure that it's an address or a network (such as 192.168.1.0/24): {}", peer)))
+                                    .collect::<Result<Vec<_>, String>>()?;
+        let token = match settings.get("intent_token") {
+            Some("") => return Err("The intent_token setting can't be empty".to_owned()),
+            token => token.map(str::to_owned),
+        };
+
+        #[cfg(feature = "tls")]
+        let tls = match (settings.get("tls_certificate"), settings.get("tls_key")) {
+            (None, None) => None,
+            (Some(certificate), Some(key)) => Some(Arc::new(tls_config(certificate, key)?)),
+            _ => return Err("The tls_certificate and tls_key settings go together".to_owned()),
+        };
+
+        Ok(Access { allowed_peers, token, #[cfg(feature = "tls")] tls })
+    }
+
+    /// Access which lets anyone in, without TLS
+    pub fn open() -> Access {
+        Access { allowed_peers : Vec::new(), token : None, #[cfg(feature = "tls")] tls : None }
+    }
+
+    /// Is the given peer allowed to connect?
+    pub fn allows(&self, peer : IpAddr) -> bool {
+        self.allowed_peers.is_empty() || self.allowed_peers.iter().any(|network| in_network(peer, *network))
+    }
+
+    /// Do the clients have to send a token before their intents?
+    pub fn needs_token(&self) -> bool {
+        self.token.is_some()
+    }
+
+    /// Is the given line (such as "auth token") the one clients have to start with? Always
+    /// true if no token is needed.
+    pub fn authenticates(&self, line : &str) -> bool {
+        let Some(expected) = &self.token else { return true };
+
+        match line.trim().split_once(char::is_whitespace) {
+            Some(("auth", token)) => constant_time_eq(token.trim().as_bytes(), expected.as_bytes()),
+            _ => false,
+        }
+    }
+
+    /// Check that a TCP client is allowed to connect, and go through the TLS handshake with it
+    /// if needed. The reason it was rejected is returned otherwise.
+    pub fn accept(&self, stream : TcpStream) -> Result<Box<dyn Connection>, String> {
+        let peer = stream.peer_addr().map_err(|e| e.to_string())?;
+        if ! self.allows(peer.ip()) {
+            return Err("not in allowed_peers".to_owned());
+        }
+
+        #[cfg(feature = "tls")]
+        if let Some(config) = &self.tls {
+            let mut connection = ServerConnection::new(Arc::clone(config)).map_err(|e| e.to_string())?;
+            let mut stream = stream;
+
+            stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT)).map_err(|e| e.to_string())?;
+            while connection.is_handshaking() {
+                connection.complete_io(&mut stream).map_err(|e| format!("TLS handshake failed: {}", e))?;
+            }
+            stream.set_read_timeout(None).map_err(|e| e.to_string())?;
+
+            return Ok(Box::new(StreamOwned::new(connection, stream)));
+        }
+
+        Ok(Box::new(stream))
+    }
+}
+
+// Parse an address or a network, such as 192.168.1.0/24 or fd00::/8
+fn parse_network(text : &str) -> Option<(IpAddr, u8)> {
+    let (address, prefix) = match text.split_once('/') {
+        Some((address, prefix)) => (address.parse::<IpAddr>().ok()?, Some(prefix.parse::<u8>().ok()?)),
+        None => (text.parse::<IpAddr>().ok()?, None),
+    };
+    let bits = if address.is_ipv4() { 32 } else { 128 };
+
+    prefix.map_or(Some(bits), |prefix| Some(prefix).filter(|prefix| *prefix <= bits))
+          .map(|prefix| (address, prefix))
+}
+
+fn in_network(peer : IpAddr, (network, prefix) : (IpAddr, u8)) -> bool {
+    match (peer, network) {
+        (IpAddr::V4(peer), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(peer) & mask == u32::from(network) & mask
+        },
+        (IpAddr::V6(peer), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(peer) & mask == u128::from(network) & mask
+        },
+        // IPv4 clients of a socket listening on IPv6 show up as ::ffff:a.b.c.d
+        (IpAddr::V6(peer), IpAddr::V4(_)) => peer.to_ipv4_mapped().is_some_and(|peer| in_network(IpAddr::V4(peer), (network, prefix))),
+        (IpAddr::V4(_), IpAddr::V6(_)) => false,
+    }
+}
+
+/// Compare secrets without telling how much of them matched by how long it took
+pub(crate) fn constant_time_eq(a : &[u8], b : &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+}
+
+// Load the certificate chain and the private key from their PEM files
+#[cfg(feature = "tls")]
+fn tls_config(certificate : &str, key : &str) -> Result<ServerConfig, String> {
+    let open = |path : &str| File::open(path).map(BufReader::new).map_err(|e| format!("Couldn't open {}: {}", path, e));
+
+    let certificates = rustls_pemfile::certs(&mut open(certificate)?).collect::<Result<Vec<_>, _>>()
+                                     .map_err(|e| format!("Couldn't read the certificates in {}: {}", certificate, e))?;
+    if certificates.is_empty() {
+        return Err(format!("There are no certificates in {}", certificate));
+    }
+    let key = rustls_pemfile::private_key(&mut open(key)?).map_err(|e| format!("Couldn't read the private key in {}: {}", key, e))?
+                             .ok_or(format!("There is no private key in {}", key))?;
+
+    ServerConfig::builder().with_no_client_auth()
+                           .with_single_cert(certificates, key)
+                           .map_err(|e| format!("Couldn't use the certificate {}: {}", certificate, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(contents : &str) -> Settings {
+        Settings::from_reader(contents.as_bytes(), "settings.txt").unwrap()
+    }
+
+    #[test]
+    fn only_allowed_peers_get_in() {
+        assert!(Access::open().allows("203.0.113.7".parse().unwrap()));
+
+        let access = Access::from_settings(&settings("[allowed_peers]\n192.168.1.0/24\n10.0.0.5\nfd00::/8\n")).unwrap();
+        for allowed in ["192.168.1.20", "10.0.0.5", "::ffff:192.168.1.7", "fd12::1"] {
+            assert!(access.allows(allowed.parse().unwrap()), "{}", allowed);
+        }
+        for refused in ["192.168.2.20", "10.0.0.6", "127.0.0.1", "::1"] {
+            assert!(! access.allows(refused.parse().unwrap()), "{}", refused);
+        }
+
+        assert!(Access::from_settings(&settings("[allowed_peers]\n192.168.1.0/33\n")).is_err());
+        assert!(Access::from_settings(&settings("[allowed_peers]\nbmo.local\n")).is_err());
+    }
+
+    #[test]
+    fn tokens_are_checked() {
+        assert!(Access::open().authenticates("hello"));
+
+        let access = Access::from_settings(&settings("[intent_token]\nmathematical\n")).unwrap();
+        assert!(access.needs_token());
+        assert!(access.authenticates("auth mathematical\n"));
+        assert!(! access.authenticates("auth algebraic"));
+        assert!(! access.authenticates("mathematical"));
+        assert!(! access.authenticates("hello"));
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn connections_are_encrypted() {
+        use std::io::{BufRead, BufReader};
+        use std::convert::TryFrom;
+        use std::net::TcpListener;
+        use std::thread;
+        use rustls::{ClientConfig, ClientConnection, RootCertStore};
+        use rustls::pki_types::ServerName;
+
+        let access = Access::from_settings(&settings("[tls_certificate]\ntests/fixtures/tls/certificate.pem\n[tls_key]\ntests/fixtures/tls/key.pem\n")).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let client = thread::spawn(move || {
+            let mut roots = RootCertStore::empty();
+            for certificate in rustls_pemfile::certs(&mut BufReader::new(File::open("tests/fixtures/tls/ca.pem").unwrap())) {
+                roots.add(certificate.unwrap()).unwrap();
+            }
+            let config = ClientConfig::builder().with_root_certificates(roots).with_no_client_auth();
+            let connection = ClientConnection::new(Arc::new(config), ServerName::try_from("localhost").unwrap()).unwrap();
+            let mut stream = StreamOwned::new(connection, TcpStream::connect(address).unwrap());
+
+            writeln!(stream, "hello").unwrap();
+            let mut reply = String::new();
+            BufReader::new(stream).read_line(&mut reply).unwrap();
+            reply
+        });
+
+        let mut connection = access.accept(listener.accept().unwrap().0).unwrap();
+        let mut line = String::new();
+        BufReader::new(&mut connection).read_line(&mut line).unwrap();
+        assert_eq!(line, "hello\n");
+        writeln!(connection, "hi").unwrap();
+        connection.flush().unwrap();
+        assert_eq!(client.join().unwrap(), "hi\n");
+
+        // Plain TCP clients don't get through the handshake
+        let plain = thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            writeln!(stream, "hello").unwrap();
+        });
+        assert!(access.accept(listener.accept().unwrap().0).err().unwrap().starts_with("TLS handshake failed"));
+        plain.join().unwrap();
+
+        assert!(Access::from_settings(&settings("[tls_certificate]\ntests/fixtures/tls/certificate.pem\n")).is_err());
+        assert!(Access::from_settings(&settings("[tls_certificate]\ntests/fixtures/tls/key.pem\n[tls_key]\ntests/fixtures/tls/key.pem\n")).is_err());
+    }
+}
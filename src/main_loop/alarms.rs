@@ -0,0 +1,613 @@
+extern crate chrono;
+extern crate sdl2;
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Weekday};
+
+// SDL libs
+use sdl2::event::Event as SdlEvent;
+use sdl2::keyboard::Keycode;
+#[cfg(feature = "text")]
+use sdl2::pixels::Color;
+#[cfg(feature = "text")]
+use sdl2::render::TextureQuery;
+use sdl2::image::LoadTexture;
+
+use std::cell::Cell;
+use std::fs;
+use std::path::Path; // for providing paths to SDL
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::Ordering;
+
+use crate::config::{parse_assets, Settings};
+use super::{play_sound_looping, AudioHandle, ClockHandle, Screen};
+use super::events::{Event, EventsHandle};
+use super::timers::TimersMutex;
+#[cfg(feature = "text")]
+use super::get_centered_rect;
+
+/// File where the alarms are stored, so that they survive restarts
+pub const ALARMS_FILE : &str = "alarms.txt";
+
+const INTENT_ALARM : &str = "alarm"; // alarm hh:mm [repeat] [label]
+const INTENT_REMIND : &str = "remind"; // remind [yyyy-mm-dd] hh:mm label
+const INTENT_ALARMS : &str = "alarms"; // lists the alarms
+const INTENT_DELETE : &str = "delete"; // delete id
+const INTENT_SNOOZE : &str = "snooze"; // snooze [minutes]
+const INTENT_DISMISS : &str = "dismiss";
+
+const DATE_TIME_FORMAT : &str = "%Y-%m-%d %H:%M";
+
+/// Longest an alarm can be snoozed for
+pub const MAX_SNOOZE : std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+const WEEK : [Weekday; 7] = [Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu,
+                             Weekday::Fri, Weekday::Sat, Weekday::Sun];
+
+/// An alarm set at a local time, either one-shot or repeating on some days of the week
+pub struct Alarm {
+    pub id : u32,
+    pub next : NaiveDateTime, // When it will go off next, in local time
+    pub days : Vec<Weekday>, // Days of the week it repeats on, or none if it's one-shot
+    pub label : String,
+}
+
+/// Set of alarms and reminders, stored on ALARMS_FILE whenever they change.
+///
+/// It also keeps track of the alarm which is ringing (be it a wall-clock one, a timer or the chronometer),
+/// until it's either snoozed or dismissed.
+pub struct Alarms {
+    clock : ClockHandle, // Tells when the alarms are due
+    alarms : Vec<Alarm>,
+    path : String,
+    ringing : Option<String>,
+    snooze : std::time::Duration, // Default snoozing time
+}
+
+/// How the alarm is shown and played when it goes off, read from the settings
+pub struct AlarmSettings {
+    pub face : String, // alarm_face, the face shown
+    pub sound : String, // alarm_sound, the audio track played in a loop
+    pub font : String, // font, used for showing the alarm's label
+    pub timeout : std::time::Duration, // alarm_timeout, how long it rings until it's dismissed on its own
+    pub ramp : std::time::Duration, // alarm_ramp, how long the audio track takes to reach its full volume
+    pub snooze : std::time::Duration, // snooze, how long the alarms are snoozed for when no length is given
+}
+
+impl AlarmSettings {
+    pub fn from_settings(settings : &Settings) -> Result<AlarmSettings, String> {
+        Ok(AlarmSettings {
+            face : settings.get_or("alarm_face", "assets/faces/alarm.jpg"),
+            sound : settings.get_or("alarm_sound", "assets/audio/alarm.wav"),
+            font : settings.get_or("font", "assets/font.ttf"),
+            timeout : settings.parse_duration_or("alarm_timeout", std::time::Duration::from_secs(5 * 60))?,
+            ramp : settings.parse_duration_or("alarm_ramp", std::time::Duration::from_secs(30))?,
+            snooze : settings.parse_duration_or("snooze", std::time::Duration::from_secs(9 * 60))?,
+        })
+    }
+}
+
+impl Alarms {
+    /// Load the alarms stored on the given file, starting with none if it doesn't exist yet.
+    /// # Format
+    /// Each alarm's id needs to be enclosed between [...], and have the next time it
+    /// will go off (yyyy-mm-dd hh:mm), the days it repeats on and its label in new lines below it.
+    ///
+    /// # Example file
+    /// [1]
+    ///
+    /// 2026-10-19 07:30
+    ///
+    /// weekdays
+    ///
+    /// wake up
+    ///
+    /// [2]
+    ///
+    /// 2026-10-24 18:00
+    ///
+    /// once
+    ///
+    /// take out the trash
+    ///
+    /// Alarms which should have gone off while the server was down go off right away.
+    /// Malformed entries are skipped, and so is the whole file if it can't be parsed.
+    ///
+    /// Snoozing an alarm without specifying for how long will use the given snooze time.
+    pub fn load(path : &str, snooze : std::time::Duration, clock : ClockHandle) -> Alarms {
+        let mut alarms = Alarms { clock, alarms : Vec::new(), path : path.to_owned(), ringing : None, snooze };
+
+        let entries = match parse_assets(path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return alarms, // Nothing stored yet
+            Err(e) => {
+                eprintln!("Couldn't read the alarms stored in {}, starting without them: {}", path, e);
+                return alarms;
+            }
+        };
+
+        for (id, lines) in entries.iter() {
+            if id.is_empty() { continue; }
+
+            let alarm = match &lines[..] {
+                [next, days, label] => id.parse::<u32>().ok().and_then(|id| {
+                    let next = NaiveDateTime::parse_from_str(next, DATE_TIME_FORMAT).ok()?;
+                    let days = parse_days(days)?;
+                    Some(Alarm { id, next, days, label : label.clone() })
+                }),
+                _ => None,
+            };
+
+            match alarm {
+                Some(alarm) => alarms.alarms.push(alarm),
+                None => eprintln!("Skipping malformed alarm {} in {}", id, path),
+            }
+        }
+
+        alarms.alarms.sort_by_key(|alarm| alarm.next);
+
+        alarms
+    }
+
+    // Store the alarms in the format read by load
+    fn save(&self) {
+        let contents : String = self.alarms.iter()
+                            .map(|alarm| format!("[{}]\n{}\n{}\n{}\n\n", alarm.id, alarm.next.format(DATE_TIME_FORMAT),
+                                                  days_as_string(&alarm.days), alarm.label))
+                            .collect();
+
+        if let Err(e) = fs::write(&self.path, contents) {
+            eprintln!("Couldn't store the alarms in {}: {}", self.path, e);
+        }
+    }
+
+    /// Add an alarm going off at the given time, on the next day matching the repeating
+    /// ones (or on the given date, if it's a one-shot one). Returns the new alarm.
+    pub fn add(&mut self, time : NaiveTime, days : Vec<Weekday>, date : Option<NaiveDate>, label : &str) -> &Alarm {
+        let id = self.alarms.iter().map(|alarm| alarm.id).max().unwrap_or(0) + 1;
+
+        let next = match date {
+            Some(date) => date.and_time(time),
+            None => next_occurrence(self.clock.local_now().naive_local(), time, &days),
+        };
+
+        self.alarms.push(Alarm { id, next, days, label : label.to_owned() });
+        self.alarms.sort_by_key(|alarm| alarm.next);
+        self.save();
+
+        self.alarms.iter().find(|alarm| alarm.id == id).unwrap()
+    }
+
+    /// Delete the alarm with the given id. Returns false if there wasn't any.
+    pub fn delete(&mut self, id : u32) -> bool {
+        let count = self.alarms.len();
+        self.alarms.retain(|alarm| alarm.id != id);
+
+        if count != self.alarms.len() {
+            self.save();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Take the alarms which are due, returning their labels. The repeating ones are
+    /// rescheduled for their next day, and the one-shot ones are removed.
+    pub fn take_due(&mut self) -> Vec<String> {
+        let now = self.clock.local_now().naive_local();
+        let mut due = Vec::new();
+
+        for alarm in self.alarms.iter_mut().filter(|alarm| alarm.next <= now) {
+            due.push(alarm.label.clone());
+
+            if ! alarm.days.is_empty() {
+                alarm.next = next_occurrence(now, alarm.next.time(), &alarm.days);
+            }
+        }
+
+        if ! due.is_empty() {
+            self.alarms.retain(|alarm| alarm.next > now);
+            self.alarms.sort_by_key(|alarm| alarm.next);
+            self.save();
+        }
+
+        due
+    }
+
+    /// Mark the alarm with the given label as the ringing one
+    pub fn ring(&mut self, label : &str) {
+        self.ringing = Some(label.to_owned());
+    }
+
+    pub fn is_ringing(&self) -> bool {
+        self.ringing.is_some()
+    }
+
+    /// Set the ringing alarm to go off again after the given amount of time (or the default
+    /// snooze time), returning its label and when it will go off. It keeps ringing if there's
+    /// an error, such as the time being zero or longer than MAX_SNOOZE.
+    pub fn snooze(&mut self, snooze : Option<std::time::Duration>) -> Result<(String, NaiveDateTime), String> {
+        let label = self.ringing.clone().ok_or("no alarm to snooze")?;
+        let at = Some(snooze.unwrap_or(self.snooze)).filter(|snooze| ! snooze.is_zero() && *snooze <= MAX_SNOOZE)
+                    .and_then(|snooze| Duration::from_std(snooze).ok())
+                    .and_then(|snooze| self.clock.local_now().naive_local().checked_add_signed(snooze))
+                    .ok_or(format!("can only snooze for 1 to {} minutes", MAX_SNOOZE.as_secs() / 60))?;
+
+        self.ringing = None;
+        self.add(at.time(), Vec::new(), Some(at.date()), &label);
+
+        Ok((label, at))
+    }
+
+    /// Stop the ringing alarm, returning its label
+    pub fn dismiss(&mut self) -> Option<String> {
+        self.ringing.take()
+    }
+
+    /// One line per alarm, with its id, when it goes off next, the days it repeats on and its label
+    pub fn list(&self) -> String {
+        if self.alarms.is_empty() {
+            return "no alarms".to_owned();
+        }
+
+        self.alarms.iter()
+                    .map(|alarm| format!("{} {} {} {}", alarm.id, alarm.next.format(DATE_TIME_FORMAT),
+                                         days_as_string(&alarm.days), alarm.label))
+                    .collect::<Vec<String>>()
+                    .join("\n")
+    }
+}
+
+// Thread-safe Alarms instance
+pub type AlarmsMutex = Arc<Mutex<Alarms>>;
+
+/// Handle the alarm commands, which don't change BMO's face:
+/// - "alarm hh:mm [repeat] [label]" sets an alarm, such as "alarm 07:30 weekdays wake up".
+///   The repeating days can be "once" (the default), "daily", "weekdays", "weekends" or a
+///   comma-separated list of days ("mon,wed,fri").
+/// - "remind [yyyy-mm-dd] hh:mm label" sets a one-shot reminder, such as "remind 18:00 take out the trash".
+/// - "alarms" lists the alarms and reminders, along with their ids.
+/// - "delete id" deletes the alarm with the given id.
+/// - "snooze [minutes]" stops the ringing alarm, and sets it to go off again later.
+/// - "dismiss" stops the ringing alarm.
+///
+/// Returns the reply to be sent back to the client, or None if the intent isn't an alarm command.
+pub fn handle_command(intent : &str, args : &str, alarms : &AlarmsMutex) -> Option<String> {
+    let mut alarms = alarms.lock().unwrap();
+    let mut words = args.split_whitespace().peekable();
+
+    let reply = match intent {
+        INTENT_ALARM | INTENT_REMIND => {
+            // The date is only accepted by reminders
+            let date = match words.peek().map(|word| NaiveDate::parse_from_str(word, "%Y-%m-%d")) {
+                Some(Ok(date)) if intent == INTENT_REMIND => { words.next(); Some(date) },
+                _ => None,
+            };
+
+            let time = words.next().and_then(|word| NaiveTime::parse_from_str(word, "%H:%M").ok());
+
+            let days = match words.peek().and_then(|word| parse_days(word)) {
+                Some(days) if intent == INTENT_ALARM => { words.next(); days },
+                _ => Vec::new(),
+            };
+
+            let label = words.collect::<Vec<&str>>().join(" ");
+            let label = if label.is_empty() { intent.to_owned() } else { label };
+
+            match time {
+                Some(_) if ! valid_label(&label) => format!("error: labels can't contain '[' or line breaks: '{}'", label),
+                Some(time) => {
+                    let alarm = alarms.add(time, days, date, &label);
+                    format!("set {} {} {} {}", alarm.id, alarm.next.format(DATE_TIME_FORMAT),
+                                               days_as_string(&alarm.days), alarm.label)
+                }
+                None => format!("error: couldn't read a time (hh:mm) from '{}'", args),
+            }
+        }
+        INTENT_ALARMS => alarms.list(),
+        INTENT_DELETE => {
+            match args.parse::<u32>() {
+                Ok(id) if alarms.delete(id) => format!("deleted {}", id),
+                _ => format!("error: no alarm with id '{}'", args),
+            }
+        }
+        INTENT_SNOOZE => {
+            let snooze = match args.trim() {
+                "" => Ok(None),
+                minutes => minutes.parse::<u64>().ok().and_then(|minutes| minutes.checked_mul(60))
+                                  .map(|seconds| Some(std::time::Duration::from_secs(seconds)))
+                                  .ok_or(format!("couldn't read a number of minutes from '{}'", minutes)),
+            };
+
+            match snooze.and_then(|snooze| alarms.snooze(snooze)) {
+                Ok((label, at)) => format!("snoozed {} until {}", label, at.format("%H:%M")),
+                Err(e) => format!("error: {}", e),
+            }
+        }
+        INTENT_DISMISS => {
+            match alarms.dismiss() {
+                Some(label) => format!("dismissed {}", label),
+                None => "error: no alarm to dismiss".to_owned(),
+            }
+        }
+        _ => return None,
+    };
+
+    println!("{} {}: {}", intent, args, reply);
+
+    Some(reply)
+}
+
+/// Hijacks an SDL context, displays the alarm face along with an optional label (such as
+/// a timer's name) at the bottom (unless BMO was built without the text feature), and plays the alarm sound in a loop with a rising volume.
+///
+/// It blocks until the alarm is either snoozed or dismissed (see handle_command), or until
+/// the timeout passes on the given clock, in which case it's dismissed on its own. The SDL
+/// events are still handled meanwhile: F12 takes a screenshot, and Escape (or closing the
+/// window) dismisses the alarm and returns true, so that BMO quits.
+pub fn fire_alarm<T: sdl2::render::RenderTarget, U>( screen : &mut Screen<T, U>,
+                                                     label : Option<&str> ) -> Result<bool, String> {
+    let Ringer { sdl_context, settings, alarms, audio, .. } = *screen.ringer;
+    let (res_width, res_height, ttf_context, capture, clock) = (screen.res_width, screen.res_height, screen.ttf_context, screen.capture, screen.clock);
+    let (canvas, texture_creator) = (&mut screen.canvas, &screen.texture_creator);
+    alarms.lock().unwrap().ring(label.unwrap_or(INTENT_ALARM));
+
+    canvas.clear();        
+    let image = Path::new(&settings.face);
+    let texture = texture_creator.load_texture(image)?;
+    canvas.copy(&texture, None, None)?;
+
+    #[cfg(feature = "text")]
+    if let Some(label) = label {
+        // Load the font
+        let mut font = ttf_context.load_font(&settings.font, 64)?;
+        font.set_style(sdl2::ttf::FontStyle::BOLD);
+
+        // render a surface, and convert it to a texture bound to the canvas
+        let surface = font
+            .render(label)
+            .blended(Color::RGBA(0, 0, 0, 255))
+            .map_err(|e| e.to_string())?;
+        let texture = texture_creator
+            .create_texture_from_surface(&surface)
+            .map_err(|e| e.to_string())?;
+
+        let TextureQuery { width, height, .. } = texture.query();
+
+        // Center it inside the bottom quarter of the screen
+        let padding = 5;
+        let mut target = get_centered_rect(
+            res_width,
+            res_height / 4,
+            width,
+            height,
+            res_width - padding,
+            res_height / 4 - padding,
+        );
+        target.offset(0, (res_height - res_height / 4) as i32);
+
+        canvas.copy(&texture, None, Some(target))?;
+    }
+
+    #[cfg(not(feature = "text"))]
+    let _ = (res_width, res_height, ttf_context);
+
+    drop(texture);
+    screen.present();
+
+    let mut event_pump = sdl_context.event_pump()?;
+    let stop = play_sound_looping(Arc::clone(audio), &settings.sound, settings.ramp);
+    let started = clock.now();
+    let mut quit = false;
+
+    // Wait until someone snoozes or dismisses it
+    while alarms.lock().unwrap().is_ringing() {
+        for event in event_pump.poll_iter() {
+            match event {
+                SdlEvent::Quit { .. }
+                | SdlEvent::KeyDown {
+                    keycode: Option::Some(Keycode::Escape),
+                    ..
+                } => {
+                    alarms.lock().unwrap().dismiss();
+                    quit = true;
+                },
+                SdlEvent::KeyDown {
+                    keycode: Option::Some(Keycode::F12),
+                    ..
+                } => { capture.lock().unwrap().screenshot(None); },
+                _ => { }
+            }
+        }
+
+        if quit {
+            println!("Quitting, dismissing the alarm");
+            break;
+        }
+
+        if clock.now() - started >= settings.timeout {
+            println!("Nobody stopped the alarm, dismissing it");
+            alarms.lock().unwrap().dismiss();
+            break;
+        }
+
+        clock.sleep(std::time::Duration::from_millis(100));
+    }
+
+    stop.store(true, Ordering::Relaxed);
+   
+    Ok(quit)
+}
+
+/// Fires the alarm of the timers which finished and of the wall-clock alarms which are due,
+/// whatever BMO is showing: the main loop and every mode (chronometer, stopwatch, clock,
+/// weather) call ring_due after presenting each frame.
+///
+/// Once BMO is asked to quit while an alarm rings, quit is set, nothing rings anymore, and
+/// the modes return right away.
+pub struct Ringer<'a> {
+    pub sdl_context : &'a sdl2::Sdl,
+    pub settings : &'a AlarmSettings,
+    pub alarms : &'a AlarmsMutex,
+    pub timers : &'a TimersMutex,
+    pub audio : &'a AudioHandle,
+    pub events : &'a EventsHandle,
+    pub quit : Cell<bool>,
+}
+
+impl Ringer<'_> {
+    /// Fire the alarm of every timer which has finished since the last call, and of every
+    /// wall-clock alarm which is due, one after the other (see fire_alarm)
+    pub fn ring_due<T: sdl2::render::RenderTarget, U>(&self, screen : &mut Screen<T, U>) -> Result<(), String> {
+        let timers_expired = self.timers.lock().unwrap().take_expired();
+        let alarms_due = self.alarms.lock().unwrap().take_due();
+
+        for (name, event) in timers_expired.into_iter().map(|name| (name.clone(), Event::TimerFired { name }))
+                                           .chain(alarms_due.into_iter().map(|label| (label.clone(), Event::AlarmFired { label }))) {
+            self.events.publish(event);
+            self.ring(screen, Some(&name))?;
+        }
+
+        Ok(())
+    }
+
+    /// Fire the alarm right away, with an optional label (see fire_alarm)
+    pub fn ring<T: sdl2::render::RenderTarget, U>(&self, screen : &mut Screen<T, U>, label : Option<&str>) -> Result<(), String> {
+        if ! self.quit.get() {
+            let quit = fire_alarm(screen, label)?;
+            self.quit.set(quit);
+        }
+
+        Ok(())
+    }
+}
+
+// Can the label be stored? A '[' could make it read back as an alarm's id, and a line break
+// as its next line
+fn valid_label(label : &str) -> bool {
+    ! label.contains(['[', '\n', '\r'])
+}
+
+// First moment after now at the given time on one of the given days of the week
+// (or on any of them, if none are given)
+fn next_occurrence(now : NaiveDateTime, time : NaiveTime, days : &[Weekday]) -> NaiveDateTime {
+    let today = now.date();
+
+    (0..=7).map(|offset| (today + Duration::days(offset)).and_time(time))
+            .find(|candidate| *candidate > now && (days.is_empty() || days.contains(&candidate.weekday())))
+            .unwrap() // There is always one within a week
+}
+
+// Read the days an alarm repeats on
+fn parse_days(text : &str) -> Option<Vec<Weekday>> {
+    match text {
+        "once" => Some(Vec::new()),
+        "daily" => Some(WEEK.to_vec()),
+        "weekdays" => Some(WEEK[..5].to_vec()),
+        "weekends" => Some(WEEK[5..].to_vec()),
+        _ => {
+            let mut days = text.split(',').map(|day| day.parse::<Weekday>().ok()).collect::<Option<Vec<Weekday>>>()?;
+            days.sort_by_key(|day| day.num_days_from_monday());
+            days.dedup();
+
+            Some(days)
+        }
+    }
+}
+
+// Inverse of parse_days
+fn days_as_string(days : &[Weekday]) -> String {
+    match days.len() {
+        0 => "once".to_owned(),
+        7 => "daily".to_owned(),
+        _ if days[..] == WEEK[..5] => "weekdays".to_owned(),
+        _ if days[..] == WEEK[5..] => "weekends".to_owned(),
+        _ => days.iter().map(|day| day.to_string().to_lowercase()).collect::<Vec<String>>().join(","),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::main_loop::ManualClock;
+    use chrono::{Local, TimeZone};
+
+    // Alarms which aren't stored anywhere, at 07:00 on a Sunday
+    fn alarms() -> (Arc<ManualClock>, Alarms) {
+        let clock = Arc::new(ManualClock::starting_at(Local.with_ymd_and_hms(2026, 10, 18, 7, 0, 0).unwrap()));
+        let alarms = Alarms::load("", std::time::Duration::from_secs(9 * 60), Arc::clone(&clock) as ClockHandle);
+
+        (clock, alarms)
+    }
+
+    #[test]
+    fn alarms_go_off_exactly_when_due() {
+        let (clock, mut alarms) = alarms();
+        alarms.add(NaiveTime::from_hms_opt(8, 0, 0).unwrap(), Vec::new(), None, "wake up");
+
+        clock.advance(std::time::Duration::from_secs(60 * 60 - 1));
+        assert!(alarms.take_due().is_empty());
+
+        clock.advance(std::time::Duration::from_secs(1));
+        assert_eq!(alarms.take_due(), vec!["wake up"]);
+        assert_eq!(alarms.list(), "no alarms");
+    }
+
+    #[test]
+    fn repeating_alarms_are_rescheduled() {
+        let (clock, mut alarms) = alarms();
+        alarms.add(NaiveTime::from_hms_opt(7, 30, 0).unwrap(), vec![Weekday::Mon], None, "work");
+
+        clock.advance(std::time::Duration::from_secs(30 * 60)); // Still Sunday
+        assert!(alarms.take_due().is_empty());
+
+        clock.advance(std::time::Duration::from_secs(24 * 60 * 60)); // Monday at 07:30
+        assert_eq!(alarms.take_due(), vec!["work"]);
+        assert_eq!(alarms.alarms[0].next, NaiveDate::from_ymd_opt(2026, 10, 26).unwrap().and_hms_opt(7, 30, 0).unwrap());
+    }
+
+    #[test]
+    fn alarms_are_stored() {
+        let path = std::env::temp_dir().join(format!("bmos_alarms_{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        let (clock, _) = alarms();
+        let alarms : AlarmsMutex = Arc::new(Mutex::new(Alarms::load(path, std::time::Duration::from_secs(60), clock.clone())));
+
+        assert_eq!(handle_command(INTENT_ALARM, "07:30 weekdays go to work", &alarms).unwrap(), "set 1 2026-10-19 07:30 weekdays go to work");
+        assert!(handle_command(INTENT_ALARM, "07:30 [work]", &alarms).unwrap().starts_with("error"));
+        assert_eq!(Alarms::load(path, std::time::Duration::from_secs(60), clock.clone()).list(), "1 2026-10-19 07:30 weekdays go to work");
+
+        // Files which can't be parsed are skipped rather than panicking
+        fs::write(path, "07:30\n[1]\n").unwrap();
+        assert_eq!(Alarms::load(path, std::time::Duration::from_secs(60), clock).list(), "no alarms");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn snoozed_alarms_go_off_again() {
+        let (clock, mut alarms) = alarms();
+        alarms.ring("wake up");
+
+        let (label, at) = alarms.snooze(None).unwrap();
+        assert_eq!((label.as_str(), at.time()), ("wake up", NaiveTime::from_hms_opt(7, 9, 0).unwrap()));
+        assert!(! alarms.is_ringing());
+
+        clock.advance(std::time::Duration::from_secs(9 * 60));
+        assert_eq!(alarms.take_due(), vec!["wake up"]);
+    }
+
+    #[test]
+    fn snoozing_is_bounded() {
+        let (_, alarms) = alarms();
+        let alarms : AlarmsMutex = Arc::new(Mutex::new(alarms));
+        alarms.lock().unwrap().ring("wake up");
+
+        for minutes in ["0", "1441", "307445734561825861", "18446744073709551615", "soon"] {
+            assert!(handle_command(INTENT_SNOOZE, minutes, &alarms).unwrap().starts_with("error"), "{}", minutes);
+        }
+        assert!(alarms.lock().unwrap().is_ringing());
+
+        assert_eq!(handle_command(INTENT_SNOOZE, "1440", &alarms).unwrap(), "snoozed wake up until 07:00");
+        assert_eq!(handle_command(INTENT_SNOOZE, "", &alarms).unwrap(), "error: no alarm to snooze");
+    }
+}
@@ -0,0 +1,221 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::Duration;
+
+#[cfg(feature = "audio-soloud")]
+use soloud::*;
+
+use crate::config::Settings;
+use super::ClockHandle;
+use super::audio_capture::{clip_duration, CaptureAudio};
+
+const INTENT_VOLUME : &str = "volume"; // volume [0-100]
+
+/// How long the clips whose length can't be read (anything but PCM WAV files) are taken to last
+pub const UNKNOWN_DURATION : Duration = Duration::from_secs(3);
+
+/// An audio track to be played, along with what identifies it (such as "hello/2", the second
+/// track of the hello intent, or the path it was read from)
+pub struct Clip {
+    pub id : String,
+    pub data : Vec<u8>,
+}
+
+/// Whatever plays BMO's audio tracks. Every call blocks until the track is over, so they're
+/// made from their own threads (see play_sound and play_sound_looping).
+pub trait AudioBackend : Send + Sync {
+    /// Play a clip once
+    fn play(&self, clip : &Clip) -> Result<(), String>;
+
+    /// Play a clip in a loop, raising its volume gradually from silence during the given ramp,
+    /// until the stop flag is set
+    fn play_looping(&self, clip : &Clip, ramp : Duration, stop : &AtomicBool) -> Result<(), String>;
+
+    /// Volume the clips are played at, from 0 (muted) to 1 (as loud as they were recorded)
+    fn volume(&self) -> f32;
+
+    /// Change the volume, which the clips already playing pick up as well
+    fn set_volume(&self, volume : f32);
+}
+
+/// Volume shared between the threads playing the clips and whoever changes it
+pub struct Volume(AtomicU32); // Bits of an f32
+
+impl Volume {
+    pub fn new() -> Volume {
+        Volume(AtomicU32::new(1f32.to_bits()))
+    }
+
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, volume : f32) {
+        self.0.store(volume.clamp(0.0, 1.0).to_bits(), Ordering::Relaxed);
+    }
+}
+
+impl Default for Volume {
+    fn default() -> Volume {
+        Volume::new()
+    }
+}
+
+// Thread-safe AudioBackend instance
+pub type AudioHandle = Arc<dyn AudioBackend>;
+
+/// Audio backend chosen in the audio setting:
+/// - **soloud** (the default) plays the tracks on the sound device. If there isn't any, the
+///   null backend is used instead. It's only available with the audio-soloud feature, and null
+///   becomes the default without it.
+/// - **null** plays nothing, taking as long as the tracks last.
+/// - **capture** plays nothing either, writing what would have played to audio_capture (see CaptureAudio).
+pub fn from_settings(settings : &Settings, clock : ClockHandle) -> AudioHandle {
+    match settings.get_or("audio", "soloud").as_str() {
+        "null" => Arc::new(NullAudio::new(clock)),
+        "capture" => Arc::new(CaptureAudio::new(&settings.get_or("audio_capture", "audio_capture.wav"), clock)),
+        #[cfg(feature = "audio-soloud")]
+        _ => match Soloud::default() {
+            Ok(_) => Arc::new(SoloudAudio::new()),
+            Err(e) => {
+                eprintln!("Couldn't open the sound device ({}), audio tracks won't be played", e);
+                Arc::new(NullAudio::new(clock))
+            }
+        },
+        #[cfg(not(feature = "audio-soloud"))]
+        _ => Arc::new(NullAudio::new(clock)),
+    }
+}
+
+/// Plays the tracks on the sound device through soloud
+#[cfg(feature = "audio-soloud")]
+pub struct SoloudAudio {
+    volume : Volume,
+}
+
+#[cfg(feature = "audio-soloud")]
+impl SoloudAudio {
+    pub fn new() -> SoloudAudio {
+        SoloudAudio { volume : Volume::new() }
+    }
+}
+
+#[cfg(feature = "audio-soloud")]
+impl Default for SoloudAudio {
+    fn default() -> SoloudAudio {
+        SoloudAudio::new()
+    }
+}
+
+#[cfg(feature = "audio-soloud")]
+impl AudioBackend for SoloudAudio {
+    fn play(&self, clip : &Clip) -> Result<(), String> {
+        let mut sl = Soloud::default().map_err(|e| e.to_string())?;
+
+        let mut wav = audio::Wav::default();
+
+        wav.load_mem(clip.data.clone()).map_err(|e| e.to_string())?;
+
+        sl.set_global_volume(self.volume.get());
+        sl.play(&wav); // calls to play are non-blocking, so we put the thread to sleep
+        while sl.active_voice_count() > 0 {
+            std::thread::sleep(Duration::from_millis(100));
+            sl.set_global_volume(self.volume.get());
+        }
+
+        Ok(())
+    }
+
+    fn play_looping(&self, clip : &Clip, ramp : Duration, stop : &AtomicBool) -> Result<(), String> {
+        let mut sl = Soloud::default().map_err(|e| e.to_string())?;
+
+        let mut wav = audio::Wav::default();
+
+        wav.load_mem(clip.data.clone()).map_err(|e| e.to_string())?;
+        wav.set_looping(true);
+
+        sl.set_global_volume(self.volume.get());
+        let handle = sl.play_ex(&wav, 0.0, 0.0, false, Handle::PRIMARY); // Start muted,
+        sl.fade_volume(handle, 1.0, ramp.as_secs_f64()); // and fade in during the ramp
+
+        while ! stop.load(Ordering::Relaxed) {
+            std::thread::sleep(Duration::from_millis(100));
+            sl.set_global_volume(self.volume.get());
+        }
+
+        sl.stop_all();
+
+        Ok(())
+    }
+
+    fn volume(&self) -> f32 {
+        self.volume.get()
+    }
+
+    fn set_volume(&self, volume : f32) {
+        self.volume.set(volume);
+    }
+}
+
+/// Plays nothing, but takes as long as the tracks last (as told by the given clock), so BMO
+/// behaves the same as with a sound device
+pub struct NullAudio {
+    clock : ClockHandle,
+    volume : Volume,
+}
+
+impl NullAudio {
+    pub fn new(clock : ClockHandle) -> NullAudio {
+        NullAudio { clock, volume : Volume::new() }
+    }
+}
+
+impl AudioBackend for NullAudio {
+    fn play(&self, clip : &Clip) -> Result<(), String> {
+        self.clock.sleep(clip_duration(&clip.data).unwrap_or(UNKNOWN_DURATION));
+
+        Ok(())
+    }
+
+    fn play_looping(&self, _clip : &Clip, _ramp : Duration, stop : &AtomicBool) -> Result<(), String> {
+        while ! stop.load(Ordering::Relaxed) {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+
+        Ok(())
+    }
+
+    fn volume(&self) -> f32 {
+        self.volume.get()
+    }
+
+    fn set_volume(&self, volume : f32) {
+        self.volume.set(volume);
+    }
+}
+
+/// Handle the volume command, returning the reply to be sent back to the client, or None if
+/// the intent isn't it:
+/// - "volume" tells the current volume, from 0 to 100.
+/// - "volume level" changes it.
+pub fn handle_command(intent : &str, args : &str, audio : &AudioHandle) -> Option<String> {
+    if intent != INTENT_VOLUME {
+        return None;
+    }
+
+    let reply = if args.is_empty() {
+        format!("volume {}", (audio.volume() * 100.0).round())
+    } else {
+        match args.parse::<f32>() {
+            Ok(level) if (0.0..=100.0).contains(&level) => {
+                audio.set_volume(level / 100.0);
+                format!("volume {}", level)
+            }
+            _ => format!("invalid volume: {}", args),
+        }
+    };
+
+    println!("{} {}: {}", intent, args, reply);
+
+    Some(reply)
+}
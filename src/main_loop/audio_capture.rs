@@ -0,0 +1,325 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use super::ClockHandle;
+use super::audio_backend::{AudioBackend, Clip, Volume, UNKNOWN_DURATION};
+
+const SAMPLE_RATE : u32 = 44100; // Of the mix written by CaptureAudio, which is mono and 16 bit
+
+/// How much of the capture is mixed, from its start
+pub const MIX_LENGTH : Duration = Duration::from_secs(60);
+
+/// A clip which would have played, and when (since the capture started)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Played {
+    pub clip : String,
+    pub at : Duration,
+    pub looping : bool,
+}
+
+// Clips played so far, and their mix
+struct Recording {
+    played : Vec<Played>,
+    mix : Vec<i32>,
+}
+
+/// Plays nothing, writing what would have played instead, so that tests (or whoever runs BMO
+/// without a sound device) can check it:
+/// - The mix of every clip, at the moment (as told by the given clock) it would have played,
+///   to the given WAV file. Only PCM WAV clips are mixed, the rest are just listed, and so are
+///   the ones played after MIX_LENGTH, so that the mix (kept in memory and rewritten whenever a
+///   clip is mixed) doesn't grow forever.
+/// - The list of clips, with when they played (in milliseconds) and their id, to a text file
+///   next to it (audio_capture.txt for audio_capture.wav).
+///
+/// They're written whenever a clip finishes. Clips take as long as they last, as with NullAudio,
+/// and they're mixed at the volume there was when they started.
+pub struct CaptureAudio {
+    path : PathBuf,
+    clock : ClockHandle,
+    started : Instant,
+    volume : Volume,
+    recording : Mutex<Recording>,
+}
+
+impl CaptureAudio {
+    pub fn new(path : &str, clock : ClockHandle) -> CaptureAudio {
+        let started = clock.now();
+
+        CaptureAudio { path : PathBuf::from(path), clock, started, volume : Volume::new(), recording : Mutex::new(Recording { played : Vec::new(), mix : Vec::new() }) }
+    }
+
+    /// Clips played so far
+    pub fn played(&self) -> Vec<Played> {
+        self.recording.lock().unwrap().played.clone()
+    }
+
+    // Mix a clip lasting the given time at the given volume (looping it if needed, raising its
+    // volume during the ramp) from the given moment on, and write down what has played so far
+    fn record(&self, clip : &Clip, at : Duration, length : Duration, volume : f32, ramp : Duration, looping : bool) {
+        let mut recording = self.recording.lock().unwrap();
+        recording.played.push(Played { clip : clip.id.clone(), at, looping });
+
+        let mixed = match read_wav(&clip.data) {
+            Some(_) if at >= MIX_LENGTH => false,
+            Some(pcm) => {
+                let start = frames(at);
+                let count = frames(length).min(frames(MIX_LENGTH) - start);
+                let ramp = frames(ramp);
+
+                if recording.mix.len() < start + count {
+                    recording.mix.resize(start + count, 0);
+                }
+
+                for i in 0..count {
+                    let gain = if i < ramp { i as f64 / ramp as f64 } else { 1.0 } * volume as f64;
+                    recording.mix[start + i] += (pcm.sample_at(i) as f64 * gain) as i32;
+                }
+
+                true
+            }
+            None => {
+                eprintln!("Can't mix {}, only PCM WAV files can be", clip.id);
+                false
+            }
+        };
+
+        let written = if mixed || ! self.path.exists() { write_wav(&self.path, &recording.mix) } else { Ok(()) };
+        if let Err(e) = written.and_then(|_| write_played(&self.path, &recording.played)) {
+            eprintln!("Couldn't write the audio capture to {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+impl AudioBackend for CaptureAudio {
+    fn play(&self, clip : &Clip) -> Result<(), String> {
+        let at = self.clock.now() - self.started;
+        let length = clip_duration(&clip.data).unwrap_or(UNKNOWN_DURATION);
+        let volume = self.volume.get();
+
+        self.clock.sleep(length);
+        self.record(clip, at, length, volume, Duration::new(0, 0), false);
+
+        Ok(())
+    }
+
+    fn play_looping(&self, clip : &Clip, ramp : Duration, stop : &AtomicBool) -> Result<(), String> {
+        let at = self.clock.now() - self.started;
+        let volume = self.volume.get();
+
+        while ! stop.load(Ordering::Relaxed) {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+
+        self.record(clip, at, self.clock.now() - self.started - at, volume, ramp, true);
+
+        Ok(())
+    }
+
+    fn volume(&self) -> f32 {
+        self.volume.get()
+    }
+
+    fn set_volume(&self, volume : f32) {
+        self.volume.set(volume);
+    }
+}
+
+// Amount of frames of the mix taking the given time
+fn frames(duration : Duration) -> usize {
+    (duration.as_secs_f64() * SAMPLE_RATE as f64) as usize
+}
+
+// Decoded PCM WAV file, with a sample rate and at least a frame
+struct Pcm {
+    sample_rate : u32,
+    channels : usize,
+    samples : Vec<i16>, // Interleaved
+}
+
+impl Pcm {
+    fn duration(&self) -> Duration {
+        Duration::from_secs_f64((self.samples.len() / self.channels) as f64 / self.sample_rate as f64)
+    }
+
+    // Sample of the given frame of the mix, which loops over the clip, with its channels averaged
+    fn sample_at(&self, frame : usize) -> i16 {
+        let frame = (frame as u64 * self.sample_rate as u64 / SAMPLE_RATE as u64) as usize % (self.samples.len() / self.channels);
+        let channels = &self.samples[frame * self.channels..][..self.channels];
+
+        (channels.iter().map(|sample| *sample as i32).sum::<i32>() / self.channels as i32) as i16
+    }
+}
+
+/// How long a clip lasts, if it's a PCM WAV file
+pub fn clip_duration(data : &[u8]) -> Option<Duration> {
+    read_wav(data).map(|pcm| pcm.duration())
+}
+
+// Decode an 8 or 16 bit PCM WAV file
+fn read_wav(data : &[u8]) -> Option<Pcm> {
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return None;
+    }
+
+    let mut format : Option<(u16, usize, u32, u16)> = None; // Format tag, channels, sample rate and bits per sample
+    let mut chunks = &data[12..];
+
+    while chunks.len() >= 8 {
+        let size = u32::from_le_bytes([chunks[4], chunks[5], chunks[6], chunks[7]]) as usize;
+        let body = &chunks[8..(8 + size).min(chunks.len())];
+
+        match &chunks[0..4] {
+            b"fmt " if body.len() >= 16 => {
+                format = Some((u16::from_le_bytes([body[0], body[1]]),
+                               u16::from_le_bytes([body[2], body[3]]) as usize,
+                               u32::from_le_bytes([body[4], body[5], body[6], body[7]]),
+                               u16::from_le_bytes([body[14], body[15]])));
+            }
+            b"data" => {
+                let samples = match format? {
+                    (1, channels, _, 16) if channels > 0 => body.chunks_exact(2).map(|pair| i16::from_le_bytes([pair[0], pair[1]])).collect(),
+                    (1, channels, _, 8) if channels > 0 => body.iter().map(|sample| (*sample as i16 - 128) << 8).collect(),
+                    _ => return None,
+                };
+                let (_, channels, sample_rate, _) = format?;
+
+                return Some(Pcm { sample_rate, channels, samples }).filter(|pcm| pcm.sample_rate > 0 && pcm.samples.len() >= pcm.channels);
+            }
+            _ => {}
+        }
+
+        chunks = &chunks[(8 + size + size % 2).min(chunks.len())..]; // Chunks are padded to an even size
+    }
+
+    None
+}
+
+// Write a mono 16 bit PCM WAV file, clipping the mix
+fn write_wav(path : &Path, mix : &[i32]) -> Result<(), String> {
+    let data_size = mix.len() as u32 * 2;
+
+    let mut wav = Vec::with_capacity(44 + data_size as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_size).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes()); // Mono
+    wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes()); // Bytes per second
+    wav.extend_from_slice(&2u16.to_le_bytes()); // Bytes per frame
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_size.to_le_bytes());
+
+    for sample in mix {
+        wav.extend_from_slice(&((*sample).clamp(i16::MIN as i32, i16::MAX as i32) as i16).to_le_bytes());
+    }
+
+    fs::write(path, wav).map_err(|e| e.to_string())
+}
+
+// Write the clips played, one per line
+fn write_played(path : &Path, played : &[Played]) -> Result<(), String> {
+    let contents : String = played.iter()
+                            .map(|played| format!("{} {}{}\n", played.at.as_millis(), played.clip, if played.looping { " (looping)" } else { "" }))
+                            .collect();
+
+    fs::write(path.with_extension("txt"), contents).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::main_loop::SystemClock;
+    use std::sync::Arc;
+
+    // A second of a mono 16 bit tone at 8 kHz, at the given level
+    fn tone(level : i16) -> Vec<u8> {
+        let mix = vec![level as i32; 8000];
+
+        let path = std::env::temp_dir().join(format!("bmos_tone_{}_{}.wav", std::process::id(), level));
+        write_wav(&path, &mix).unwrap();
+        let mut data = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        data[24..28].copy_from_slice(&8000u32.to_le_bytes()); // Written at 44.1 kHz, so fix the sample rate
+        data
+    }
+
+    #[test]
+    fn wav_durations_are_read() {
+        assert_eq!(clip_duration(&tone(1000)), Some(Duration::from_secs(1)));
+        assert_eq!(clip_duration(b"OggS not a wav file"), None);
+    }
+
+    #[test]
+    fn empty_wav_files_are_refused() {
+        let mut silent = tone(1000);
+        silent[24..28].copy_from_slice(&0u32.to_le_bytes());
+        assert_eq!(clip_duration(&silent), None);
+
+        let mut empty = tone(1000);
+        empty[40..44].copy_from_slice(&0u32.to_le_bytes());
+        assert_eq!(clip_duration(&empty[..44]), None);
+    }
+
+    #[test]
+    fn only_the_start_is_mixed() {
+        let path = std::env::temp_dir().join(format!("bmos_audio_window_{}.wav", std::process::id()));
+        let audio = CaptureAudio::new(path.to_str().unwrap(), Arc::new(SystemClock));
+
+        audio.record(&Clip { id : "early".to_owned(), data : tone(1000) }, MIX_LENGTH - Duration::from_millis(500), Duration::from_secs(1), 1.0, Duration::new(0, 0), false);
+        audio.record(&Clip { id : "late".to_owned(), data : tone(1000) }, Duration::from_secs(60 * 60), Duration::from_secs(1), 1.0, Duration::new(0, 0), false);
+
+        assert_eq!(audio.played().len(), 2);
+        assert_eq!(read_wav(&fs::read(&path).unwrap()).unwrap().duration(), MIX_LENGTH);
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("txt")).unwrap();
+    }
+
+    #[test]
+    fn played_clips_are_listed_and_mixed() {
+        let path = std::env::temp_dir().join(format!("bmos_audio_capture_{}.wav", std::process::id()));
+        let audio = CaptureAudio::new(path.to_str().unwrap(), Arc::new(SystemClock));
+
+        // Two overlapping clips, the second one starting half a second later at half the volume
+        audio.record(&Clip { id : "hello/1".to_owned(), data : tone(1000) }, Duration::new(0, 0), Duration::from_secs(1), 1.0, Duration::new(0, 0), false);
+        audio.record(&Clip { id : "hello/2".to_owned(), data : tone(4000) }, Duration::from_millis(500), Duration::from_secs(1), 0.5, Duration::new(0, 0), false);
+
+        assert_eq!(audio.played(), vec![Played { clip : "hello/1".to_owned(), at : Duration::new(0, 0), looping : false },
+                                        Played { clip : "hello/2".to_owned(), at : Duration::from_millis(500), looping : false }]);
+        assert_eq!(fs::read_to_string(path.with_extension("txt")).unwrap(), "0 hello/1\n500 hello/2\n");
+
+        let mix = read_wav(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(mix.duration(), Duration::from_millis(1500));
+        assert_eq!(mix.samples[frames(Duration::from_millis(250))], 1000);
+        assert_eq!(mix.samples[frames(Duration::from_millis(750))], 3000);
+        assert_eq!(mix.samples[frames(Duration::from_millis(1250))], 2000);
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("txt")).unwrap();
+    }
+
+    #[test]
+    fn clips_take_as_long_as_they_last() {
+        let path = std::env::temp_dir().join(format!("bmos_audio_sleep_{}.wav", std::process::id()));
+        let audio = CaptureAudio::new(path.to_str().unwrap(), Arc::new(SystemClock));
+
+        let started = Instant::now();
+        let mut clip = tone(1000);
+        clip[40..44].copy_from_slice(&1600u32.to_le_bytes()); // Only a tenth of a second of it
+        audio.play(&Clip { id : "short".to_owned(), data : clip }).unwrap();
+
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        assert_eq!(audio.played()[0].clip, "short");
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("txt")).unwrap();
+    }
+}
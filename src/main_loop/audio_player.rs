@@ -1,28 +1,23 @@
+use std::fs;
 use std::thread;
 use std::sync::Arc;
-
-use soloud::*;
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
 
 use super::StateMutex;
+use super::audio_backend::{AudioHandle, Clip};
 
 
-/// Plays a sound asynchronously, and sets the pointed state's audio_finished to true 
-/// once it finishes.
+/// Plays a sound asynchronously on the given backend, and sets the pointed state's audio_finished
+/// to true once it finishes (or fails to play, which is just logged).
 /// The behavior is undefined if multiple sounds are played at the same time with the
 /// same state instance.
-pub fn play_sound(path : Vec<u8>, state : StateMutex ) {
+pub fn play_sound(audio : AudioHandle, clip : Clip, state : StateMutex ) {
     let state_clone = Arc::clone(&state);
 
     thread::spawn(move || {
-        let sl = Soloud::default().unwrap();
-
-        let mut wav = audio::Wav::default();
-
-        wav.load_mem(path).unwrap();
-
-        sl.play(&wav); // calls to play are non-blocking, so we put the thread to sleep
-        while sl.active_voice_count() > 0 {
-            std::thread::sleep(std::time::Duration::from_millis(100));
+        if let Err(e) = audio.play(&clip) {
+            eprintln!("Couldn't play {}: {}", clip.id, e);
         }
 
         if let Ok(mut state) = state_clone.lock() {
@@ -32,18 +27,24 @@ pub fn play_sound(path : Vec<u8>, state : StateMutex ) {
 }
 
 
-/// Plays a sound, blocking until it's finished.
-pub fn play_sound_blocking(path : &str) {
-    let sl = Soloud::default().unwrap();
+/// Plays the sound on the given path in a loop asynchronously on the given backend, raising its
+/// volume gradually from silence during the given ramp. It keeps playing until the returned flag
+/// is set to true.
+pub fn play_sound_looping(audio : AudioHandle, path : &str, ramp : Duration) -> Arc<AtomicBool> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let stop_clone = Arc::clone(&stop);
+    let path = path.to_owned();
 
-    let mut wav = audio::Wav::default();
+    thread::spawn(move || {
+        let played = fs::read(&path).map_err(|e| e.to_string())
+                                    .and_then(|data| audio.play_looping(&Clip { id : path.clone(), data }, ramp, &stop_clone));
 
-    wav.load(&std::path::Path::new(&path)).unwrap();
+        if let Err(e) = played {
+            eprintln!("Couldn't play {}: {}", path, e);
+        }
+    });
 
-    sl.play(&wav); // calls to play are non-blocking, so we put the thread to sleep
-    while sl.active_voice_count() > 0 {
-        std::thread::sleep(std::time::Duration::from_millis(100));
-    }
+    stop
 }
 
 
@@ -0,0 +1,289 @@
+extern crate chrono;
+extern crate sdl2;
+
+// SDL libs
+use sdl2::image::SaveSurface;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::Canvas;
+use sdl2::rwops::RWops;
+use sdl2::surface::Surface;
+
+use chrono::Local;
+
+use std::fs;
+use std::io::Seek;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::time::{Duration, Instant};
+
+use crate::config::Settings;
+use super::ClockHandle;
+
+const INTENT_SCREENSHOT : &str = "screenshot"; // screenshot [file name]
+const INTENT_RECORD : &str = "record"; // record fps [folder name], or record stop
+const ARG_STOP : &str = "stop";
+
+/// Highest rate frames can be recorded at
+pub const MAX_FPS : f64 = 60.0;
+
+// Frames being recorded to a numbered PNG sequence
+struct Recording {
+    folder : PathBuf,
+    interval : Duration, // Time between frames
+    next_at : Instant, // When the next frame is due
+    frame : u32, // Number of the next frame
+}
+
+/// Screenshots and recordings of what BMO shows, taken when a frame is presented (see present).
+/// Frames are recorded at the given rate as told by the given clock.
+pub struct Capture {
+    clock : ClockHandle,
+    screenshots : PathBuf, // Folder where the screenshots are stored by default
+    frames : PathBuf, // Folder where the frames are recorded by default
+    screenshot : Option<PathBuf>, // Screenshot asked for, taken on the next frame
+    frames_wanted : Vec<SyncSender<Result<Vec<u8>, String>>>, // Sent the next frame (see next_frame)
+    recording : Option<Recording>,
+}
+
+impl Capture {
+    /// Capture with the folders in the settings:
+    /// - **screenshots** (screenshots by default) : Folder where the screenshots are stored.
+    /// - **record_folder** (frames by default) : Folder where the frames are recorded.
+    /// - **record_fps** : If present, frames are recorded at this rate from launch.
+    pub fn from_settings(settings : &Settings, clock : ClockHandle) -> Capture {
+        let mut capture = Capture {
+            clock,
+            screenshots : PathBuf::from(settings.get_or("screenshots", "screenshots")),
+            frames : PathBuf::from(settings.get_or("record_folder", "frames")),
+            screenshot : None,
+            frames_wanted : Vec::new(),
+            recording : None,
+        };
+
+        if let Some(fps) = settings.get("record_fps") {
+            match frame_interval(fps) {
+                Ok(interval) => {
+                    let folder = capture.frames.clone();
+                    capture.record(interval, folder);
+                }
+                Err(e) => eprintln!("Couldn't record from launch: {}", e),
+            }
+        }
+
+        capture
+    }
+
+    /// Take a screenshot on the next frame, storing it on the given path or on a new file named
+    /// after the current time in the screenshots folder. Returns where it will be stored.
+    pub fn screenshot(&mut self, path : Option<PathBuf>) -> PathBuf {
+        let path = path.unwrap_or_else(|| self.screenshots.join(format!("screenshot-{}.png", Local::now().format("%Y%m%d-%H%M%S%.3f"))));
+        self.screenshot = Some(path.clone());
+        path
+    }
+
+    /// Receive the next frame presented as a PNG file, without storing it anywhere
+    pub fn next_frame(&mut self) -> Receiver<Result<Vec<u8>, String>> {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        self.frames_wanted.push(sender);
+        receiver
+    }
+
+    /// Start recording frames every so often (see frame_interval) to a numbered sequence
+    /// (frame-000001.png...) in the given folder
+    pub fn record(&mut self, interval : Duration, folder : PathBuf) {
+        self.recording = Some(Recording {
+            folder,
+            interval,
+            next_at : self.clock.now(),
+            frame : 1,
+        });
+    }
+
+    /// Stop recording, returning how many frames were recorded
+    pub fn stop(&mut self) -> Option<u32> {
+        self.recording.take().map(|recording| recording.frame - 1)
+    }
+
+    // Paths where the frame about to be presented needs to be stored, if any
+    fn take_due(&mut self) -> Vec<PathBuf> {
+        let mut due : Vec<PathBuf> = self.screenshot.take().into_iter().collect();
+
+        let now = self.clock.now();
+
+        if let Some(ref mut recording) = self.recording {
+            if recording.next_at <= now {
+                due.push(recording.folder.join(format!("frame-{:06}.png", recording.frame)));
+                recording.frame += 1;
+                recording.next_at += recording.interval;
+
+                // Skip the frames which couldn't be recorded, rather than catching up
+                if recording.next_at < now {
+                    recording.next_at = now + recording.interval;
+                }
+            }
+        }
+
+        due
+    }
+}
+
+/// Time between the frames recorded at the given rate ("30", "0.5"...), which needs to be above 0
+/// and MAX_FPS at most
+pub fn frame_interval(fps : &str) -> Result<Duration, String> {
+    fps.parse::<f64>().ok()
+       .filter(|fps| *fps > 0.0 && *fps <= MAX_FPS)
+       .and_then(|fps| Duration::try_from_secs_f64(1.0 / fps).ok())
+       .ok_or(format!("invalid frame rate: {} (up to {} frames per second)", fps, MAX_FPS))
+}
+
+// Thread-safe Capture instance
+pub type CaptureMutex = Arc<Mutex<Capture>>;
+
+/// Handle the capture commands, returning the reply to be sent back to the client, or None if
+/// the intent isn't one of them:
+/// - "screenshot [file name]" takes a screenshot on the next frame.
+/// - "record fps [folder name]" starts recording frames, and "record stop" stops it.
+///
+/// Clients can only name the file or folder, which goes in the screenshots or record_folder
+/// folder, rather than write anywhere.
+pub fn handle_command(intent : &str, args : &str, capture : &CaptureMutex) -> Option<String> {
+    let mut capture = capture.lock().unwrap();
+
+    match intent {
+        INTENT_SCREENSHOT if args.is_empty() => Some(format!("screenshot {}", capture.screenshot(None).display())),
+        INTENT_SCREENSHOT => Some(match bare_name(args) {
+            Some(name) => {
+                let path = capture.screenshots.join(name);
+                format!("screenshot {}", capture.screenshot(Some(path)).display())
+            }
+            None => format!("invalid file name: {}", args),
+        }),
+        INTENT_RECORD if args == ARG_STOP => Some(match capture.stop() {
+            Some(frames) => format!("recorded {} frames", frames),
+            None => "not recording".to_owned(),
+        }),
+        INTENT_RECORD => {
+            let (fps, folder) = match args.split_once(' ') {
+                Some((fps, folder)) => match bare_name(folder.trim()) {
+                    Some(folder) => (fps, capture.frames.join(folder)),
+                    None => return Some(format!("invalid folder name: {}", folder.trim())),
+                },
+                None => (args, capture.frames.clone()),
+            };
+
+            Some(match frame_interval(fps) {
+                Ok(interval) => {
+                    let reply = format!("recording {} frames per second to {}", fps, folder.display());
+                    capture.record(interval, folder);
+                    reply
+                }
+                Err(e) => e,
+            })
+        }
+        _ => None,
+    }
+}
+
+// The given file or folder name, if it's just that: no separators, and neither . nor ..
+fn bare_name(name : &str) -> Option<&str> {
+    Some(name).filter(|name| ! name.is_empty() && *name != "." && *name != ".." && ! name.contains(['/', '\\']))
+}
+
+/// Present the canvas, storing it beforehand if a screenshot or a recorded frame is due, and
+/// sending it to whoever asked for the next frame. Failing to store it isn't worth stopping BMO
+/// for, so the errors are just logged.
+pub fn present<T: sdl2::render::RenderTarget>(canvas : &mut Canvas<T>, capture : &CaptureMutex) {
+    let (due, wanted) = {
+        let mut capture = capture.lock().unwrap();
+        (capture.take_due(), std::mem::take(&mut capture.frames_wanted))
+    };
+
+    for path in due {
+        if let Err(e) = save_png(canvas, &path) {
+            eprintln!("Couldn't store {}: {}", path.display(), e);
+        }
+    }
+
+    if ! wanted.is_empty() {
+        let png = encode_png(canvas);
+
+        for sender in wanted {
+            let _ = sender.send(png.clone()); // Whoever asked might have given up waiting
+        }
+    }
+
+    canvas.present();
+}
+
+/// Store the pixels of a canvas (as drawn so far) in a PNG file, creating its folder if needed
+pub fn save_png<T: sdl2::render::RenderTarget>(canvas : &Canvas<T>, path : &Path) -> Result<(), String> {
+    let png = encode_png(canvas)?;
+
+    if let Some(folder) = path.parent() {
+        fs::create_dir_all(folder).map_err(|e| e.to_string())?;
+    }
+
+    fs::write(path, png).map_err(|e| e.to_string())
+}
+
+/// Encode the pixels of a canvas (as drawn so far) as a PNG file
+pub fn encode_png<T: sdl2::render::RenderTarget>(canvas : &Canvas<T>) -> Result<Vec<u8>, String> {
+    let (width, height) = canvas.output_size()?;
+    let mut pixels = canvas.read_pixels(None, PixelFormatEnum::RGB24)?;
+
+    // Room for the pixels even if they can't be compressed at all, along with every header
+    let mut png = vec![0; pixels.len() + pixels.len() / 64 + height as usize + 4096];
+
+    let surface = Surface::from_data(&mut pixels, width, height, width * 3, PixelFormatEnum::RGB24)?;
+    let length = {
+        let mut writer = RWops::from_bytes_mut(&mut png)?;
+        surface.save_rw(&mut writer)?;
+        writer.stream_position().map_err(|e| e.to_string())?
+    };
+
+    png.truncate(length as usize);
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::main_loop::SystemClock;
+
+    #[test]
+    fn clients_only_name_the_files() {
+        let capture : CaptureMutex = Arc::new(Mutex::new(Capture::from_settings(&Settings::default(), Arc::new(SystemClock))));
+
+        assert_eq!(handle_command(INTENT_SCREENSHOT, "kitchen.png", &capture).unwrap(), "screenshot screenshots/kitchen.png");
+        assert_eq!(handle_command(INTENT_RECORD, "10 demo", &capture).unwrap(), "recording 10 frames per second to frames/demo");
+
+        for path in ["../kitchen.png", "/etc/passwd", "shots/kitchen.png", "..", "C:\\kitchen.png"] {
+            assert_eq!(handle_command(INTENT_SCREENSHOT, path, &capture).unwrap(), format!("invalid file name: {}", path));
+            assert_eq!(handle_command(INTENT_RECORD, &format!("10 {}", path), &capture).unwrap(), format!("invalid folder name: {}", path));
+        }
+    }
+
+    #[test]
+    fn the_next_frame_is_sent_back() {
+        let capture : CaptureMutex = Arc::new(Mutex::new(Capture::from_settings(&Settings::default(), Arc::new(SystemClock))));
+        let mut canvas = crate::main_loop::offscreen_canvas(32, 24).unwrap();
+
+        let frame = capture.lock().unwrap().next_frame();
+        assert!(frame.try_recv().is_err());
+
+        present(&mut canvas, &capture);
+        let png = frame.try_recv().unwrap().unwrap();
+        assert!(png.starts_with(b"\x89PNG") && png.ends_with(b"IEND\xae\x42\x60\x82"));
+    }
+
+    #[test]
+    fn frame_rates_are_bounded() {
+        assert_eq!(frame_interval("0.5"), Ok(Duration::from_secs(2)));
+        assert_eq!(frame_interval("60"), Ok(Duration::from_secs_f64(1.0 / 60.0)));
+
+        for fps in ["0", "-1", "61", "1e-20", "1e-320", "NaN", "inf", "fast"] {
+            assert!(frame_interval(fps).unwrap_err().starts_with("invalid frame rate"), "{}", fps);
+        }
+    }
+}
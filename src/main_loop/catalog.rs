@@ -0,0 +1,147 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::config::Config;
+use crate::features;
+
+const INTENT_INTENTS : &str = "intents"; // lists the intents with faces
+const INTENT_RELOAD : &str = "reload"; // loads the configuration files again
+
+// Preset intents, shown without any faces of their own
+const PRESETS : [&str; 4] = ["chronometer", "stopwatch", "clock", "weather"];
+
+/// Intents BMO can show, and the configuration files they were loaded from, which can be
+/// loaded again while it runs. The main loop picks the reloaded configuration up (see take_reloaded).
+pub struct Catalog {
+    folder : PathBuf,
+    intents : Vec<String>,
+    reloaded : Option<Config>,
+}
+
+impl Catalog {
+    pub fn new(config : &Config) -> Catalog {
+        Catalog { folder : config.folder.clone(), intents : intent_names(config), reloaded : None }
+    }
+
+    /// Intents with faces, sorted
+    pub fn intents(&self) -> &[String] {
+        &self.intents
+    }
+
+    /// Preset intents available with the features BMO was built with
+    pub fn presets(&self) -> Vec<&'static str> {
+        PRESETS.iter().copied().filter(|intent| features::missing_for_intent(intent).is_none()).collect()
+    }
+
+    /// Load the configuration files again, keeping the current ones if they can't be loaded or
+    /// if they lack anything the intents need to be shown (see showable). Only the intents'
+    /// faces, audio tracks, timings and the chronometer steps are reloaded, the settings are
+    /// read at launch.
+    pub fn reload(&mut self) -> Result<(), String> {
+        let config = Config::load(&self.folder)?;
+        config.validate()?;
+        showable(&config)?;
+
+        self.intents = intent_names(&config);
+        self.reloaded = Some(config);
+
+        Ok(())
+    }
+
+    /// Configuration reloaded since the last time it was taken, if any
+    pub fn take_reloaded(&mut self) -> Option<Config> {
+        self.reloaded.take()
+    }
+}
+
+// Thread-safe Catalog instance
+pub type CatalogMutex = Arc<Mutex<Catalog>>;
+
+fn intent_names(config : &Config) -> Vec<String> {
+    let mut intents : Vec<String> = config.intent_faces.keys().cloned().collect();
+    intents.sort();
+    intents
+}
+
+/// Can the main loop show every intent of the configuration? They need a face and a timing
+/// each, and the default intent (which the main loop falls back to) has to be one of them.
+pub fn showable(config : &Config) -> Result<(), String> {
+    if ! config.intent_faces.contains_key("default") {
+        return Err("No faces found for the default intent".to_owned());
+    }
+
+    for intent in intent_names(config) {
+        if config.intent_faces[&intent].is_empty() {
+            return Err(format!("No faces found for intent {}", intent));
+        }
+        if ! config.intent_timings.contains_key(&intent) {
+            return Err(format!("No timing found for intent {}", intent));
+        }
+    }
+
+    Ok(())
+}
+
+/// Handle the catalog commands, returning the reply to be sent back to the client, or None if
+/// the intent isn't one of them:
+/// - "intents" lists the intents with faces, and then the preset ones.
+/// - "reload" loads the configuration files again.
+pub fn handle_command(intent : &str, args : &str, catalog : &CatalogMutex) -> Option<String> {
+    let mut catalog = catalog.lock().unwrap();
+
+    let reply = match intent {
+        INTENT_INTENTS => format!("{} {}", catalog.intents().join(" "), catalog.presets().join(" ")).trim().to_owned(),
+        INTENT_RELOAD => match catalog.reload() {
+            Ok(()) => format!("reloaded {} intents", catalog.intents().len()),
+            Err(e) => format!("error: {}", e),
+        },
+        _ => return None,
+    };
+
+    println!("{} {}: {}", intent, args, reply);
+
+    Some(reply)
+}
+
+#[cfg(all(test, feature = "weather", feature = "text"))] // The fixtures' settings need them
+mod tests {
+    use super::*;
+
+    const FIXTURES : &str = "tests/fixtures/golden";
+
+    #[test]
+    fn reloads_are_picked_up_once() {
+        let config = Config::load(FIXTURES).unwrap();
+        let catalog : CatalogMutex = Arc::new(Mutex::new(Catalog::new(&config)));
+
+        assert!(catalog.lock().unwrap().intents().contains(&"default".to_owned()));
+        assert_eq!(handle_command("reload", "", &catalog).unwrap(), format!("reloaded {} intents", config.intent_faces.len()));
+
+        let reloaded = catalog.lock().unwrap().take_reloaded().unwrap();
+        assert_eq!(reloaded.intent_faces.len(), config.intent_faces.len());
+        assert!(catalog.lock().unwrap().take_reloaded().is_none());
+    }
+
+    #[test]
+    fn failed_reloads_keep_the_current_configuration() {
+        let mut config = Config::load(FIXTURES).unwrap();
+        config.folder = PathBuf::from("tests/fixtures/missing");
+        let catalog : CatalogMutex = Arc::new(Mutex::new(Catalog::new(&config)));
+
+        assert!(handle_command("reload", "", &catalog).unwrap().starts_with("error: Couldn't load"));
+        assert!(catalog.lock().unwrap().take_reloaded().is_none());
+        assert!(! catalog.lock().unwrap().intents().is_empty());
+    }
+
+    #[test]
+    fn intents_which_cant_be_shown_are_refused() {
+        let mut config = Config::load(FIXTURES).unwrap();
+        assert_eq!(showable(&config), Ok(()));
+
+        config.intent_timings.remove("weather_rain");
+        assert_eq!(showable(&config), Err("No timing found for intent weather_rain".to_owned()));
+
+        config.intent_faces.remove("default");
+        assert_eq!(showable(&config), Err("No faces found for the default intent".to_owned()));
+    }
+}
@@ -2,39 +2,45 @@ extern crate sdl2;
 extern crate random_number; // For loading random assets for a given intent
 
 // SDL libs
+#[cfg(feature = "text")]
 use sdl2::render::Canvas;
+#[cfg(feature = "text")]
 use sdl2::pixels::Color;
+#[cfg(feature = "text")]
 use sdl2::render::TextureQuery;
 use sdl2::rect::Rect;
+#[cfg(feature = "text")]
 use sdl2::render::TextureCreator;
 use std::time::Duration; // Sleeping and timers
-use std::path::{Path}; // for providing paths to SDL
+#[cfg(feature = "text")]
 use super::StateMutex;
+#[cfg(feature = "text")]
 use std::sync::{Mutex, Arc, Condvar};
+#[cfg(feature = "text")]
+use std::collections::HashMap;
 
-use crate::main_loop::sdl2::image::LoadTexture; // use the implemented trait here
-use super::play_sound_blocking;
+#[cfg(feature = "text")]
+use super::CHRONOMETER_STATE;
+#[cfg(feature = "text")]
+use super::Screen;
+#[cfg(feature = "text")]
+use crate::config::parse_duration;
 
-const INTENT_5_MORE : &str = "5more";
-const INTENT_10_MORE : &str = "10more";
-const INTENT_20_MORE : &str = "20more";
-const INTENT_5_LESS : &str = "5less";
-const INTENT_10_LESS : &str = "10less";
-const INTENT_20_LESS : &str = "20less";
+#[cfg(feature = "text")]
 const INTENT_DONE : &str = "done";
 
-const ALARM_FACE : &str = "assets/faces/alarm.jpg";
-const ALARM_SOUND : &str = "assets/audio/alarm.wav";
 
 
 /// Hijacks an SDL context and displays a Duration while the received intent is not INTENT_DONE,
-/// which is modified by change_duration and eventually returned
-pub fn get_time<T: crate::main_loop::sdl2::render::RenderTarget, U>(res_width : u32, res_height : u32,
-                                                                    canvas : &mut Canvas<T>,
-                                                                    ttf_context : &sdl2::ttf::Sdl2TtfContext, 
-                                                                    texture_creator : &TextureCreator<U>, 
+/// which is modified by change_duration with the given steps and eventually returned.
+///
+/// Receiving the chronometer intent along with a duration (e.g. "chronometer 1:15:00")
+/// returns that duration straight away.
+#[cfg(feature = "text")]
+pub fn get_time<T: crate::main_loop::sdl2::render::RenderTarget, U>(screen : &mut Screen<T, U>,
                                                                     state: StateMutex, 
-                                                                    new_intent_available : Arc<(Mutex<bool>,Condvar)>) 
+                                                                    new_intent_available : Arc<(Mutex<bool>,Condvar)>,
+                                                                    steps : &HashMap<String, i64>) 
                                                                     -> Result<Duration, String> {
     let mut parsed_duration = Duration::new(0, 0);
     let mut parsed_intent = "".to_owned();
@@ -42,21 +48,22 @@ pub fn get_time<T: crate::main_loop::sdl2::render::RenderTarget, U>(res_width :
     let (lock, cvar) = &*new_intent_available;
 
     // Load the font
-    let mut font = ttf_context.load_font("assets/font.ttf", 128)?;
+    let ttf_context = screen.ttf_context;
+    let mut font = ttf_context.load_font(screen.font_path, 128)?;
     font.set_style(sdl2::ttf::FontStyle::BOLD);
 
     while parsed_intent != INTENT_DONE {
         // render a surface, and convert it to a texture bound to the canvas
         let surface = font
-            .render(&as_string(parsed_duration)[..]) // As a string slice
+            .render(&as_string(parsed_duration, false)[..]) // As a string slice
             .blended(Color::RGBA(0, 0, 0, 255))
             .map_err(|e| e.to_string())?;
-        let texture = texture_creator
+        let texture = screen.texture_creator
             .create_texture_from_surface(&surface)
             .map_err(|e| e.to_string())?;
 
-        canvas.set_draw_color(Color::RGBA(128, 230, 209, 1));
-        canvas.clear();
+        screen.canvas.set_draw_color(Color::RGBA(128, 230, 209, 1));
+        screen.canvas.clear();
 
         let TextureQuery { width, height, .. } = texture.query();
 
@@ -64,61 +71,83 @@ pub fn get_time<T: crate::main_loop::sdl2::render::RenderTarget, U>(res_width :
         // If the example text is too big for the screen, downscale it (and center it regardless)
         let padding = 5;
         let target = get_centered_rect(
-            res_width,
-            res_height,
+            screen.res_width,
+            screen.res_height,
             width,
             height,
-            res_width - padding,
-            res_height - padding,
+            screen.res_width - padding,
+            screen.res_height - padding,
         );
 
-        canvas.copy(&texture, None, Some(target))?;
-        canvas.present();
+        screen.canvas.copy(&texture, None, Some(target))?;
+        drop(texture);
+        screen.present();
+        screen.ring_due()?;
+        if screen.ringer.quit.get() {
+            return Ok(Duration::ZERO);
+        }
 
-        // Wait while there isn't a new intent for auxiliary functions
-        let mut new = cvar.wait_while(lock.lock().unwrap(), |new| !*new).unwrap();
+        // Wait for a short time for a new intent, redrawing the screen otherwise (so that the
+        // timers and alarms due meanwhile go off)
+        let (mut new, _) = cvar.wait_timeout_while(lock.lock().unwrap(), Duration::from_millis(200), |new| !*new).unwrap();
+        if ! *new {
+            continue;
+        }
         *new = false;
+        drop(new);
+
+        let parsed_args = {
+            let state = state.lock().unwrap();
+            parsed_intent = state.current_intent.clone();
+            state.current_args.clone()
+        };
 
-        parsed_intent = state.lock().unwrap().current_intent.clone();
+        if parsed_intent == CHRONOMETER_STATE {
+            if let Some(duration) = parse_duration(&parsed_args) {
+                return Ok(duration);
+            }
+        }
 
-        parsed_duration = change_duration(parsed_duration, &parsed_intent);
+        parsed_duration = change_duration(parsed_duration, &parsed_intent, steps);
     }   
 
     Ok(parsed_duration)
 }
 
 
-/// Hijacks and SDL context and displays a chronometer for the given amount of time provided
-pub fn display_chronometer<T: crate::main_loop::sdl2::render::RenderTarget, U>( res_width : u32, res_height : u32,
-                                                                                canvas : &mut Canvas<T>, 
-                                                                                ttf_context : &sdl2::ttf::Sdl2TtfContext, 
-                                                                                texture_creator : &TextureCreator<U>, 
+/// Hijacks and SDL context and displays a chronometer for the given amount of time provided,
+/// returning once it reaches zero. The time left is told by the given clock, and redrawn every 100
+/// milliseconds of it.
+#[cfg(feature = "text")]
+pub fn display_chronometer<T: crate::main_loop::sdl2::render::RenderTarget, U>( screen : &mut Screen<T, U>,
                                                                                 time : Duration ) -> Result<(), String> {
-    let mut remaining = time;
+    let clock = screen.clock;
+    let ends_at = clock.now() + time;
     let limit = Duration::new(0,0);
 
     loop { 
-        remaining = remaining.saturating_sub(Duration::from_millis(100));
+        let remaining = ends_at.saturating_duration_since(clock.now());
         
         if remaining == limit { // Stop the chronometer
             break;
         };
 
         // Load the font
-        let mut font = ttf_context.load_font("assets/font.ttf", 128)?;
+        let ttf_context = screen.ttf_context;
+        let mut font = ttf_context.load_font(screen.font_path, 128)?;
         font.set_style(sdl2::ttf::FontStyle::BOLD);
 
         // render a surface, and convert it to a texture bound to the canvas
         let surface = font
-            .render(&as_string(remaining)[..]) // As a string slice
+            .render(&as_string(remaining, false)[..]) // As a string slice
             .blended(Color::RGBA(0, 0, 0, 255))
             .map_err(|e| e.to_string())?;
-        let texture = texture_creator
+        let texture = screen.texture_creator
             .create_texture_from_surface(&surface)
             .map_err(|e| e.to_string())?;
 
-        canvas.set_draw_color(Color::RGBA(128, 230, 209, 1));
-        canvas.clear();
+        screen.canvas.set_draw_color(Color::RGBA(128, 230, 209, 1));
+        screen.canvas.clear();
 
         let TextureQuery { width, height, .. } = texture.query();
 
@@ -126,36 +155,34 @@ pub fn display_chronometer<T: crate::main_loop::sdl2::render::RenderTarget, U>(
         // If the example text is too big for the screen, downscale it (and center it regardless)
         let padding = 5;
         let target = get_centered_rect(
-            res_width,
-            res_height,
+            screen.res_width,
+            screen.res_height,
             width,
             height,
-            res_width - padding,
-            res_height - padding,
+            screen.res_width - padding,
+            screen.res_height - padding,
         );
 
-        canvas.copy(&texture, None, Some(target))?;
-        canvas.present();
+        screen.canvas.copy(&texture, None, Some(target))?;
+        drop(texture);
+        screen.present();
+        screen.ring_due()?;
+        if screen.ringer.quit.get() {
+            break;
+        }
 
         // Sleep for a short time, since we don't want to the poor raspberry to catch in flames
-        ::std::thread::sleep(Duration::from_millis(100));
+        clock.sleep(Duration::from_millis(100));
     } 
 
-    // Display and fire up the alarm sound
-    canvas.clear();        
-    let image = Path::new(ALARM_FACE);
-    let texture = texture_creator.load_texture(image)?;
-    canvas.copy(&texture, None, None)?;
-    canvas.present();
-
-    play_sound_blocking(ALARM_SOUND);
-   
     Ok(())
 }
 
 
-// Return a Duration as a hh:mm:ss String
-fn as_string(dur : Duration) -> String {
+
+/// Return a Duration as a hh:mm:ss String, or as a hh:mm:ss.cc one if the hundredths
+/// of a second are requested
+pub fn as_string(dur : Duration, hundredths : bool) -> String {
     let minutes = dur.as_secs() / 60;
     let seconds = dur.as_secs() % 60;
 
@@ -163,23 +190,54 @@ fn as_string(dur : Duration) -> String {
     let minutes = minutes % 60;
     
     // right-aligned argument with a padding of 0's (09,08...)
-    format!("{:0>2}:{:0>2}:{:0>2}",hours,minutes,seconds).to_owned() 
+    if hundredths {
+        format!("{:0>2}:{:0>2}:{:0>2}.{:0>2}",hours,minutes,seconds,dur.subsec_millis() / 10)
+    } else {
+        format!("{:0>2}:{:0>2}:{:0>2}",hours,minutes,seconds)
+    }
 }
 
 
-// Return the provided duration modified by the amount dictated by an intent
-fn change_duration(dur : Duration, intent : &str) -> Duration {
-    let dur_temp = match intent {   
-        INTENT_5_MORE  => dur.saturating_add(Duration::from_secs(60*5)),
-        INTENT_10_MORE => dur.saturating_add(Duration::from_secs(60*10)),
-        INTENT_20_MORE => dur.saturating_add(Duration::from_secs(60*20)),
-        INTENT_5_LESS  => dur.saturating_sub(Duration::from_secs(60*5)),
-        INTENT_10_LESS => dur.saturating_sub(Duration::from_secs(60*10)),
-        INTENT_20_LESS => dur.saturating_sub(Duration::from_secs(60*20)),
-        _ => dur, // No matching intent found
-    };
+// Return the provided duration modified by the amount of seconds dictated by an intent's step
+#[cfg(feature = "text")]
+fn change_duration(dur : Duration, intent : &str, steps : &HashMap<String, i64>) -> Duration {
+    match steps.get(intent) {   
+        Some(step) if *step >= 0 => dur.saturating_add(Duration::from_secs(*step as u64)),
+        Some(step) => dur.saturating_sub(Duration::from_secs(step.unsigned_abs())),
+        None => dur, // No matching intent found
+    }
+}
 
-    dur_temp
+/// Render a line of text in black, centered inside the given area of the canvas (and scaled
+/// down if it doesn't fit)
+#[cfg(feature = "text")]
+pub fn draw_text<T: crate::main_loop::sdl2::render::RenderTarget, U>(canvas : &mut Canvas<T>, 
+                                                                     texture_creator : &TextureCreator<U>, 
+                                                                     font : &sdl2::ttf::Font,
+                                                                     text : &str, area : Rect) -> Result<(), String> {
+    // render a surface, and convert it to a texture bound to the canvas
+    let surface = font
+        .render(text)
+        .blended(Color::RGBA(0, 0, 0, 255))
+        .map_err(|e| e.to_string())?;
+    let texture = texture_creator
+        .create_texture_from_surface(&surface)
+        .map_err(|e| e.to_string())?;
+
+    let TextureQuery { width, height, .. } = texture.query();
+
+    let padding = 5;
+    let mut target = get_centered_rect(
+        area.width(),
+        area.height(),
+        width,
+        height,
+        area.width().saturating_sub(padding),
+        area.height().saturating_sub(padding),
+    );
+    target.offset(area.x(), area.y());
+
+    canvas.copy(&texture, None, Some(target))
 }
 
 // https://github.com/Rust-SDL2/rust-sdl2/blob/master/examples/ttf-demo.rs
@@ -192,7 +250,7 @@ macro_rules! rect(
 
 // https://github.com/Rust-SDL2/rust-sdl2/blob/master/examples/ttf-demo.rs
 // Scale fonts to a reasonable size when they're too big (though they might look less smooth)
-fn get_centered_rect(res_width : u32, res_height : u32, rect_width: u32, rect_height: u32, cons_width: u32, cons_height: u32) -> Rect {
+pub fn get_centered_rect(res_width : u32, res_height : u32, rect_width: u32, rect_height: u32, cons_width: u32, cons_height: u32) -> Rect {
     let wr = rect_width as f32 / cons_width as f32;
     let hr = rect_height as f32 / cons_height as f32;
 
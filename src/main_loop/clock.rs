@@ -0,0 +1,173 @@
+extern crate sdl2;
+extern crate chrono;
+extern crate chrono_tz;
+
+// SDL libs
+use sdl2::render::Canvas;
+use sdl2::pixels::Color;
+use sdl2::rect::{Point, Rect};
+
+use chrono::{NaiveDateTime, Timelike};
+use chrono_tz::Tz;
+
+use std::f64::consts::PI;
+use std::time::Duration;
+use std::sync::{Mutex, Arc, Condvar};
+
+use crate::config::Settings;
+use super::StateMutex;
+use super::{draw_text, Screen};
+
+const INTENT_DONE : &str = "done";
+const ARG_ANALOG : &str = "analog"; // clock analog, shows the analog face regardless of the settings
+const ARG_DIGITAL : &str = "digital"; // clock digital, the other way around
+
+/// How the clock is shown, read from the settings
+pub struct ClockSettings {
+    pub twelve_hour : bool, // clock_format, either 24h (the default) or 12h
+    pub analog : bool, // clock_analog, whether to draw an analog face (yes/no)
+    pub zones : Vec<Tz>, // clock_zones, IANA time zones shown after the local time, one per line
+    pub rotation : Duration, // clock_rotation, how long each time zone is shown
+}
+
+impl ClockSettings {
+    pub fn from_settings(settings : &Settings) -> Result<ClockSettings, String> {
+        let zones = settings.get_list("clock_zones").iter()
+                            .map(|zone| zone.parse::<Tz>().map_err(|e| format!("Invalid time zone in the clock_zones setting: {}", e)))
+                            .collect::<Result<Vec<Tz>, String>>()?;
+
+        Ok(ClockSettings {
+            twelve_hour : settings.get_or("clock_format", "24h") == "12h",
+            analog : settings.get_or("clock_analog", "no") == "yes",
+            zones,
+            rotation : settings.parse_duration_or("clock_rotation", Duration::from_secs(5))?,
+        })
+    }
+}
+
+/// Hijacks an SDL context and displays the date and time (as told by the given clock) until
+/// INTENT_DONE is received.
+///
+/// If there are time zones in the settings, it rotates between the local time and each one
+/// of them (a world clock). An analog face is drawn above the date and time if it's enabled
+/// in the settings or if the intent's arguments ask for it.
+pub fn show_clock<T: sdl2::render::RenderTarget, U>(screen : &mut Screen<T, U>,
+                                                    settings : &ClockSettings,
+                                                    args : &str,
+                                                    state : StateMutex,
+                                                    new_intent_available : Arc<(Mutex<bool>,Condvar)>)
+                                                    -> Result<(), String> {
+    let (lock, cvar) = &*new_intent_available;
+
+    // Load the fonts
+    let ttf_context = screen.ttf_context;
+    let mut font = ttf_context.load_font(screen.font_path, 128)?;
+    font.set_style(sdl2::ttf::FontStyle::BOLD);
+    let mut small_font = ttf_context.load_font(screen.font_path, 40)?;
+    small_font.set_style(sdl2::ttf::FontStyle::BOLD);
+
+    let analog = match args {
+        ARG_ANALOG => true,
+        ARG_DIGITAL => false,
+        _ => settings.analog,
+    };
+
+    let (res_width, res_height, clock) = (screen.res_width, screen.res_height, screen.clock);
+    let time_format = if settings.twelve_hour { "%I:%M:%S %p" } else { "%H:%M:%S" };
+    let started = clock.now();
+
+    loop {
+        // Local time first, then each time zone
+        let view = ((clock.now() - started).as_millis() / settings.rotation.as_millis().max(1)) as usize % (settings.zones.len() + 1);
+
+        let (place, now) = match view {
+            0 => ("".to_owned(), clock.local_now().naive_local()),
+            _ => {
+                let zone = settings.zones[view - 1];
+                (zone_name(&zone), clock.local_now().with_timezone(&zone).naive_local())
+            }
+        };
+
+        let (canvas, texture_creator) = (&mut screen.canvas, &screen.texture_creator);
+        canvas.set_draw_color(Color::RGBA(128, 230, 209, 1));
+        canvas.clear();
+
+        // The place goes on the top sixth of the screen, the time (or the analog face along
+        // with it) below it, and the date at the bottom sixth
+        let row = res_height / 6;
+
+        if analog {
+            draw_analog(canvas, res_width / 2, row * 5 / 2, (row * 3 / 2).min(res_width / 2) as i32 - 5, now)?;
+            draw_text(canvas, texture_creator, &small_font, &now.format(time_format).to_string(), Rect::new(0, row as i32 * 4, res_width, row))?;
+        } else {
+            draw_text(canvas, texture_creator, &font, &now.format(time_format).to_string(), Rect::new(0, row as i32, res_width, row * 4))?;
+        }
+
+        if ! place.is_empty() {
+            draw_text(canvas, texture_creator, &small_font, &place, Rect::new(0, 0, res_width, row))?;
+        }
+        draw_text(canvas, texture_creator, &small_font, &now.format("%A, %-d %B %Y").to_string(), Rect::new(0, row as i32 * 5, res_width, row))?;
+
+        screen.present();
+
+        screen.ring_due()?;
+        if screen.ringer.quit.get() {
+            return Ok(());
+        }
+
+        // Wait for a short time for a new intent, refreshing the time otherwise
+        let (mut new, _) = cvar.wait_timeout_while(lock.lock().unwrap(), Duration::from_millis(200), |new| !*new).unwrap();
+
+        if *new {
+            *new = false;
+            drop(new);
+
+            if state.lock().unwrap().current_intent == INTENT_DONE {
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Name of the city of a time zone ("America/New_York" is shown as "New York")
+fn zone_name(zone : &Tz) -> String {
+    zone.name().rsplit('/').next().unwrap_or_default().replace('_', " ")
+}
+
+// Draw an analog clock face with the given center and radius, using lines only
+fn draw_analog<T: sdl2::render::RenderTarget>(canvas : &mut Canvas<T>, cx : u32, cy : u32, radius : i32, now : NaiveDateTime) -> Result<(), String> {
+    let center = Point::new(cx as i32, cy as i32);
+
+    // Point at the given fraction of a turn (clockwise, starting at 12) and fraction of the radius
+    let at = |turn : f64, length : f64| -> Point {
+        let angle = turn * 2.0 * PI - PI / 2.0;
+        let length = length * radius as f64;
+        center.offset((angle.cos() * length) as i32, (angle.sin() * length) as i32)
+    };
+
+    canvas.set_draw_color(Color::RGBA(0, 0, 0, 255));
+
+    // Outline
+    let outline : Vec<Point> = (0..=120).map(|i| at(i as f64 / 120.0, 1.0)).collect();
+    canvas.draw_lines(&outline[..])?;
+
+    // Hour marks
+    for hour in 0..12 {
+        canvas.draw_line(at(hour as f64 / 12.0, 0.85), at(hour as f64 / 12.0, 1.0))?;
+    }
+
+    // Hands, thickened by drawing them a few times side by side
+    let seconds = now.second() as f64;
+    let minutes = now.minute() as f64 + seconds / 60.0;
+    let hours = (now.hour() % 12) as f64 + minutes / 60.0;
+
+    for (turn, length, thickness) in [(hours / 12.0, 0.5, 3), (minutes / 60.0, 0.8, 2), (seconds / 60.0, 0.9, 1)].iter() {
+        for offset in 0..*thickness {
+            canvas.draw_line(center.offset(offset, offset), at(*turn, *length).offset(offset, offset))?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,271 @@
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::Settings;
+
+/// Version of the protocol spoken on the intent socket, announced along with BMO
+pub const PROTOCOL_VERSION : u32 = 1;
+
+/// UDP port BMO listens for discovery queries on, and sends its beacons to, unless the
+/// discovery_port setting says otherwise
+pub const DISCOVERY_PORT : u16 = 2301;
+
+// What clients send to find BMO
+const QUERY : &str = "bmos?";
+
+/// What BMO tells about itself: its name, the version of its protocol and the TCP port it
+/// receives intents on
+#[derive(Clone, Debug, PartialEq)]
+pub struct Announcement {
+    pub name : String,
+    pub version : u32,
+    pub port : u16,
+}
+
+impl Announcement {
+    /// Message carrying it, such as "bmos 1 2300 BMO" (the name goes last, so that it can have
+    /// spaces)
+    pub fn message(&self) -> String {
+        format!("bmos {} {} {}", self.version, self.port, self.name)
+    }
+
+    pub fn parse(message : &str) -> Option<Announcement> {
+        let mut parts = message.trim().splitn(4, ' ');
+
+        if parts.next() != Some("bmos") {
+            return None;
+        }
+        let version = parts.next()?.parse().ok()?;
+        let port = parts.next()?.parse().ok()?;
+        let name = parts.next()?.to_owned();
+
+        Some(Announcement { name, version, port })
+    }
+}
+
+/// Settings of the discovery service, or None if it's off:
+/// - **discovery** (off by default) : on lets BMO announce itself.
+/// - **discovery_name** (BMO by default) : Name it announces itself with.
+/// - **discovery_port** (2301 by default) : UDP port of the queries and beacons.
+/// - **discovery_interval** (30s by default) : How often the beacon is broadcast, or 0 to
+///   only answer queries.
+pub struct DiscoverySettings {
+    pub name : String,
+    pub port : u16,
+    pub interval : Option<Duration>,
+}
+
+impl DiscoverySettings {
+    pub fn from_settings(settings : &Settings) -> Result<Option<DiscoverySettings>, String> {
+        match settings.get_or("discovery", "off").as_str() {
+            "on" => (),
+            "off" => return Ok(None),
+            other => return Err(format!("Couldn't parse the discovery setting, please ensure that it's on or off: {}", other)),
+        }
+
+        let port = settings.get_or("discovery_port", &DISCOVERY_PORT.to_string());
+        let port = port.parse::<u16>().map_err(|_| format!("Couldn't parse the discovery_port setting, please ensure that it's a port number: {}", port))?;
+        let interval = settings.parse_duration_or("discovery_interval", Duration::from_secs(30))?;
+
+        Ok(Some(DiscoverySettings { name : settings.get_or("discovery_name", "BMO"),
+                                    port,
+                                    interval : Some(interval).filter(|interval| ! interval.is_zero()) }))
+    }
+}
+
+/// Answers the discovery queries, and broadcasts the announcement every so often so that
+/// clients can find BMO without even asking
+pub struct Beacon {
+    socket : UdpSocket,
+    sender : UdpSocket, // Bound to the address announced, which clients will connect to
+    message : String,
+    beacon_to : SocketAddr,
+    interval : Option<Duration>,
+}
+
+impl Beacon {
+    /// Listen for queries on the given address, answering them and sending the beacons to
+    /// beacon_to (which is usually the broadcast address) from the from address, and through
+    /// its interface only unless it's unspecified
+    pub fn bind(address : SocketAddr, from : IpAddr, announcement : &Announcement, beacon_to : SocketAddr, interval : Option<Duration>) -> std::io::Result<Beacon> {
+        let socket = UdpSocket::bind(address)?;
+        let sender = UdpSocket::bind((from, 0))?;
+        sender.set_broadcast(true)?;
+
+        Ok(Beacon { socket, sender, message : announcement.message(), beacon_to, interval })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Answer queries and send beacons, forever
+    pub fn run(self) {
+        let mut next_beacon = Instant::now();
+        let mut buffer = [0; 64];
+
+        loop {
+            if let Some(interval) = self.interval {
+                if Instant::now() >= next_beacon {
+                    if let Err(e) = self.sender.send_to(self.message.as_bytes(), self.beacon_to) {
+                        eprintln!("Couldn't send the discovery beacon to {}: {}", self.beacon_to, e);
+                    }
+                    next_beacon += interval;
+                }
+            }
+
+            let timeout = self.interval.map_or(Duration::from_secs(1), |_| next_beacon.saturating_duration_since(Instant::now()));
+            let _ = self.socket.set_read_timeout(Some(timeout.max(Duration::from_millis(1))));
+
+            match self.socket.recv_from(&mut buffer) {
+                Ok((length, from)) if std::str::from_utf8(&buffer[..length]).is_ok_and(|query| query.trim() == QUERY) => {
+                    if let Err(e) = self.sender.send_to(self.message.as_bytes(), from) {
+                        eprintln!("Couldn't answer the discovery query from {}: {}", from, e);
+                    }
+                },
+                Ok(_) => (), // Beacons (including its own) and anything else
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => (),
+                Err(e) => {
+                    eprintln!("Couldn't receive discovery queries: {}", e);
+                    thread::sleep(Duration::from_secs(1));
+                },
+            }
+        }
+    }
+}
+
+/// Address to announce BMO from, when it receives intents on the given one: the same, so
+/// that clients connect to where it really listens, or the error saying why BMO can't be found
+/// from the network through it (loopback addresses, and IPv6 ones since discovery is broadcast
+/// over IPv4)
+pub fn announced_from(tcp_address : IpAddr) -> Result<Ipv4Addr, String> {
+    match tcp_address {
+        address if address.is_loopback() => Err(format!("{} is only reachable from this machine", address)),
+        IpAddr::V4(address) => Ok(address),
+        IpAddr::V6(address) if address.is_unspecified() => Ok(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(address) => Err(format!("{} is an IPv6 address, and discovery only works over IPv4", address)),
+    }
+}
+
+/// Start announcing BMO, receiving intents on the given TCP address and port, in the
+/// background (see announced_from). Nothing is announced when BMO can't be reached from the
+/// network anyway.
+pub fn spawn(settings : DiscoverySettings, tcp_address : &str, tcp_port : u16) -> Result<(), String> {
+    let tcp_address = (tcp_address, tcp_port).to_socket_addrs().ok().and_then(|mut addresses| addresses.next())
+                                             .ok_or(format!("Couldn't resolve {}, which BMO listens on", tcp_address))?;
+    let from = match announced_from(tcp_address.ip()) {
+        Ok(from) => from,
+        Err(reason) => {
+            println!("Not announcing BMO on the network, as {}", reason);
+            return Ok(());
+        },
+    };
+    if ! (from.is_unspecified() || from.is_private() || from.is_link_local()) {
+        eprintln!("Warning: announcing BMO from {}, which isn't a local network address", from);
+    }
+
+    let DiscoverySettings { name, port, interval } = settings;
+    let announcement = Announcement { name, version : PROTOCOL_VERSION, port : tcp_port };
+    let beacon = Beacon::bind((Ipv4Addr::UNSPECIFIED, port).into(), from.into(), &announcement, (Ipv4Addr::BROADCAST, port).into(), interval)
+                       .map_err(|e| format!("Couldn't listen for discovery queries on port {}: {}", port, e))?;
+
+    if from.is_unspecified() {
+        println!("Announcing \"{}\" on UDP port {}", announcement.name, port);
+    } else {
+        println!("Announcing \"{}\" from {} on UDP port {}", announcement.name, from, port);
+    }
+    thread::spawn(move || beacon.run());
+
+    Ok(())
+}
+
+/// Ask for every BMO around, sending a query to the given address (such as the broadcast
+/// address and DISCOVERY_PORT) and waiting for the answers for as long as the timeout. Returns
+/// where each one receives intents, along with what it announced.
+pub fn discover(target : SocketAddr, timeout : Duration) -> std::io::Result<Vec<(SocketAddr, Announcement)>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_broadcast(true)?;
+    socket.send_to(QUERY.as_bytes(), target)?;
+
+    let deadline = Instant::now() + timeout;
+    let mut found : Vec<(SocketAddr, Announcement)> = Vec::new();
+    let mut buffer = [0; 512];
+
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()).filter(|remaining| ! remaining.is_zero()) {
+        socket.set_read_timeout(Some(remaining))?;
+
+        match socket.recv_from(&mut buffer) {
+            Ok((length, from)) => {
+                let Some(announcement) = std::str::from_utf8(&buffer[..length]).ok().and_then(Announcement::parse) else { continue };
+                let address = SocketAddr::new(from.ip(), announcement.port);
+
+                if ! found.iter().any(|(known, _)| *known == address) {
+                    found.push((address, announcement));
+                }
+            },
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => break,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn announcements_are_parsed() {
+        let announcement = Announcement { name : "BMO in the kitchen".to_owned(), version : PROTOCOL_VERSION, port : 2300 };
+
+        assert_eq!(announcement.message(), "bmos 1 2300 BMO in the kitchen");
+        assert_eq!(Announcement::parse(&announcement.message()), Some(announcement));
+        assert_eq!(Announcement::parse("bmos?"), None);
+        assert_eq!(Announcement::parse("bmos 1 99999 BMO"), None);
+        assert_eq!(Announcement::parse("hello 1 2300 BMO"), None);
+    }
+
+    #[test]
+    fn bmo_is_found_on_loopback() {
+        let announcement = Announcement { name : "BMO".to_owned(), version : PROTOCOL_VERSION, port : 2300 };
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        let beacon = Beacon::bind("127.0.0.1:0".parse().unwrap(), Ipv4Addr::LOCALHOST.into(), &announcement, client.local_addr().unwrap(), Some(Duration::from_millis(50))).unwrap();
+        let address = beacon.local_addr().unwrap();
+        thread::spawn(move || beacon.run());
+
+        // Beacons arrive without asking...
+        let mut buffer = [0; 64];
+        let (length, _) = client.recv_from(&mut buffer).unwrap();
+        assert_eq!(Announcement::parse(std::str::from_utf8(&buffer[..length]).unwrap()), Some(announcement.clone()));
+
+        // ...and queries are answered
+        let found = discover(address, Duration::from_millis(500)).unwrap();
+        assert_eq!(found, vec![("127.0.0.1:2300".parse().unwrap(), announcement)]);
+    }
+
+    #[test]
+    fn bmo_is_announced_from_where_it_listens() {
+        assert_eq!(announced_from("192.168.1.15".parse().unwrap()), Ok(Ipv4Addr::new(192, 168, 1, 15)));
+        assert_eq!(announced_from("0.0.0.0".parse().unwrap()), Ok(Ipv4Addr::UNSPECIFIED));
+        assert_eq!(announced_from("::".parse().unwrap()), Ok(Ipv4Addr::UNSPECIFIED));
+        assert!(announced_from("127.0.0.1".parse().unwrap()).is_err());
+        assert!(announced_from("::1".parse().unwrap()).is_err());
+        assert!(announced_from("fe80::1".parse().unwrap()).is_err());
+    }
+
+    #[test]
+    fn discovery_is_opt_in() {
+        assert!(DiscoverySettings::from_settings(&Settings::default()).unwrap().is_none());
+
+        let parse = |contents : &str| DiscoverySettings::from_settings(&Settings::from_reader(contents.as_bytes(), "settings.txt").unwrap());
+
+        let settings = parse("[discovery]\non\n").unwrap().unwrap();
+        assert_eq!((settings.name.as_str(), settings.port, settings.interval), ("BMO", DISCOVERY_PORT, Some(Duration::from_secs(30))));
+        assert!(parse("[discovery]\nyes\n").is_err());
+    }
+}
@@ -0,0 +1,50 @@
+extern crate sdl2;
+
+// SDL libs
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::Canvas;
+use sdl2::surface::Surface;
+use sdl2::video::Window;
+
+use crate::config::Settings;
+
+/// Where BMO is drawn. Everything is drawn on a Canvas, whichever the backend is, so it looks
+/// the same either way.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    Window, // An OpenGL window, the default
+    Offscreen, // A software surface in memory, for running without a display (over SSH, in CI...)
+}
+
+impl Backend {
+    /// Backend chosen in the display setting, either window (the default) or offscreen
+    pub fn from_settings(settings : &Settings) -> Backend {
+        match settings.get_or("display", "window").as_str() {
+            "offscreen" => Backend::Offscreen,
+            _ => Backend::Window,
+        }
+    }
+}
+
+/// Canvas of a new window with the given resolution
+pub fn window_canvas(sdl_context : &sdl2::Sdl, res_width : u32, res_height : u32) -> Result<Canvas<Window>, String> {
+    let video_subsystem = sdl_context.video()?;
+    let window = video_subsystem
+        .window("rust-sdl2 demo: Video", res_width, res_height)
+        .position_centered()
+        .opengl()
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    window
+        .into_canvas()
+        //.software()
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+/// Canvas of a new surface in memory with the given resolution, which doesn't need a display
+/// (nor the SDL video subsystem)
+pub fn offscreen_canvas(res_width : u32, res_height : u32) -> Result<Canvas<Surface<'static>>, String> {
+    Surface::new(res_width, res_height, PixelFormatEnum::RGB888)?.into_canvas()
+}
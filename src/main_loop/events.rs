@@ -0,0 +1,194 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::time::{Duration, Instant};
+
+use super::ClockHandle;
+use super::audio_backend::{AudioBackend, AudioHandle, Clip};
+
+/// Events kept for each subscriber until it receives them. Subscribers falling further
+/// behind are disconnected, rather than held in memory forever.
+pub const SUBSCRIBER_BACKLOG : usize = 256;
+
+/// Something BMO did
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    IntentReceived { intent : String, args : String }, // An intent arrived, which will be shown next
+    CommandHandled { intent : String, args : String, reply : String }, // A command arrived, and was replied to right away
+    IntentShown { intent : String }, // The main loop switched to showing an intent
+    ModeEntered { mode : String }, // A preset intent (chronometer, stopwatch, clock, weather) took over the screen
+    ModeExited { mode : String }, // ...and gave it back
+    TimerFired { name : String },
+    AlarmFired { label : String },
+    AudioStarted { clip : String },
+    AudioFinished { clip : String, error : Option<String> },
+    VolumeChanged { volume : f32 },
+    Reloaded { intents : usize }, // The configuration files were reloaded
+    Stopped, // The main loop ended
+}
+
+impl Event {
+    /// Name of the kind of event, such as "intent_shown"
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::IntentReceived { .. } => "intent_received",
+            Event::CommandHandled { .. } => "command_handled",
+            Event::IntentShown { .. } => "intent_shown",
+            Event::ModeEntered { .. } => "mode_entered",
+            Event::ModeExited { .. } => "mode_exited",
+            Event::TimerFired { .. } => "timer_fired",
+            Event::AlarmFired { .. } => "alarm_fired",
+            Event::AudioStarted { .. } => "audio_started",
+            Event::AudioFinished { .. } => "audio_finished",
+            Event::VolumeChanged { .. } => "volume_changed",
+            Event::Reloaded { .. } => "reloaded",
+            Event::Stopped => "stopped",
+        }
+    }
+}
+
+/// An event, along with when it happened, since the events started being published
+#[derive(Clone, Debug)]
+pub struct Stamped {
+    pub at : Duration,
+    pub event : Event,
+}
+
+/// Hands every event published to whoever subscribed to them (the HTTP API's event stream,
+/// tests waiting for BMO to do something...). Subscribers which went away, or which fell
+/// SUBSCRIBER_BACKLOG events behind, are forgotten on the next event: their receiver then ends.
+pub struct Events {
+    clock : ClockHandle,
+    started : Instant,
+    subscribers : Mutex<Vec<SyncSender<Stamped>>>,
+}
+
+impl Events {
+    pub fn new(clock : ClockHandle) -> Events {
+        Events { started : clock.now(), clock, subscribers : Mutex::new(Vec::new()) }
+    }
+
+    pub fn publish(&self, event : Event) {
+        let stamped = Stamped { at : self.clock.now() - self.started, event };
+
+        self.subscribers.lock().unwrap().retain(|subscriber| match subscriber.try_send(stamped.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                eprintln!("Disconnected an event subscriber which fell {} events behind", SUBSCRIBER_BACKLOG);
+                false
+            },
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+
+    /// Receive every event published from now on, for as long as they're kept up with
+    pub fn subscribe(&self) -> Receiver<Stamped> {
+        let (sender, receiver) = sync_channel(SUBSCRIBER_BACKLOG);
+        self.subscribers.lock().unwrap().push(sender);
+
+        receiver
+    }
+}
+
+// Thread-safe Events instance
+pub type EventsHandle = Arc<Events>;
+
+/// Audio backend publishing when the clips start and finish playing, and when the volume
+/// changes, playing them with another one
+pub struct ObservedAudio {
+    audio : AudioHandle,
+    events : EventsHandle,
+}
+
+impl ObservedAudio {
+    pub fn new(audio : AudioHandle, events : EventsHandle) -> ObservedAudio {
+        ObservedAudio { audio, events }
+    }
+
+    fn observe(&self, clip : &Clip, play : impl FnOnce() -> Result<(), String>) -> Result<(), String> {
+        self.events.publish(Event::AudioStarted { clip : clip.id.clone() });
+        let played = play();
+        self.events.publish(Event::AudioFinished { clip : clip.id.clone(), error : played.as_ref().err().cloned() });
+
+        played
+    }
+}
+
+impl AudioBackend for ObservedAudio {
+    fn play(&self, clip : &Clip) -> Result<(), String> {
+        self.observe(clip, || self.audio.play(clip))
+    }
+
+    fn play_looping(&self, clip : &Clip, ramp : Duration, stop : &AtomicBool) -> Result<(), String> {
+        self.observe(clip, || self.audio.play_looping(clip, ramp, stop))
+    }
+
+    fn volume(&self) -> f32 {
+        self.audio.volume()
+    }
+
+    fn set_volume(&self, volume : f32) {
+        self.audio.set_volume(volume);
+        self.events.publish(Event::VolumeChanged { volume : self.audio.volume() });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::main_loop::{ManualClock, NullAudio};
+
+    #[test]
+    fn subscribers_get_every_event_from_then_on() {
+        let clock = Arc::new(ManualClock::new());
+        let events = Events::new(clock.clone());
+
+        events.publish(Event::Stopped); // Nobody's listening yet
+        let first = events.subscribe();
+        clock.advance(Duration::from_secs(2));
+        events.publish(Event::IntentShown { intent : "hello".to_owned() });
+
+        let second = events.subscribe();
+        drop(first);
+        events.publish(Event::TimerFired { name : "tea".to_owned() });
+
+        let stamped = second.try_recv().unwrap();
+        assert_eq!(stamped.event, Event::TimerFired { name : "tea".to_owned() });
+        assert_eq!(stamped.at, Duration::from_secs(2));
+        assert!(second.try_recv().is_err());
+        assert_eq!(events.subscribers.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn slow_subscribers_are_disconnected() {
+        let events = Events::new(Arc::new(ManualClock::new()));
+        let slow = events.subscribe();
+        let fast = events.subscribe();
+
+        for _ in 0..SUBSCRIBER_BACKLOG {
+            events.publish(Event::Stopped);
+            assert!(fast.try_recv().is_ok());
+        }
+        events.publish(Event::Stopped);
+
+        assert_eq!(slow.try_iter().count(), SUBSCRIBER_BACKLOG);
+        assert!(slow.recv().is_err()); // Ended, rather than waiting forever
+        assert!(fast.try_recv().is_ok());
+    }
+
+    #[test]
+    fn audio_is_observed() {
+        let clock = Arc::new(ManualClock::new());
+        let events = Arc::new(Events::new(clock.clone()));
+        let received = events.subscribe();
+        let audio = ObservedAudio::new(Arc::new(NullAudio::new(clock)), Arc::clone(&events));
+
+        audio.set_volume(0.25);
+        audio.play_looping(&Clip { id : "alarm".to_owned(), data : Vec::new() }, Duration::new(0, 0), &AtomicBool::new(true)).unwrap();
+
+        let received : Vec<Event> = received.try_iter().map(|stamped| stamped.event).collect();
+        assert_eq!(received, vec![Event::VolumeChanged { volume : 0.25 },
+                                  Event::AudioStarted { clip : "alarm".to_owned() },
+                                  Event::AudioFinished { clip : "alarm".to_owned(), error : None }]);
+    }
+}
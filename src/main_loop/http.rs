@@ -0,0 +1,382 @@
+extern crate serde_json;
+extern crate tiny_http;
+extern crate tungstenite;
+
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response};
+use tungstenite::{Message, WebSocket};
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::Role;
+
+use std::io::Read;
+use std::net::SocketAddr;
+use std::thread;
+use std::time::Duration;
+
+use crate::config::Settings;
+use super::access::constant_time_eq;
+use super::intent_receiver::Pipeline;
+use super::limits::Limits;
+use super::events::{Event, Stamped};
+
+const SCREENSHOT_TIMEOUT : Duration = Duration::from_secs(5); // Real time given to the main loop to present a frame
+
+/// Where the HTTP API listens, and the token it asks for, read from the settings:
+/// - **http_address** : Address and port to listen on (such as 0.0.0.0:8080). The API is only
+///   started if it's present.
+/// - **http_token** : Token every request has to carry, as "Authorization: Bearer token" (or
+///   as the access_token query parameter, for browsers opening the event stream). It's required
+///   along with http_address.
+pub struct HttpSettings {
+    pub address : String,
+    pub token : String,
+}
+
+impl HttpSettings {
+    /// Settings of the HTTP API, or None if it isn't enabled
+    pub fn from_settings(settings : &Settings) -> Result<Option<HttpSettings>, String> {
+        match (settings.get("http_address"), settings.get("http_token")) {
+            (None, _) => Ok(None),
+            (Some(_), None) | (Some(_), Some("")) => Err("The http_address setting needs an http_token, so that nobody else can drive BMO".to_owned()),
+            (Some(address), Some(token)) => Ok(Some(HttpSettings { address : address.to_owned(), token : token.to_owned() })),
+        }
+    }
+}
+
+// Reply to a request
+enum Reply {
+    Json(u16, Value),
+    Png(Vec<u8>),
+}
+
+fn error(status : u16, message : &str) -> Reply {
+    Reply::Json(status, json!({ "error" : message }))
+}
+
+/// Start the HTTP API in the background, handing the intents and commands received to the
+/// pipeline. Returns the address it listens on.
+///
+/// Every request needs the token, in its Authorization header or its query, and its body can't
+/// be longer than the intent lines received through TCP (see Limits). The endpoints are:
+/// - **POST /intents/name** : Send an intent, with its arguments (if any) as the body. Commands
+///   (such as "timer" or "alarm") answer with their reply, as they would through TCP.
+/// - **GET /intents** : Intents with faces, and the preset ones available.
+/// - **GET /state** : Intent being shown, the last one received, timers, stopwatch and volume.
+/// - **POST /reload** : Load the configuration files again (see Catalog::reload).
+/// - **GET /volume** and **PUT /volume** : Tell or change the volume, from 0 to 100.
+/// - **GET /screenshot** : PNG of the next frame presented.
+/// - **GET /events** : WebSocket streaming every event BMO publishes from then on (see Event),
+///   one JSON object per message, such as {"event":"intent_shown","at":1500,"intent":"hello"},
+///   where "at" is the milliseconds since BMO started.
+pub fn spawn(settings : HttpSettings, pipeline : Pipeline, limits : Limits) -> Result<SocketAddr, String> {
+    let server = tiny_http::Server::http(&settings.address).map_err(|e| format!("Couldn't listen on {}: {}", settings.address, e))?;
+    let address = server.server_addr().to_ip().ok_or("The HTTP API isn't listening on an IP address")?;
+
+    println!("HTTP API listening on {}", address);
+
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            handle_request(request, &settings.token, &pipeline, &limits);
+        }
+    });
+
+    Ok(address)
+}
+
+fn handle_request(mut request : Request, token : &str, pipeline : &Pipeline, limits : &Limits) {
+    let path = request.url().split('?').next().unwrap_or("").to_owned();
+
+    let reply = if ! authorized(&request, token) {
+        eprintln!("Unauthorized HTTP request from {}: {} {}",
+                  request.remote_addr().map_or("unknown".to_owned(), |peer| peer.to_string()), request.method(), path);
+        error(401, "Unauthorized")
+    } else if *request.method() == Method::Get && path == "/events" {
+        match header(&request, "Sec-WebSocket-Key") {
+            Some(key) => return stream_events(request, &key, pipeline),
+            None => error(400, "The events are streamed through a WebSocket"),
+        }
+    } else {
+        let mut body = String::new();
+
+        // Read one byte past the limit, to tell whether it's over it
+        match request.as_reader().take(limits.max_length as u64 + 1).read_to_string(&mut body) {
+            Ok(length) if length > limits.max_length => {
+                eprintln!("Rejected an HTTP request from {}: body too long",
+                          request.remote_addr().map_or("unknown".to_owned(), |peer| peer.to_string()));
+                error(413, &format!("Body too long (the limit is {} bytes)", limits.max_length))
+            }
+            Ok(_) => route(request.method(), &path, body.trim(), pipeline),
+            Err(e) => error(400, &format!("Couldn't read the body: {}", e)),
+        }
+    };
+
+    let response = match reply {
+        Reply::Json(status, value) => Response::from_string(value.to_string()).with_status_code(status)
+                                                                               .with_header(content_type("application/json")),
+        Reply::Png(data) => Response::from_data(data).with_header(content_type("image/png")),
+    };
+
+    if let Err(e) = request.respond(response) {
+        eprintln!("Couldn't answer an HTTP request: {}", e);
+    }
+}
+
+fn content_type(value : &str) -> Header {
+    Header::from_bytes("Content-Type", value).unwrap()
+}
+
+fn header(request : &Request, name : &'static str) -> Option<String> {
+    request.headers().iter().find(|header| header.field.equiv(name)).map(|header| header.value.as_str().to_owned())
+}
+
+// Does the request carry the token, either in its Authorization header or in its query?
+fn authorized(request : &Request, token : &str) -> bool {
+    let expected = format!("Bearer {}", token);
+    let query = request.url().split_once('?').map_or("", |(_, query)| query);
+
+    request.headers().iter()
+           .filter(|header| header.field.equiv("Authorization"))
+           .any(|header| constant_time_eq(header.value.as_str().as_bytes(), expected.as_bytes())) ||
+    query.split('&')
+         .filter_map(|parameter| parameter.strip_prefix("access_token="))
+         .any(|access_token| constant_time_eq(access_token.as_bytes(), token.as_bytes()))
+}
+
+fn route(method : &Method, path : &str, body : &str, pipeline : &Pipeline) -> Reply {
+    let segments : Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    match (method, segments.as_slice()) {
+        (Method::Post, ["intents", intent]) if ! intent.is_empty() => {
+            match pipeline.handle(intent.to_string(), body.to_owned()) {
+                Ok(Some(reply)) => Reply::Json(200, json!({ "reply" : reply })),
+                Ok(None) => Reply::Json(202, json!({ "intent" : intent, "args" : body })),
+                Err(_) => error(400, &format!("Invalid intent name: {}, only letters, digits, _ and - are allowed", intent)),
+            }
+        }
+        (Method::Get, ["intents"]) => {
+            let catalog = pipeline.catalog.lock().unwrap();
+            Reply::Json(200, json!({ "intents" : catalog.intents(), "presets" : catalog.presets() }))
+        }
+        (Method::Get, ["state"]) => Reply::Json(200, state(pipeline)),
+        (Method::Post, ["reload"]) => {
+            let mut catalog = pipeline.catalog.lock().unwrap();
+            match catalog.reload() {
+                Ok(()) => Reply::Json(200, json!({ "intents" : catalog.intents() })),
+                Err(e) => error(500, &e),
+            }
+        }
+        (Method::Get, ["volume"]) => Reply::Json(200, json!({ "volume" : volume(pipeline) })),
+        (Method::Put, ["volume"]) => match body.parse::<f32>() {
+            Ok(level) if (0.0..=100.0).contains(&level) => {
+                pipeline.audio.set_volume(level / 100.0);
+                Reply::Json(200, json!({ "volume" : volume(pipeline) }))
+            }
+            _ => error(400, &format!("Invalid volume: {}, it goes from 0 to 100", body)),
+        },
+        (Method::Get, ["screenshot"]) => match screenshot(pipeline) {
+            Ok(data) => Reply::Png(data),
+            Err(e) => error(503, &e),
+        },
+        (_, ["intents", _]) | (_, ["intents"]) | (_, ["state"]) | (_, ["reload"]) | (_, ["volume"]) | (_, ["screenshot"]) | (_, ["events"]) => {
+            error(405, &format!("{} isn't allowed on {}", method, path))
+        }
+        _ => error(404, &format!("Nothing found at {}", path)),
+    }
+}
+
+fn volume(pipeline : &Pipeline) -> f32 {
+    (pipeline.audio.volume() * 100.0).round()
+}
+
+// What BMO is up to
+fn state(pipeline : &Pipeline) -> Value {
+    let (shown, received, args) = {
+        let state = pipeline.state.lock().unwrap();
+        (state.shown.clone(), state.current_intent.clone(), state.current_args.clone())
+    };
+
+    let timers : Vec<Value> = pipeline.timers.lock().unwrap().remaining().iter()
+                                      .map(|(name, remaining)| json!({ "name" : name, "remaining" : remaining.as_secs() }))
+                                      .collect();
+
+    json!({
+        "intent" : shown,
+        "received" : { "intent" : received, "args" : args },
+        "timers" : timers,
+        "stopwatch" : pipeline.stopwatch.lock().unwrap().status(),
+        "volume" : volume(pipeline),
+    })
+}
+
+// Switch the connection to a WebSocket, and send every event through it until the client goes away
+fn stream_events(request : Request, key : &str, pipeline : &Pipeline) {
+    let events = pipeline.events.subscribe(); // Before answering, so that nothing is missed after the handshake
+    let peer = request.remote_addr().map_or("unknown".to_owned(), |peer| peer.to_string());
+    let response = Response::empty(101).with_header(Header::from_bytes("Sec-WebSocket-Accept", derive_accept_key(key.as_bytes())).unwrap());
+    let stream = request.upgrade("websocket", response);
+
+    println!("Streaming the events to {}", peer);
+
+    thread::spawn(move || {
+        let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+
+        for stamped in events {
+            if socket.send(Message::Text(event_json(&stamped).to_string())).is_err() {
+                break;
+            }
+        }
+
+        println!("Stopped streaming the events to {}", peer);
+    });
+}
+
+/// An event as sent through the event stream
+pub fn event_json(stamped : &Stamped) -> Value {
+    let mut value = match &stamped.event {
+        Event::IntentReceived { intent, args } => json!({ "intent" : intent, "args" : args }),
+        Event::CommandHandled { intent, args, reply } => json!({ "intent" : intent, "args" : args, "reply" : reply }),
+        Event::IntentShown { intent } => json!({ "intent" : intent }),
+        Event::ModeEntered { mode } | Event::ModeExited { mode } => json!({ "mode" : mode }),
+        Event::TimerFired { name } => json!({ "name" : name }),
+        Event::AlarmFired { label } => json!({ "label" : label }),
+        Event::AudioStarted { clip } => json!({ "clip" : clip }),
+        Event::AudioFinished { clip, error } => json!({ "clip" : clip, "error" : error }),
+        Event::VolumeChanged { volume } => json!({ "volume" : (volume * 100.0).round() }),
+        Event::Reloaded { intents } => json!({ "intents" : intents }),
+        Event::Stopped => json!({}),
+    };
+
+    value["event"] = json!(stamped.event.name());
+    value["at"] = json!(stamped.at.as_millis() as u64);
+    value
+}
+
+// Wait for the next frame to be presented
+fn screenshot(pipeline : &Pipeline) -> Result<Vec<u8>, String> {
+    let frame = pipeline.capture.lock().unwrap().next_frame();
+
+    frame.recv_timeout(SCREENSHOT_TIMEOUT).map_err(|_| "No frame was presented in time".to_owned())?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::main_loop::SystemClock;
+    use std::io::Write;
+    use std::net::TcpStream;
+    use std::sync::Arc;
+
+    const TOKEN : &str = "s3cr3t";
+
+    // Start the API on any free port, with a pipeline of its own
+    fn start() -> (SocketAddr, Pipeline) {
+        let pipeline = Pipeline::standalone(Arc::new(SystemClock));
+        let settings = HttpSettings { address : "127.0.0.1:0".to_owned(), token : TOKEN.to_owned() };
+        let limits = Limits::from_settings(&Settings::default(), Arc::new(SystemClock)).unwrap();
+        (spawn(settings, pipeline.clone(), limits).unwrap(), pipeline)
+    }
+
+    // Send a request, returning the status code and the body of the response
+    fn request(address : SocketAddr, method : &str, path : &str, token : Option<&str>, body : &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        let authorization = token.map_or("".to_owned(), |token| format!("Authorization: Bearer {}\r\n", token));
+        write!(stream, "{} {} HTTP/1.1\r\nHost: bmo\r\nConnection: close\r\n{}Content-Length: {}\r\n\r\n{}",
+               method, path, authorization, body.len(), body).unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        let status = response[9..12].parse().unwrap();
+        let body = response.split_once("\r\n\r\n").map_or("", |(_, body)| body).to_owned();
+        (status, body)
+    }
+
+    #[test]
+    fn requests_need_the_token() {
+        let (address, pipeline) = start();
+
+        assert_eq!(request(address, "POST", "/intents/hello", None, "").0, 401);
+        assert_eq!(request(address, "POST", "/intents/hello", Some("guess"), "").0, 401);
+        assert!(! pipeline.state.lock().unwrap().new_intent);
+    }
+
+    #[test]
+    fn intents_and_commands_go_through_the_pipeline() {
+        let (address, pipeline) = start();
+
+        let (status, body) = request(address, "POST", "/intents/chronometer", Some(TOKEN), "7m30s");
+        assert_eq!(status, 202);
+        assert_eq!(serde_json::from_str::<Value>(&body).unwrap(), json!({ "intent" : "chronometer", "args" : "7m30s" }));
+        assert_eq!(pipeline.state.lock().unwrap().current_intent, "chronometer");
+        assert_eq!(pipeline.state.lock().unwrap().current_args, "7m30s");
+
+        let (status, body) = request(address, "POST", "/intents/timer", Some(TOKEN), "tea 3m");
+        assert_eq!(status, 200);
+        assert_eq!(serde_json::from_str::<Value>(&body).unwrap(), json!({ "reply" : "started tea 00:03:00" }));
+
+        let state : Value = serde_json::from_str(&request(address, "GET", "/state", Some(TOKEN), "").1).unwrap();
+        assert_eq!(state["received"]["intent"], "chronometer");
+        assert_eq!(state["timers"][0]["name"], "tea");
+
+        assert_eq!(request(address, "POST", "/intents/hello%1B%5B2J", Some(TOKEN), "").0, 400);
+        assert_eq!(pipeline.state.lock().unwrap().current_intent, "chronometer");
+    }
+
+    #[test]
+    fn bodies_are_held_to_the_intent_length() {
+        let (address, pipeline) = start();
+
+        assert_eq!(request(address, "POST", "/intents/chronometer", Some(TOKEN), &"1".repeat(1025)).0, 413);
+        assert!(! pipeline.state.lock().unwrap().new_intent);
+
+        assert_eq!(request(address, "POST", "/intents/chronometer", Some(TOKEN), &"1".repeat(1024)).0, 202);
+    }
+
+    #[test]
+    fn volume_can_be_changed() {
+        let (address, pipeline) = start();
+
+        assert_eq!(request(address, "PUT", "/volume", Some(TOKEN), "40"), (200, "{\"volume\":40.0}".to_owned()));
+        assert_eq!(pipeline.audio.volume(), 0.4);
+        assert_eq!(request(address, "PUT", "/volume", Some(TOKEN), "loud").0, 400);
+        assert_eq!(request(address, "GET", "/volume", Some(TOKEN), ""), (200, "{\"volume\":40.0}".to_owned()));
+    }
+
+    #[test]
+    fn intents_are_listed_and_unknown_paths_are_not_found() {
+        let (address, _) = start();
+
+        let (status, body) = request(address, "GET", "/intents", Some(TOKEN), "");
+        assert_eq!(status, 200);
+        assert_eq!(serde_json::from_str::<Value>(&body).unwrap()["intents"], json!(["hello"]));
+
+        assert_eq!(request(address, "POST", "/reload", Some(TOKEN), "").0, 500); // Its folder doesn't exist
+        assert_eq!(request(address, "DELETE", "/state", Some(TOKEN), "").0, 405);
+        assert_eq!(request(address, "GET", "/nothing", Some(TOKEN), "").0, 404);
+    }
+
+    #[test]
+    fn events_are_streamed_through_a_websocket() {
+        let (address, _) = start();
+
+        assert_eq!(request(address, "GET", "/events", Some(TOKEN), "").0, 400); // Not a WebSocket
+
+        let url = format!("ws://{}/events?access_token={}", address, TOKEN);
+        let (mut socket, _) = tungstenite::client(url.as_str(), TcpStream::connect(address).unwrap()).unwrap();
+
+        request(address, "POST", "/intents/hello", Some(TOKEN), "there");
+        request(address, "POST", "/intents/volume", Some(TOKEN), "30");
+
+        let mut next = || serde_json::from_str::<Value>(&socket.read().unwrap().into_text().unwrap()).unwrap();
+        let received = next();
+        assert_eq!((&received["event"], &received["intent"], &received["args"]), (&json!("intent_received"), &json!("hello"), &json!("there")));
+        assert_eq!(next()["event"], "command_handled");
+    }
+
+    #[test]
+    fn events_are_named_and_stamped() {
+        let stamped = Stamped { at : Duration::from_millis(1500), event : Event::IntentShown { intent : "hello".to_owned() } };
+
+        assert_eq!(event_json(&stamped), json!({ "event" : "intent_shown", "at" : 1500, "intent" : "hello" }));
+    }
+}
@@ -1,38 +1,94 @@
-use std::net::{TcpStream, TcpListener};
-use std::io::{BufReader, BufRead};
+#[cfg(feature = "tcp")]
+use std::net::TcpListener;
+#[cfg(any(feature = "tcp", all(feature = "unix", unix)))]
+use std::io::{BufReader, ErrorKind};
+#[cfg(all(feature = "unix", unix))]
+use std::{fs, path::Path};
+#[cfg(all(feature = "unix", unix))]
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+#[cfg(all(feature = "unix", unix))]
+use std::os::unix::net::UnixListener;
 use std::sync::{Mutex, Arc, Condvar};
+#[cfg(any(feature = "tcp", all(feature = "unix", unix)))]
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(any(feature = "tcp", all(feature = "unix", unix)))]
+use std::thread;
 
 use super::StateMutex;
+use super::timers::{self, TimersMutex};
+use super::stopwatch::{self, StopwatchMutex};
+use super::alarms::{self, AlarmsMutex};
+use super::capture::{self, CaptureMutex};
+use super::audio_backend::{self, AudioHandle};
+use super::catalog::{self, CatalogMutex};
+use super::events::{Event, EventsHandle};
+#[cfg(any(feature = "tcp", all(feature = "unix", unix)))]
+use super::access::{Access, Connection, HANDSHAKE_TIMEOUT};
+use super::limits::{Violation, valid_intent_name};
+#[cfg(any(feature = "tcp", all(feature = "unix", unix)))]
+use super::limits::{Limits, Received, Violations, read_line};
+#[cfg(any(feature = "tcp", all(feature = "unix", unix)))]
+use crate::config::Settings;
 
-/// Listen and handle one connection on the given address and port. Whenever 
-/// an intent is received, the current_intent and new_intent variables are updated.
-pub fn listen(addr : String, port : String, state : StateMutex, new_intent_available : Arc<(Mutex<bool>, Condvar)>) -> std::io::Result<()> {
-    
-    let listener = TcpListener::bind(format!("{}:{}",addr, port))?;
+/// Where every intent and command ends up, whichever way it's received (the TCP socket, the
+/// HTTP API...)
+#[derive(Clone)]
+pub struct Pipeline {
+    pub state : StateMutex,
+    pub new_intent_available : Arc<(Mutex<bool>, Condvar)>,
+    pub timers : TimersMutex,
+    pub stopwatch : StopwatchMutex,
+    pub alarms : AlarmsMutex,
+    pub capture : CaptureMutex,
+    pub audio : AudioHandle,
+    pub catalog : CatalogMutex,
+    pub events : EventsHandle,
+}
+
+impl Pipeline {
+    /// Handle a received line: an intent, optionally followed by its arguments, separated
+    /// by whitespace (for example "chronometer 7m30s"). See handle.
+    pub fn handle_line(&self, line : &str) -> Result<Option<String>, Violation> {
+        let (intent, args) = split_intent(line);
 
-    for stream in listener.incoming().take(1) { // Only handle one connection
-        handle_client(stream?, Arc::clone(&state), Arc::clone(&new_intent_available))?;
+        self.handle(intent, args)
     }
 
-    Ok(())
-}
+    /// Handle an intent along with its arguments. Whenever an intent is received, the
+    /// current_intent, current_args and new_intent variables are updated.
+    ///
+    /// Timer, alarm, capture, volume and catalog commands, and the stopwatch status query
+    /// (see timers::handle_command, alarms::handle_command, capture::handle_command,
+    /// audio_backend::handle_command, catalog::handle_command and stopwatch::handle_command)
+    /// are handled right away instead, returning their reply.
+    ///
+    /// Intents with invalid names (see valid_intent_name) are refused, whichever way they were
+    /// received.
+    pub fn handle(&self, intent : String, args : String) -> Result<Option<String>, Violation> {
+        if ! valid_intent_name(&intent) {
+            return Err(Violation::InvalidIntent);
+        }
 
-/// Helper function for listen. 
-fn handle_client(stream : TcpStream, state : StateMutex, new_intent_available : Arc<(Mutex<bool>, Condvar)>) -> std::io::Result<()> {
-    let mut buffer = "".to_owned();
-    
-    let reader = BufReader::new(stream);
+        if let Some(reply) = timers::handle_command(&intent, &args, &self.timers)
+                                .or_else(|| stopwatch::handle_command(&intent, &args, &self.stopwatch))
+                                .or_else(|| alarms::handle_command(&intent, &args, &self.alarms))
+                                .or_else(|| capture::handle_command(&intent, &args, &self.capture))
+                                .or_else(|| audio_backend::handle_command(&intent, &args, &self.audio))
+                                .or_else(|| catalog::handle_command(&intent, &args, &self.catalog)) {
+            self.events.publish(Event::CommandHandled { intent, args, reply : reply.clone() });
+            return Ok(Some(reply));
+        }
 
-    for l in reader.lines() { // For every line that arrives from the client (its messages end in '\n')
-        buffer.pop(); // Remove the delimiter
+        self.events.publish(Event::IntentReceived { intent : intent.clone(), args : args.clone() });
 
-        if let Ok(mut state) = state.lock() {
-            state.current_intent = l.unwrap().clone();
+        if let Ok(mut state) = self.state.lock() {
+            state.current_intent = intent;
+            state.current_args = args;
             // Signal that there is a new intent available
             state.new_intent = true;
         }
 
-        let (lock, cvar) = &*new_intent_available;
+        let (lock, cvar) = &*self.new_intent_available;
        
         // Also let know auxiliary functions that there is a new intent
         if let Ok(mut new_intent) = lock.lock() {
@@ -40,9 +96,460 @@ fn handle_client(stream : TcpStream, state : StateMutex, new_intent_available :
             cvar.notify_one();
         }
 
-        buffer.clear();
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+impl Pipeline {
+    /// Pipeline of its own, with faces for the hello intent only, for testing whatever
+    /// receives the intents
+    pub fn standalone(clock : super::ClockHandle) -> Pipeline {
+        use std::collections::HashMap;
+        use std::path::PathBuf;
+        use std::time::Duration;
+        use crate::config::{Config, Settings};
+        use super::{Capture, Events, NullAudio, State};
+        use super::alarms::Alarms;
+        use super::catalog::Catalog;
+        use super::stopwatch::Stopwatch;
+        use super::timers::Timers;
+
+        let mut intent_faces = HashMap::new();
+        intent_faces.insert("hello".to_owned(), vec![Vec::new()]);
+        let config = Config { folder : PathBuf::from("tests/fixtures/missing"), intent_faces, intent_audio : HashMap::new(),
+                              intent_timings : HashMap::new(), chronometer_steps : HashMap::new(), settings : Settings::default() };
+
+        Pipeline {
+            state : Arc::new(Mutex::new(State::new())),
+            new_intent_available : Arc::new((Mutex::new(false), Condvar::new())),
+            timers : Arc::new(Mutex::new(Timers::new(clock.clone()))),
+            stopwatch : Arc::new(Mutex::new(Stopwatch::new(clock.clone()))),
+            alarms : Arc::new(Mutex::new(Alarms::load("", Duration::from_secs(60), clock.clone()))),
+            capture : Arc::new(Mutex::new(Capture::from_settings(&Settings::default(), clock.clone()))),
+            audio : Arc::new(NullAudio::new(clock.clone())),
+            catalog : Arc::new(Mutex::new(Catalog::new(&config))),
+            events : Arc::new(Events::new(clock)),
+        }
     }
+}
+
+/// Listen on the given address and port, handling each connection on a thread of its own and
+/// handing every line received to the pipeline (see Pipeline::handle_line). The replies to
+/// the commands are sent back to the client, one line at a time.
+///
+/// Clients which the access doesn't allow, which fail the TLS handshake, which don't send
+/// the token, which stay idle for too long or which are one too many (see Limits) are rejected
+/// (and logged). BMO keeps running once a client disconnects, unless exit_on_disconnect is set
+/// (see the setting of the same name).
+#[cfg(feature = "tcp")]
+pub fn listen(addr : String, port : String, pipeline : Pipeline, access : Access, limits : Limits, exit_on_disconnect : bool) -> std::io::Result<()> {
     
-    // Either the client finished the connection or errored out, either way we need to exit
-    std::process::exit(1);
+    let listener = TcpListener::bind(format!("{}:{}",addr, port))?;
+    let listening = Arc::new(Listening::new(pipeline, access, limits, exit_on_disconnect));
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Couldn't accept a connection on {}:{}: {}", addr, port, e);
+                continue;
+            },
+        };
+        let peer = stream.peer_addr().map_or("an unknown peer".to_owned(), |peer| peer.to_string());
+        let Some(client) = Client::admit(&listening, peer) else { continue };
+
+        thread::spawn(move || match client.listening.access.accept(stream) {
+            Ok(connection) => client.serve(connection),
+            Err(reason) => eprintln!("Rejected the connection from {}: {}", client.peer, reason),
+        });
+    }
+
+    Ok(())
+}
+
+/// Should BMO quit once a client disconnects? From the exit_on_disconnect setting: off by
+/// default, or on for the original behaviour of serving a single client.
+#[cfg(any(feature = "tcp", all(feature = "unix", unix)))]
+pub fn exit_on_disconnect(settings : &Settings) -> Result<bool, String> {
+    match settings.get_or("exit_on_disconnect", "off").as_str() {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        other => Err(format!("Couldn't parse the exit_on_disconnect setting, please ensure that it's on or off: {}", other)),
+    }
+}
+
+// Once handle_client is done with a client, log why if it lost the connection, and if the
+// client went away rather than being dropped, quit if asked to
+#[cfg(any(feature = "tcp", all(feature = "unix", unix)))]
+fn served(peer : &str, served : std::io::Result<bool>, exit_on_disconnect : bool) {
+    match served {
+        Ok(true) => (),
+        Ok(false) => return, // Already logged
+        Err(e) => eprintln!("Lost the connection from {}: {}", peer, e),
+    }
+
+    if exit_on_disconnect {
+        println!("{} disconnected, exiting", peer);
+        std::process::exit(1);
+    }
+
+    println!("{} disconnected", peer);
+}
+
+/// Permissions the Unix socket is created with, from the unix_socket_mode setting (in octal,
+/// 660 by default, so that only its owner and group can connect)
+#[cfg(all(feature = "unix", unix))]
+pub fn unix_socket_mode(settings : &Settings) -> Result<u32, String> {
+    let mode = settings.get_or("unix_socket_mode", "660");
+
+    u32::from_str_radix(&mode, 8).ok().filter(|mode| *mode <= 0o777)
+                                .ok_or(format!("Couldn't parse the unix_socket_mode setting, please ensure that it's in octal (such as 660): {}", mode))
+}
+
+/// Create a Unix domain socket on the given path with the given permissions, replacing the
+/// one left behind by a previous run (but nothing else)
+#[cfg(all(feature = "unix", unix))]
+pub fn bind_unix(path : &Path, mode : u32) -> Result<UnixListener, String> {
+    if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        fs::remove_file(path).map_err(|e| format!("Couldn't remove the old socket {}: {}", path.display(), e))?;
+    }
+
+    let listener = UnixListener::bind(path).map_err(|e| format!("Couldn't listen on {}: {}", path.display(), e))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode)).map_err(|e| format!("Couldn't change the permissions of {}: {}", path.display(), e))?;
+
+    Ok(listener)
+}
+
+/// Handle the connections to the given Unix socket, the same way as listen does for TCP (the
+/// socket's permissions already tell who can connect, but the token is still needed if set)
+#[cfg(all(feature = "unix", unix))]
+pub fn listen_unix(listener : UnixListener, pipeline : Pipeline, access : Access, limits : Limits, exit_on_disconnect : bool) {
+    let listening = Arc::new(Listening::new(pipeline, access, limits, exit_on_disconnect));
+    let peer = listener.local_addr().ok().and_then(|address| address.as_pathname().map(|path| format!("a client of {}", path.display())))
+                                    .unwrap_or("a client of the Unix socket".to_owned());
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => if let Some(client) = Client::admit(&listening, peer.clone()) {
+                thread::spawn(move || client.serve(stream));
+            },
+            Err(e) => eprintln!("Couldn't accept a connection from {}: {}", peer, e),
+        }
+    }
+}
+
+// What the connections to a listener share
+#[cfg(any(feature = "tcp", all(feature = "unix", unix)))]
+struct Listening {
+    pipeline : Pipeline,
+    access : Access,
+    limits : Limits,
+    violations : Violations,
+    exit_on_disconnect : bool,
+    clients : AtomicUsize, // Being served right now
+}
+
+#[cfg(any(feature = "tcp", all(feature = "unix", unix)))]
+impl Listening {
+    fn new(pipeline : Pipeline, access : Access, limits : Limits, exit_on_disconnect : bool) -> Listening {
+        Listening { pipeline, access, limits, violations : Violations::new(), exit_on_disconnect, clients : AtomicUsize::new(0) }
+    }
+}
+
+// A client being served, counted as such until it's dropped
+#[cfg(any(feature = "tcp", all(feature = "unix", unix)))]
+struct Client {
+    listening : Arc<Listening>,
+    peer : String,
+}
+
+#[cfg(any(feature = "tcp", all(feature = "unix", unix)))]
+impl Client {
+    // Admit a new client, unless as many as the limits allow are already being served
+    fn admit(listening : &Arc<Listening>, peer : String) -> Option<Client> {
+        let max_clients = listening.limits.max_clients;
+
+        if listening.clients.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |clients| Some(clients + 1).filter(|clients| *clients <= max_clients)).is_err() {
+            eprintln!("Rejected the connection from {}: too many clients (the limit is {})", peer, max_clients);
+            return None;
+        }
+
+        Some(Client { listening : Arc::clone(listening), peer })
+    }
+
+    fn serve(&self, connection : impl Connection) {
+        let Listening { pipeline, access, limits, violations, exit_on_disconnect, .. } = &*self.listening;
+
+        served(&self.peer, handle_client(connection, &self.peer, access, limits, violations, pipeline), *exit_on_disconnect);
+    }
+}
+
+#[cfg(any(feature = "tcp", all(feature = "unix", unix)))]
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.listening.clients.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Helper function for listen and listen_unix, reading the lines from the connection and
+/// writing the replies back to it. If the access needs a token, the first line has to be
+/// "auth token", which is answered with "ok", or with an error before the client is dropped.
+/// Returns whether the client was served until it disconnected, rather than dropped.
+///
+/// Lines breaking the limits (too long, not UTF-8, too many of them, or with an invalid intent
+/// name) are counted and answered with an error instead of being handled, and clients idle for
+/// longer than the limits allow are dropped as well (see Limits).
+#[cfg(any(feature = "tcp", all(feature = "unix", unix)))]
+fn handle_client(connection : impl Connection, peer : &str, access : &Access, limits : &Limits, violations : &Violations,
+                 pipeline : &Pipeline) -> std::io::Result<bool> {
+    let mut reader = BufReader::new(connection);
+
+    if access.needs_token() {
+        reader.get_ref().set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+
+        match read_line(&mut reader, limits.max_length) {
+            Ok(Received::Line(line)) if access.authenticates(&line) => (),
+            Err(e) => {
+                eprintln!("Rejected the connection from {}: {}", peer, e);
+                return Ok(false);
+            },
+            _ => {
+                eprintln!("Rejected the connection from {}: wrong token", peer);
+                let _ = send(&mut reader, "error: unauthorized");
+                return Ok(false);
+            },
+        }
+
+        send(&mut reader, "ok")?;
+    }
+
+    println!("Receiving intents from {}", peer);
+    reader.get_ref().set_read_timeout(limits.idle_timeout)?;
+    let mut rate_limiter = limits.rate_limiter();
+
+    loop { // For every line that arrives from the client (its messages end in '\n')
+        let received = match read_line(&mut reader, limits.max_length) {
+            Ok(Received::Closed) => break,
+            Ok(received) => received,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                violations.record(Violation::Idle, peer);
+                let _ = send(&mut reader, &Violation::Idle.reply(limits));
+                return Ok(false);
+            },
+            Err(e) => {
+                eprintln!("Lost the connection from {}: {}", peer, e);
+                break;
+            },
+        };
+
+        let reply = match received {
+            _ if ! rate_limiter.allow() => Err(Violation::RateLimited),
+            Received::Line(line) => {
+                let (intent, args) = split_intent(&line);
+
+                if intent.is_empty() {
+                    continue; // Blank lines are ignored
+                }
+
+                pipeline.handle(intent, args)
+            },
+            Received::Violation(violation) => Err(violation),
+            Received::Closed => unreachable!(),
+        };
+
+        let reply = reply.unwrap_or_else(|violation| {
+            violations.record(violation, peer);
+            Some(violation.reply(limits))
+        });
+        if let Some(reply) = reply {
+            if let Err(e) = send(&mut reader, &reply) {
+                eprintln!("Lost the connection from {}: {}", peer, e);
+                break;
+            }
+        }
+    }
+
+    Ok(true) // Either the client finished the connection or errored out
+}
+
+// Send a line back to the client
+#[cfg(any(feature = "tcp", all(feature = "unix", unix)))]
+fn send(reader : &mut BufReader<impl Connection>, line : &str) -> std::io::Result<()> {
+    writeln!(reader.get_mut(), "{}", line)?;
+    reader.get_mut().flush()
+}
+
+/// Split a received line into its intent name and its (possibly empty) arguments
+pub fn split_intent(line : &str) -> (String, String) {
+    let line = line.trim();
+
+    match line.find(char::is_whitespace) {
+        Some(i) => (line[..i].to_owned(), line[i..].trim().to_owned()),
+        None => (line.to_owned(), "".to_owned()),
+    }
+}
+
+#[cfg(all(test, feature = "unix", unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unix_sockets_replace_old_ones_only() {
+        let path = std::env::temp_dir().join(format!("bmos_socket_{}.sock", std::process::id()));
+
+        let old = bind_unix(&path, 0o600).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        drop(old); // Its file stays behind, as if BMO had crashed
+
+        bind_unix(&path, 0o660).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o660);
+        fs::remove_file(&path).unwrap();
+
+        fs::write(&path, "not a socket").unwrap();
+        assert!(bind_unix(&path, 0o660).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "not a socket");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn socket_modes_are_octal() {
+        assert_eq!(unix_socket_mode(&Settings::default()), Ok(0o660));
+
+        let settings = Settings::from_reader(&b"[unix_socket_mode]\n999\n"[..], "settings.txt").unwrap();
+        assert!(unix_socket_mode(&settings).is_err());
+    }
+
+    #[test]
+    fn clients_without_the_token_are_dropped() {
+        use std::io::{Read, Write};
+        use std::os::unix::net::UnixStream;
+        use crate::main_loop::ManualClock;
+
+        let access = Access::from_settings(&Settings::from_reader(&b"[intent_token]\nmathematical\n"[..], "settings.txt").unwrap()).unwrap();
+        let pipeline = Pipeline::standalone(Arc::new(ManualClock::new()));
+
+        let (mut client, server) = UnixStream::pair().unwrap();
+        client.write_all(b"auth algebraic\nhello\n").unwrap();
+        let limits = Limits::from_settings(&Settings::default(), Arc::new(ManualClock::new())).unwrap();
+        assert!(! handle_client(server, "a test client", &access, &limits, &Violations::new(), &pipeline).unwrap()); // Dropped
+
+        let mut reply = String::new();
+        client.read_to_string(&mut reply).unwrap();
+        assert_eq!(reply, "error: unauthorized\n");
+        assert!(! pipeline.state.lock().unwrap().new_intent); // hello never made it
+    }
+
+    #[test]
+    fn misbehaving_clients_get_errors() {
+        use std::io::{Read, Write};
+        use std::os::unix::net::UnixStream;
+        use std::time::Duration;
+        use crate::main_loop::ManualClock;
+
+        let clock = Arc::new(ManualClock::new());
+        let pipeline = Pipeline::standalone(clock.clone());
+        let mut limits = Limits::from_settings(&Settings::default(), clock).unwrap();
+        limits.max_length = 16;
+        limits.rate = 5;
+        limits.idle_timeout = Some(Duration::from_millis(200));
+        let violations = Violations::new();
+
+        let (mut client, server) = UnixStream::pair().unwrap();
+        client.write_all(b"timer tea 3m and a very long label\n\xffhello\n../hello\n\nvolume\nhello\n").unwrap();
+        // Stays connected without sending anything else, until it's dropped for being idle
+        assert!(! handle_client(server, "a test client", &Access::open(), &limits, &violations, &pipeline).unwrap());
+
+        let mut replies = String::new();
+        client.read_to_string(&mut replies).unwrap();
+        assert_eq!(replies.lines().collect::<Vec<_>>(), vec!["error: line too long (the limit is 16 bytes)",
+                                                             "error: invalid UTF-8",
+                                                             "error: invalid intent name (letters, digits, _ and - only)",
+                                                             "volume 100", // The blank line is ignored
+                                                             "error: too many lines (the limit is 5 per second)",
+                                                             "error: idle for too long"]);
+        for violation in [Violation::TooLong, Violation::InvalidUtf8, Violation::InvalidIntent, Violation::RateLimited, Violation::Idle] {
+            assert_eq!(violations.count(violation), 1);
+        }
+        assert!(! pipeline.state.lock().unwrap().new_intent);
+    }
+
+    #[test]
+    fn the_next_client_is_waited_for() {
+        use std::io::{BufRead, Write};
+        use std::os::unix::net::UnixStream;
+        use crate::main_loop::ManualClock;
+
+        let clock = Arc::new(ManualClock::new());
+        let pipeline = Pipeline::standalone(clock.clone());
+        let limits = Limits::from_settings(&Settings::default(), clock).unwrap();
+        assert_eq!(exit_on_disconnect(&Settings::default()), Ok(false));
+
+        let path = std::env::temp_dir().join(format!("bmos_next_client_{}.sock", std::process::id()));
+        let listener = bind_unix(&path, 0o600).unwrap();
+        std::thread::spawn(move || listen_unix(listener, pipeline, Access::open(), limits, false));
+
+        for _ in 0..2 { // The first one disconnecting doesn't stop BMO
+            let mut client = UnixStream::connect(&path).unwrap();
+            client.write_all(b"volume\n").unwrap();
+            let mut reply = String::new();
+            BufReader::new(&client).read_line(&mut reply).unwrap();
+            assert_eq!(reply, "volume 100\n");
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn lost_connections_dont_stop_the_listener() {
+        use std::io::{BufRead, Write};
+        use std::os::unix::net::UnixStream;
+        use crate::main_loop::ManualClock;
+
+        let access = Access::from_settings(&Settings::from_reader(&b"[intent_token]\nmathematical\n"[..], "settings.txt").unwrap()).unwrap();
+        let clock = Arc::new(ManualClock::new());
+        let pipeline = Pipeline::standalone(clock.clone());
+        let limits = Limits::from_settings(&Settings::default(), clock).unwrap();
+
+        let path = std::env::temp_dir().join(format!("bmos_lost_client_{}.sock", std::process::id()));
+        let listener = bind_unix(&path, 0o600).unwrap();
+        std::thread::spawn(move || listen_unix(listener, pipeline, access, limits, false));
+
+        // Gone before its handshake is answered
+        UnixStream::connect(&path).unwrap().write_all(b"auth mathematical\n").unwrap();
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"auth mathematical\nvolume\n").unwrap();
+        let replies : Vec<String> = BufReader::new(&client).lines().take(2).map(|line| line.unwrap()).collect();
+        assert_eq!(replies, vec!["ok", "volume 100"]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn silent_clients_dont_keep_the_others_waiting() {
+        use std::io::{BufRead, Read, Write};
+        use std::os::unix::net::UnixStream;
+        use crate::main_loop::ManualClock;
+
+        let clock = Arc::new(ManualClock::new());
+        let pipeline = Pipeline::standalone(clock.clone());
+        let mut limits = Limits::from_settings(&Settings::default(), clock).unwrap();
+        limits.max_clients = 2;
+
+        let path = std::env::temp_dir().join(format!("bmos_silent_client_{}.sock", std::process::id()));
+        let listener = bind_unix(&path, 0o600).unwrap();
+        std::thread::spawn(move || listen_unix(listener, pipeline, Access::open(), limits, false));
+
+        let _silent = UnixStream::connect(&path).unwrap();
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"volume\n").unwrap();
+        let mut reply = String::new();
+        BufReader::new(&client).read_line(&mut reply).unwrap();
+        assert_eq!(reply, "volume 100\n");
+
+        // Both are still connected, so a third one is one too many
+        let mut extra = String::new();
+        UnixStream::connect(&path).unwrap().read_to_string(&mut extra).unwrap();
+        assert_eq!(extra, "");
+        fs::remove_file(&path).unwrap();
+    }
 }
@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::ClockHandle;
+use crate::config::Settings;
+
+/// What the clients of the intent socket are held to. Read from these settings:
+/// - **intent_max_length** (1024 by default) : Longest line accepted, in bytes. The HTTP API
+///   holds the request bodies to it as well.
+/// - **intent_rate** (20 by default) : Lines accepted per second, in bursts of as many.
+/// - **intent_idle_timeout** : Clients which don't send anything for that long (such as 30m)
+///   are dropped. They're kept forever without it.
+/// - **intent_max_clients** (8 by default) : Clients served at once, by each listener (TCP or
+///   the Unix socket). Those connecting beyond it are dropped right away.
+pub struct Limits {
+    pub max_length : usize,
+    pub rate : u32,
+    pub idle_timeout : Option<Duration>,
+    pub max_clients : usize,
+    clock : ClockHandle,
+}
+
+impl Limits {
+    pub fn from_settings(settings : &Settings, clock : ClockHandle) -> Result<Limits, String> {
+        let max_length = settings.get_or("intent_max_length", "1024");
+        let max_length = max_length.parse::<usize>().ok().filter(|max_length| *max_length > 0)
+                                   .ok_or(format!("Couldn't parse the intent_max_length setting, please ensure that it's a number of bytes: {}", max_length))?;
+        let rate = settings.get_or("intent_rate", "20");
+        let rate = rate.parse::<u32>().ok().filter(|rate| *rate > 0)
+                       .ok_or(format!("Couldn't parse the intent_rate setting, please ensure that it's a number of lines per second: {}", rate))?;
+        let idle_timeout = match settings.get("intent_idle_timeout") {
+            Some(timeout) => Some(crate::config::parse_duration(timeout).filter(|timeout| ! timeout.is_zero())
+                                  .ok_or(format!("Couldn't parse the intent_idle_timeout setting, please ensure that it's a valid duration (5m, 30s, 1:00...): {}", timeout))?),
+            None => None,
+        };
+        let max_clients = settings.get_or("intent_max_clients", "8");
+        let max_clients = max_clients.parse::<usize>().ok().filter(|max_clients| *max_clients > 0)
+                                     .ok_or(format!("Couldn't parse the intent_max_clients setting, please ensure that it's a number of clients: {}", max_clients))?;
+
+        Ok(Limits { max_length, rate, idle_timeout, max_clients, clock })
+    }
+
+    /// Rate limiter for a new connection, full to begin with
+    pub fn rate_limiter(&self) -> RateLimiter {
+        RateLimiter { rate : self.rate as f64, available : self.rate as f64, last : self.clock.now(), clock : self.clock.clone() }
+    }
+}
+
+/// Reply to a line whose intent name isn't valid (see valid_intent_name)
+pub const INVALID_INTENT_REPLY : &str = "error: invalid intent name (letters, digits, _ and - only)";
+
+/// Ways a client can misbehave
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Violation {
+    TooLong,
+    InvalidUtf8,
+    InvalidIntent,
+    RateLimited,
+    Idle,
+}
+
+impl Violation {
+    /// Protocol error sent back to the client
+    pub fn reply(&self, limits : &Limits) -> String {
+        match self {
+            Violation::TooLong => format!("error: line too long (the limit is {} bytes)", limits.max_length),
+            Violation::InvalidUtf8 => "error: invalid UTF-8".to_owned(),
+            Violation::InvalidIntent => INVALID_INTENT_REPLY.to_owned(),
+            Violation::RateLimited => format!("error: too many lines (the limit is {} per second)", limits.rate),
+            Violation::Idle => "error: idle for too long".to_owned(),
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            Violation::TooLong => "line too long",
+            Violation::InvalidUtf8 => "invalid UTF-8",
+            Violation::InvalidIntent => "invalid intent name",
+            Violation::RateLimited => "rate limited",
+            Violation::Idle => "idle for too long",
+        }
+    }
+}
+
+/// How many times each violation happened, across every connection of a listener
+#[derive(Default)]
+pub struct Violations {
+    counts : Mutex<HashMap<Violation, u64>>,
+}
+
+impl Violations {
+    pub fn new() -> Violations {
+        Violations::default()
+    }
+
+    /// Count and log a violation from the given peer
+    pub fn record(&self, violation : Violation, peer : &str) {
+        let mut counts = self.counts.lock().unwrap();
+        let count = counts.entry(violation).or_insert(0);
+        *count += 1;
+
+        eprintln!("Rejected a line from {}: {} ({} so far)", peer, violation.description(), count);
+    }
+
+    pub fn count(&self, violation : Violation) -> u64 {
+        self.counts.lock().unwrap().get(&violation).copied().unwrap_or(0)
+    }
+}
+
+/// Token bucket, refilled with rate lines per second up to rate lines
+pub struct RateLimiter {
+    rate : f64,
+    available : f64,
+    last : Instant,
+    clock : ClockHandle,
+}
+
+impl RateLimiter {
+    /// Take a line from the bucket, if there's any left
+    pub fn allow(&mut self) -> bool {
+        let now = self.clock.now();
+        self.available = (self.available + now.duration_since(self.last).as_secs_f64() * self.rate).min(self.rate);
+        self.last = now;
+
+        if self.available >= 1.0 {
+            self.available -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// What was read from a client
+#[derive(Debug, PartialEq)]
+pub enum Received {
+    Line(String),
+    Violation(Violation),
+    Closed,
+}
+
+/// Read the next line, of max_length bytes at most (without its '\n'). Longer lines are
+/// skipped, without keeping more than max_length bytes of them around.
+pub fn read_line(reader : &mut impl BufRead, max_length : usize) -> io::Result<Received> {
+    let mut line = Vec::new();
+
+    if reader.by_ref().take(max_length as u64 + 1).read_until(b'\n', &mut line)? == 0 {
+        return Ok(Received::Closed);
+    }
+
+    if line.last() != Some(&b'\n') && line.len() > max_length {
+        loop { // Skip the rest of the line
+            let (skipped, finished) = {
+                let buffer = reader.fill_buf()?;
+                match buffer.iter().position(|byte| *byte == b'\n') {
+                    Some(end) => (end + 1, true),
+                    None => (buffer.len(), buffer.is_empty()),
+                }
+            };
+            reader.consume(skipped);
+
+            if finished {
+                return Ok(Received::Violation(Violation::TooLong));
+            }
+        }
+    }
+
+    Ok(String::from_utf8(line).map_or(Received::Violation(Violation::InvalidUtf8), Received::Line))
+}
+
+/// Can the given intent name be shown, or handled as a command? Intent names are made of
+/// letters, digits, '_' and '-', 64 of them at most.
+pub fn valid_intent_name(intent : &str) -> bool {
+    (1..=64).contains(&intent.len()) && intent.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufReader;
+    use std::sync::Arc;
+    use crate::main_loop::ManualClock;
+
+    #[test]
+    fn long_lines_are_skipped() {
+        let mut reader = BufReader::with_capacity(4, "hello\n0123456789\ntimer tea 3m\nbye".as_bytes());
+
+        assert_eq!(read_line(&mut reader, 8).unwrap(), Received::Line("hello\n".to_owned()));
+        assert_eq!(read_line(&mut reader, 8).unwrap(), Received::Violation(Violation::TooLong));
+        assert_eq!(read_line(&mut reader, 12).unwrap(), Received::Line("timer tea 3m\n".to_owned()));
+        assert_eq!(read_line(&mut reader, 8).unwrap(), Received::Line("bye".to_owned()));
+        assert_eq!(read_line(&mut reader, 8).unwrap(), Received::Closed);
+    }
+
+    #[test]
+    fn invalid_utf8_is_reported() {
+        let mut reader = BufReader::new(&b"hel\xfflo\nhello\n"[..]);
+
+        assert_eq!(read_line(&mut reader, 1024).unwrap(), Received::Violation(Violation::InvalidUtf8));
+        assert_eq!(read_line(&mut reader, 1024).unwrap(), Received::Line("hello\n".to_owned()));
+    }
+
+    #[test]
+    fn intent_names_are_sanitized() {
+        for valid in ["hello", "weather_clear", "good-night", "timer2"] {
+            assert!(valid_intent_name(valid), "{}", valid);
+        }
+        for invalid in ["", "../hello", "hé", "hello\u{1b}[2J", &"a".repeat(65)] {
+            assert!(! valid_intent_name(invalid), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn lines_are_rate_limited() {
+        let clock = Arc::new(ManualClock::new());
+        let limits = Limits::from_settings(&Settings::default(), clock.clone()).unwrap();
+        let mut limiter = limits.rate_limiter();
+
+        assert!((0..20).all(|_| limiter.allow()));
+        assert!(! limiter.allow());
+
+        clock.advance(Duration::from_millis(100));
+        assert!(limiter.allow() && limiter.allow());
+        assert!(! limiter.allow());
+
+        clock.advance(Duration::from_secs(60)); // Bursts are still capped
+        assert_eq!((0..30).filter(|_| limiter.allow()).count(), 20);
+    }
+
+    #[test]
+    fn violations_are_counted() {
+        let violations = Violations::new();
+
+        violations.record(Violation::TooLong, "127.0.0.1:4000");
+        violations.record(Violation::TooLong, "127.0.0.1:4000");
+        violations.record(Violation::Idle, "127.0.0.1:4001");
+
+        assert_eq!(violations.count(Violation::TooLong), 2);
+        assert_eq!(violations.count(Violation::Idle), 1);
+        assert_eq!(violations.count(Violation::RateLimited), 0);
+    }
+}
@@ -0,0 +1,263 @@
+extern crate rumqttc;
+extern crate serde_json;
+
+use rumqttc::{Client, LastWill, MqttOptions, Packet, QoS};
+use serde_json::json;
+
+use std::thread;
+use std::time::Duration;
+
+use crate::config::Settings;
+use super::intent_receiver::Pipeline;
+use super::limits::INVALID_INTENT_REPLY;
+use super::events::Event;
+use super::timers::TimersMutex;
+
+const COMMAND_TOPIC : &str = "command"; // Intents and commands, as they're sent through TCP ("timer tea 3m")
+const REPLY_TOPIC : &str = "reply"; // Replies to the commands
+const STATUS_TOPIC : &str = "status"; // online, or offline once BMO goes away
+const INTENT_TOPIC : &str = "intent"; // Intent being shown
+const MODE_TOPIC : &str = "mode"; // Preset intent which took over the screen, or none
+const TIMERS_TOPIC : &str = "timers"; // Timers running, as JSON
+const VOLUME_TOPIC : &str = "volume"; // From 0 to 100
+
+const NO_MODE : &str = "none";
+const PRESETS : [&str; 4] = ["chronometer", "stopwatch", "clock", "weather"];
+
+const KEEP_ALIVE : Duration = Duration::from_secs(30);
+const RETRY : Duration = Duration::from_secs(5); // How long to wait before connecting again to the broker
+const CAPACITY : usize = 64; // Messages waiting to be sent to the broker
+
+/// Broker to connect to, and the topics to use, read from the settings:
+/// - **mqtt_host** : Host of the broker. BMO only connects to one if it's present.
+/// - **mqtt_port** (1883 by default) : Port of the broker.
+/// - **mqtt_prefix** (bmo by default) : Prefix of every topic, such as bmo/command.
+/// - **mqtt_client_id** (bmo by default) : Client ID, which has to be unique on the broker.
+/// - **mqtt_username** and **mqtt_password** : Credentials, if the broker asks for them.
+#[derive(Clone)]
+pub struct MqttSettings {
+    pub host : String,
+    pub port : u16,
+    pub prefix : String,
+    pub client_id : String,
+    pub credentials : Option<(String, String)>,
+}
+
+impl MqttSettings {
+    /// Settings of the MQTT client, or None if it isn't enabled
+    pub fn from_settings(settings : &Settings) -> Result<Option<MqttSettings>, String> {
+        let host = match settings.get("mqtt_host") {
+            Some(host) => host.to_owned(),
+            None => return Ok(None),
+        };
+        let port = settings.get_or("mqtt_port", "1883").parse::<u16>()
+                           .map_err(|_| format!("Couldn't parse the mqtt_port setting: {}", settings.get_or("mqtt_port", "")))?;
+        let credentials = match (settings.get("mqtt_username"), settings.get("mqtt_password")) {
+            (Some(username), Some(password)) => Some((username.to_owned(), password.to_owned())),
+            (None, None) => None,
+            _ => return Err("The mqtt_username and mqtt_password settings go together".to_owned()),
+        };
+
+        Ok(Some(MqttSettings {
+            host, port, credentials,
+            prefix : settings.get_or("mqtt_prefix", "bmo").trim_end_matches('/').to_owned(),
+            client_id : settings.get_or("mqtt_client_id", "bmo"),
+        }))
+    }
+
+    /// Full name of one of BMO's topics
+    pub fn topic(&self, name : &str) -> String {
+        format!("{}/{}", self.prefix, name)
+    }
+}
+
+/// Connect to the broker in the background, handing the intents and commands published on
+/// prefix/command to the pipeline (see Pipeline::handle_line), and publishing the replies to
+/// the commands on prefix/reply.
+///
+/// BMO's state is kept up to date on retained topics: prefix/status (online, or offline as the
+/// last will once BMO goes away), prefix/intent, prefix/mode, prefix/timers and prefix/volume.
+/// Whenever the connection is lost, it's attempted again every few seconds.
+pub fn spawn(settings : MqttSettings, pipeline : Pipeline) {
+    let mut options = MqttOptions::new(settings.client_id.clone(), settings.host.clone(), settings.port);
+    options.set_keep_alive(KEEP_ALIVE);
+    options.set_last_will(LastWill::new(settings.topic(STATUS_TOPIC), "offline", QoS::AtLeastOnce, true));
+    if let Some((username, password)) = &settings.credentials {
+        options.set_credentials(username, password);
+    }
+
+    let (client, mut connection) = Client::new(options, CAPACITY);
+
+    // Publish the state as it changes, and all of it again after falling behind the events
+    let mut events = pipeline.events.subscribe();
+    let state_client = client.clone();
+    let state_pipeline = pipeline.clone();
+    let state_settings = settings.clone();
+    thread::spawn(move || {
+        loop {
+            for stamped in events {
+                for (topic, payload) in event_messages(&stamped.event, &state_pipeline.timers) {
+                    if let Err(e) = state_client.publish(state_settings.topic(topic), QoS::AtLeastOnce, true, payload) {
+                        eprintln!("Couldn't publish to {}: {}", state_settings.topic(topic), e);
+                    }
+                }
+            }
+
+            events = state_pipeline.events.subscribe();
+            for (topic, payload) in state_messages(&state_pipeline) {
+                if let Err(e) = state_client.publish(state_settings.topic(topic), QoS::AtLeastOnce, true, payload) {
+                    eprintln!("Couldn't publish to {}: {}", state_settings.topic(topic), e);
+                }
+            }
+        }
+    });
+
+    thread::spawn(move || {
+        let command_topic = settings.topic(COMMAND_TOPIC);
+
+        for notification in connection.iter() {
+            match notification {
+                Ok(rumqttc::Event::Incoming(Packet::ConnAck(_))) => {
+                    println!("Connected to the MQTT broker at {}:{}", settings.host, settings.port);
+
+                    // The broker forgets about the subscriptions along with the connection
+                    if let Err(e) = client.try_subscribe(command_topic.as_str(), QoS::AtLeastOnce) {
+                        eprintln!("Couldn't subscribe to {}: {}", command_topic, e);
+                    }
+                    for (topic, payload) in state_messages(&pipeline) {
+                        if let Err(e) = client.try_publish(settings.topic(topic), QoS::AtLeastOnce, true, payload) {
+                            eprintln!("Couldn't publish to {}: {}", settings.topic(topic), e);
+                        }
+                    }
+                }
+                Ok(rumqttc::Event::Incoming(Packet::Publish(publish))) if publish.topic == command_topic => {
+                    let reply = pipeline.handle_line(&String::from_utf8_lossy(&publish.payload)).unwrap_or_else(|_| {
+                        eprintln!("Rejected a command published to {}: invalid intent name", command_topic);
+                        Some(INVALID_INTENT_REPLY.to_owned())
+                    });
+                    if let Some(reply) = reply {
+                        if let Err(e) = client.try_publish(settings.topic(REPLY_TOPIC), QoS::AtLeastOnce, false, reply) {
+                            eprintln!("Couldn't publish to {}: {}", settings.topic(REPLY_TOPIC), e);
+                        }
+                    }
+                }
+                Ok(_) => { }
+                Err(e) => {
+                    eprintln!("MQTT connection to {}:{} failed ({}), retrying in {} seconds", settings.host, settings.port, e, RETRY.as_secs());
+                    thread::sleep(RETRY);
+                }
+            }
+        }
+    });
+}
+
+// Every state topic along with its current value, published on connecting
+fn state_messages(pipeline : &Pipeline) -> Vec<(&'static str, String)> {
+    let shown = pipeline.state.lock().unwrap().shown.clone();
+    let mode = if PRESETS.contains(&shown.as_str()) { shown.clone() } else { NO_MODE.to_owned() };
+
+    vec![(STATUS_TOPIC, "online".to_owned()),
+         (INTENT_TOPIC, shown),
+         (MODE_TOPIC, mode),
+         (TIMERS_TOPIC, timers_json(&pipeline.timers)),
+         (VOLUME_TOPIC, (pipeline.audio.volume() * 100.0).round().to_string())]
+}
+
+// State topics changed by an event, along with their new value
+fn event_messages(event : &Event, timers : &TimersMutex) -> Vec<(&'static str, String)> {
+    match event {
+        Event::IntentShown { intent } => vec![(INTENT_TOPIC, intent.clone())],
+        Event::ModeEntered { mode } => vec![(MODE_TOPIC, mode.clone())],
+        Event::ModeExited { .. } => vec![(MODE_TOPIC, NO_MODE.to_owned())],
+        Event::VolumeChanged { volume } => vec![(VOLUME_TOPIC, (volume * 100.0).round().to_string())],
+        Event::TimerFired { .. } | Event::CommandHandled { .. } => vec![(TIMERS_TOPIC, timers_json(timers))],
+        Event::Stopped => vec![(STATUS_TOPIC, "offline".to_owned())],
+        _ => Vec::new(),
+    }
+}
+
+// Timers running, along with the seconds they have left
+fn timers_json(timers : &TimersMutex) -> String {
+    json!(timers.lock().unwrap().remaining().iter()
+                .map(|(name, remaining)| json!({ "name" : name, "remaining" : remaining.as_secs() }))
+                .collect::<Vec<_>>()).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::main_loop::{ManualClock, SystemClock};
+    use crate::main_loop::timers::Timers;
+    use std::sync::{Arc, Mutex};
+    use std::sync::mpsc::channel;
+
+    // Settings read from the given contents
+    fn settings(contents : &str) -> Settings {
+        Settings::from_reader(contents.as_bytes(), "settings.txt").unwrap()
+    }
+
+    #[test]
+    fn settings_need_a_host() {
+        assert!(MqttSettings::from_settings(&settings("[mqtt_prefix]\nhome/bmo/\n")).unwrap().is_none());
+
+        let mqtt = MqttSettings::from_settings(&settings("[mqtt_host]\nlocalhost\n[mqtt_prefix]\nhome/bmo/\n")).unwrap().unwrap();
+        assert_eq!((mqtt.port, mqtt.topic(COMMAND_TOPIC)), (1883, "home/bmo/command".to_owned()));
+
+        assert!(MqttSettings::from_settings(&settings("[mqtt_host]\nlocalhost\n[mqtt_username]\nbmo\n")).is_err());
+        assert!(MqttSettings::from_settings(&settings("[mqtt_host]\nlocalhost\n[mqtt_port]\nhigh\n")).is_err());
+    }
+
+    #[test]
+    fn events_update_the_state_topics() {
+        let clock = Arc::new(ManualClock::new());
+        let timers : TimersMutex = Arc::new(Mutex::new(Timers::new(clock)));
+        timers.lock().unwrap().start(Some("tea"), Duration::from_secs(180));
+
+        assert_eq!(event_messages(&Event::ModeEntered { mode : "clock".to_owned() }, &timers), vec![(MODE_TOPIC, "clock".to_owned())]);
+        assert_eq!(event_messages(&Event::ModeExited { mode : "clock".to_owned() }, &timers), vec![(MODE_TOPIC, "none".to_owned())]);
+        assert_eq!(event_messages(&Event::VolumeChanged { volume : 0.4 }, &timers), vec![(VOLUME_TOPIC, "40".to_owned())]);
+        assert_eq!(event_messages(&Event::TimerFired { name : "egg".to_owned() }, &timers),
+                   vec![(TIMERS_TOPIC, r#"[{"name":"tea","remaining":180}]"#.to_owned())]);
+        assert!(event_messages(&Event::AudioStarted { clip : "hello/1".to_owned() }, &timers).is_empty());
+    }
+
+    // Needs a broker, such as mosquitto, listening on BMO_MQTT_HOST (localhost by default):
+    // cargo test -- --ignored
+    #[test]
+    #[ignore]
+    fn commands_and_state_go_through_the_broker() {
+        let host = std::env::var("BMO_MQTT_HOST").unwrap_or_else(|_| "localhost".to_owned());
+        let prefix = format!("bmo-test-{}", std::process::id());
+        let pipeline = Pipeline::standalone(Arc::new(SystemClock));
+        spawn(MqttSettings { host : host.clone(), port : 1883, prefix : prefix.clone(), client_id : prefix.clone(), credentials : None },
+              pipeline.clone());
+
+        // Follow everything BMO publishes from another client
+        let (mut options, (sender, received)) = (MqttOptions::new(format!("{}-observer", prefix), host, 1883), channel());
+        options.set_keep_alive(KEEP_ALIVE);
+        let (observer, mut connection) = Client::new(options, CAPACITY);
+        observer.subscribe(format!("{}/#", prefix), QoS::AtLeastOnce).unwrap();
+        thread::spawn(move || {
+            for notification in connection.iter() {
+                if let Ok(rumqttc::Event::Incoming(Packet::Publish(publish))) = notification {
+                    let _ = sender.send((publish.topic, String::from_utf8_lossy(&publish.payload).into_owned()));
+                }
+            }
+        });
+        let wait_for = |topic : &str, payload : &str| {
+            let expected = (format!("{}/{}", prefix, topic), payload.to_owned());
+            while received.recv_timeout(Duration::from_secs(5)).expect("Nothing was published in time") != expected { }
+        };
+
+        wait_for(STATUS_TOPIC, "online");
+        observer.publish(format!("{}/{}", prefix, COMMAND_TOPIC), QoS::AtLeastOnce, false, "timer tea 3m").unwrap();
+        wait_for(REPLY_TOPIC, "started tea 00:03:00");
+        observer.publish(format!("{}/{}", prefix, COMMAND_TOPIC), QoS::AtLeastOnce, false, "volume 40").unwrap();
+        wait_for(REPLY_TOPIC, "volume 40");
+        observer.publish(format!("{}/{}", prefix, COMMAND_TOPIC), QoS::AtLeastOnce, false, "hello there").unwrap();
+
+        thread::sleep(Duration::from_millis(500));
+        let state = pipeline.state.lock().unwrap();
+        assert_eq!((state.current_intent.as_str(), state.current_args.as_str()), ("hello", "there"));
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::fs;
+
+use crate::config::parse_assets;
+use super::weather_provider::{Place, WeatherProvider};
+
+/// File where the places looked up are stored, so that they aren't geocoded again
+pub const PLACES_FILE : &str = "places.txt";
+
+/// Places looked up so far, by the query used for them.
+///
+/// # Format
+/// Each query is enclosed between [...], in lowercase, followed by the place's name,
+/// country code, latitude and longitude, one per line.
+pub struct Places {
+    places : HashMap<String, Place>,
+    path : String,
+}
+
+impl Places {
+    pub fn load(path : &str) -> Places {
+        let mut places = Places { places : HashMap::new(), path : path.to_owned() };
+
+        let entries = match parse_assets(path) {
+            Ok(entries) => entries,
+            Err(_) => return places, // Nothing stored yet
+        };
+
+        for (query, lines) in entries.iter() {
+            if query.is_empty() { continue; }
+
+            let place = match &lines[..] {
+                [name, country, lat, lon] => lat.parse().ok().zip(lon.parse().ok()).map(|(lat, lon)| {
+                    Place { name : name.clone(), country : country.clone(), lat, lon }
+                }),
+                _ => None,
+            };
+
+            match place {
+                Some(place) => { places.places.insert(query.clone(), place); },
+                None => eprintln!("Skipping malformed place {} in {}", query, path),
+            }
+        }
+
+        places
+    }
+
+    // Store the places in the format read by load
+    fn save(&self) {
+        let contents : String = self.places.iter()
+                            .map(|(query, place)| format!("[{}]\n{}\n{}\n{}\n{}\n\n", query, place.name, place.country, place.lat, place.lon))
+                            .collect();
+
+        if let Err(e) = fs::write(&self.path, contents) {
+            eprintln!("Couldn't store the places in {}: {}", self.path, e);
+        }
+    }
+
+    /// Find the place a location asked for refers to ("Madrid ES", "New York US" or "Madrid,ES"),
+    /// geocoding it through the provider if it hasn't been looked up before
+    pub fn resolve(&mut self, provider : &dyn WeatherProvider, location : &str) -> Result<Place, String> {
+        let query = geocoding_query(location);
+        let key = query.to_lowercase();
+
+        if let Some(place) = self.places.get(&key) {
+            return Ok(place.clone());
+        }
+
+        let place = provider.geocode(&query)?;
+        println!("Found {} at {}, {}", place.location(), place.lat, place.lon);
+
+        self.places.insert(key, place.clone());
+        self.save();
+
+        Ok(place)
+    }
+}
+
+// Turn a location as asked for into a geocoding query: a trailing two letter word is taken as
+// the country code ("New York US" becomes "New York,US")
+fn geocoding_query(location : &str) -> String {
+    let location = location.trim();
+
+    match location.rsplit_once(' ') {
+        Some((name, country)) if ! location.contains(',') && country.len() == 2 => format!("{},{}", name.trim(), country),
+        _ => location.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::main_loop::weather_provider::FixtureProvider;
+
+    #[test]
+    fn queries_take_the_country_code() {
+        assert_eq!(geocoding_query("Madrid ES"), "Madrid,ES");
+        assert_eq!(geocoding_query(" New York US "), "New York,US");
+        assert_eq!(geocoding_query("Madrid,ES"), "Madrid,ES");
+        assert_eq!(geocoding_query("Madrid"), "Madrid");
+    }
+
+    #[test]
+    fn places_are_stored_once_looked_up() {
+        let path = std::env::temp_dir().join(format!("bmos_places_{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut places = Places::load(path);
+        let place = places.resolve(&FixtureProvider::new("tests/fixtures/weather"), "Madrid ES").unwrap();
+        assert_eq!(place.location(), "Madrid,ES");
+
+        // Read back without geocoding again
+        let mut places = Places::load(path);
+        assert_eq!(places.resolve(&FixtureProvider::new("tests/fixtures/missing"), "madrid es"), Ok(place));
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,254 @@
+extern crate sdl2;
+
+// SDL libs
+#[cfg(feature = "text")]
+use sdl2::pixels::Color;
+#[cfg(feature = "text")]
+use sdl2::render::TextureQuery;
+
+use std::time::{Duration, Instant};
+use std::sync::{Mutex, Arc};
+#[cfg(feature = "text")]
+use std::sync::Condvar;
+
+use super::{as_string, ClockHandle};
+#[cfg(feature = "text")]
+use super::{get_centered_rect, Screen, StateMutex};
+
+#[cfg(feature = "text")]
+const INTENT_START : &str = "start";
+#[cfg(feature = "text")]
+const INTENT_STOP : &str = "stop";
+#[cfg(feature = "text")]
+const INTENT_LAP : &str = "lap";
+#[cfg(feature = "text")]
+const INTENT_RESET : &str = "reset";
+#[cfg(feature = "text")]
+const INTENT_DONE : &str = "done";
+
+const INTENT_STOPWATCH : &str = "stopwatch";
+const ARG_STATUS : &str = "status"; // stopwatch status, answered back to the client
+
+#[cfg(feature = "text")]
+const SHOWN_LAPS : usize = 4; // How many of the latest laps are shown below the elapsed time
+
+/// Count-up chronometer (as told by the given clock), which keeps running in the background once started
+pub struct Stopwatch {
+    clock : ClockHandle,
+    started_at : Option<Instant>, // When it was last started, if it's running
+    accumulated : Duration, // Time elapsed before it was last started
+    laps : Vec<Duration>, // Elapsed time at each lap
+}
+
+impl Stopwatch {
+    pub fn new(clock : ClockHandle) -> Stopwatch {
+        Stopwatch { clock, started_at : None, accumulated : Duration::new(0, 0), laps : Vec::new() }
+    }
+
+    /// Start (or resume) counting
+    pub fn start(&mut self) {
+        if self.started_at.is_none() {
+            self.started_at = Some(self.clock.now());
+        }
+    }
+
+    /// Stop counting, keeping the elapsed time
+    pub fn stop(&mut self) {
+        self.accumulated = self.elapsed();
+        self.started_at = None;
+    }
+
+    /// Record the current elapsed time as a new lap
+    pub fn lap(&mut self) {
+        let elapsed = self.elapsed();
+        self.laps.push(elapsed);
+    }
+
+    /// Stop counting and forget the elapsed time and laps
+    pub fn reset(&mut self) {
+        *self = Stopwatch::new(Arc::clone(&self.clock));
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.started_at.is_some()
+    }
+
+    /// Total time counted so far
+    pub fn elapsed(&self) -> Duration {
+        match self.started_at {
+            Some(started_at) => self.accumulated + (self.clock.now() - started_at),
+            None => self.accumulated,
+        }
+    }
+
+    /// Time of each lap, measured from the previous one, along with the total elapsed time at that lap
+    pub fn laps(&self) -> Vec<(Duration, Duration)> {
+        let mut previous = Duration::new(0, 0);
+
+        self.laps.iter().map(|split| {
+            let lap = *split - previous;
+            previous = *split;
+            (lap, *split)
+        }).collect()
+    }
+
+    /// Multi-line description of the stopwatch, with its elapsed time and laps
+    pub fn status(&self) -> String {
+        let mut status = format!("{} {}", if self.is_running() { "running" } else { "stopped" },
+                                          as_string(self.elapsed(), true));
+
+        for (i, (lap, split)) in self.laps().iter().enumerate() {
+            status.push_str(&format!("\nlap {} {} {}", i + 1, as_string(*lap, true), as_string(*split, true)));
+        }
+
+        status
+    }
+}
+
+// Thread-safe Stopwatch instance
+pub type StopwatchMutex = Arc<Mutex<Stopwatch>>;
+
+/// Handle the "stopwatch status" query, returning the reply to be sent back to the client,
+/// or None if the intent isn't the query.
+pub fn handle_command(intent : &str, args : &str, stopwatch : &StopwatchMutex) -> Option<String> {
+    if intent == INTENT_STOPWATCH && args == ARG_STATUS {
+        Some(stopwatch.lock().unwrap().status())
+    } else {
+        None
+    }
+}
+
+/// Hijacks an SDL context and displays the stopwatch with its latest laps until INTENT_DONE
+/// is received. INTENT_START, INTENT_STOP, INTENT_LAP and INTENT_RESET control it meanwhile.
+///
+/// The stopwatch keeps running after leaving, so it can be shown again later on.
+#[cfg(feature = "text")]
+pub fn show_stopwatch<T: sdl2::render::RenderTarget, U>(screen : &mut Screen<T, U>,
+                                                        state : StateMutex,
+                                                        new_intent_available : Arc<(Mutex<bool>,Condvar)>,
+                                                        stopwatch : StopwatchMutex)
+                                                        -> Result<(), String> {
+    let (lock, cvar) = &*new_intent_available;
+
+    // Load the fonts
+    let ttf_context = screen.ttf_context;
+    let mut font = ttf_context.load_font(screen.font_path, 128)?;
+    font.set_style(sdl2::ttf::FontStyle::BOLD);
+    let mut laps_font = ttf_context.load_font(screen.font_path, 40)?;
+    laps_font.set_style(sdl2::ttf::FontStyle::BOLD);
+
+    let (res_width, res_height) = (screen.res_width, screen.res_height);
+    let padding = 5;
+    let elapsed_height = res_height * 2 / 3; // The elapsed time takes the upper 2/3 of the screen, and the laps the rest
+    let lap_height = (res_height - elapsed_height) / SHOWN_LAPS as u32;
+
+    loop {
+        let (elapsed, laps) = {
+            let stopwatch = stopwatch.lock().unwrap();
+            (stopwatch.elapsed(), stopwatch.laps())
+        };
+
+        screen.canvas.set_draw_color(Color::RGBA(128, 230, 209, 1));
+        screen.canvas.clear();
+
+        // render a surface, and convert it to a texture bound to the canvas
+        let surface = font
+            .render(&as_string(elapsed, true))
+            .blended(Color::RGBA(0, 0, 0, 255))
+            .map_err(|e| e.to_string())?;
+        let texture = screen.texture_creator
+            .create_texture_from_surface(&surface)
+            .map_err(|e| e.to_string())?;
+
+        let TextureQuery { width, height, .. } = texture.query();
+
+        let target = get_centered_rect(
+            res_width,
+            elapsed_height,
+            width,
+            height,
+            res_width - padding,
+            elapsed_height - padding,
+        );
+
+        screen.canvas.copy(&texture, None, Some(target))?;
+        drop(texture);
+
+        // The latest laps go below it, the newest one first
+        let first_shown = laps.len().saturating_sub(SHOWN_LAPS);
+        for (row, (i, (lap, split))) in laps.iter().enumerate().skip(first_shown).rev().enumerate() {
+            let surface = laps_font
+                .render(&format!("lap {}: {} ({})", i + 1, as_string(*lap, true), as_string(*split, true)))
+                .blended(Color::RGBA(0, 0, 0, 255))
+                .map_err(|e| e.to_string())?;
+            let texture = screen.texture_creator
+                .create_texture_from_surface(&surface)
+                .map_err(|e| e.to_string())?;
+
+            let TextureQuery { width, height, .. } = texture.query();
+
+            let mut target = get_centered_rect(
+                res_width,
+                lap_height,
+                width,
+                height,
+                res_width - padding,
+                lap_height - padding,
+            );
+            target.offset(0, (elapsed_height + row as u32 * lap_height) as i32);
+
+            screen.canvas.copy(&texture, None, Some(target))?;
+        }
+
+        screen.present();
+
+        screen.ring_due()?;
+        if screen.ringer.quit.get() {
+            return Ok(());
+        }
+
+        // Wait for a short time for a new intent, refreshing the elapsed time otherwise
+        let (mut new, _) = cvar.wait_timeout_while(lock.lock().unwrap(), Duration::from_millis(30), |new| !*new).unwrap();
+
+        if *new {
+            *new = false;
+            drop(new);
+
+            let parsed_intent = state.lock().unwrap().current_intent.clone();
+            let mut stopwatch = stopwatch.lock().unwrap();
+
+            match parsed_intent.as_str() {
+                INTENT_START => stopwatch.start(),
+                INTENT_STOP => stopwatch.stop(),
+                INTENT_LAP => stopwatch.lap(),
+                INTENT_RESET => stopwatch.reset(),
+                INTENT_DONE => break,
+                _ => { }, // No matching intent found
+            }
+        }
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::main_loop::ManualClock;
+
+    #[test]
+    fn stopwatch_counts_while_running() {
+        let clock = Arc::new(ManualClock::new());
+        let mut stopwatch = Stopwatch::new(Arc::clone(&clock) as ClockHandle);
+
+        stopwatch.start();
+        clock.advance(Duration::from_secs(75));
+        stopwatch.lap();
+        clock.advance(Duration::from_secs(45));
+        stopwatch.stop();
+        clock.advance(Duration::from_secs(600)); // Not counted
+
+        assert_eq!(stopwatch.status(), "stopped 00:02:00.00\nlap 1 00:01:15.00 00:01:15.00");
+    }
+}
@@ -0,0 +1,166 @@
+extern crate chrono;
+
+use chrono::{DateTime, Local};
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Source of the time for everything which depends on it (the main loop, the intents' timings,
+/// the chronometer, timers, alarms...), so that it can be driven without waiting in real time
+pub trait Clock : Send + Sync {
+    /// Current moment, for measuring how much time passes
+    fn now(&self) -> Instant;
+
+    /// Current date and time, for whatever goes off or is shown at a given time of the day
+    fn local_now(&self) -> DateTime<Local>;
+
+    /// Block the calling thread for the given amount of time
+    fn sleep(&self, duration : Duration);
+}
+
+// Thread-safe Clock instance, shared by whoever needs to wait
+pub type ClockHandle = Arc<dyn Clock>;
+
+/// The real time, as told by the operating system
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn local_now(&self) -> DateTime<Local> {
+        Local::now()
+    }
+
+    fn sleep(&self, duration : Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+// Virtual time elapsed so far, and when each of the sleeping threads has to wake up
+struct ManualTime {
+    elapsed : Duration,
+    wake_ups : Vec<Duration>,
+}
+
+/// Virtual time which only passes when it's advanced by hand. Whoever sleeps on it stays
+/// asleep until it's advanced past their wake up time, so tests can step through frames or
+/// fast-forward an hour in an instant.
+pub struct ManualClock {
+    started : Instant,
+    started_local : DateTime<Local>,
+    time : Mutex<ManualTime>,
+    cvar : Condvar,
+}
+
+impl ManualClock {
+    /// Virtual time starting at the current date and time
+    pub fn new() -> ManualClock {
+        ManualClock::starting_at(Local::now())
+    }
+
+    /// Virtual time starting at the given date and time
+    pub fn starting_at(local : DateTime<Local>) -> ManualClock {
+        ManualClock {
+            started : Instant::now(),
+            started_local : local,
+            time : Mutex::new(ManualTime { elapsed : Duration::new(0, 0), wake_ups : Vec::new() }),
+            cvar : Condvar::new(),
+        }
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.time.lock().unwrap().elapsed
+    }
+
+    /// Let the given amount of time pass, returning once every thread it woke up is awake
+    pub fn advance(&self, duration : Duration) {
+        let mut time = self.time.lock().unwrap();
+        time.elapsed += duration;
+        self.cvar.notify_all();
+
+        let _time = self.cvar.wait_while(time, |time| time.wake_ups.iter().any(|wake_up| *wake_up <= time.elapsed)).unwrap();
+    }
+
+    /// Wait (in real time) until someone is asleep on the clock, returning false if nobody
+    /// was before the timeout
+    pub fn wait_until_asleep(&self, timeout : Duration) -> bool {
+        let (_time, result) = self.cvar.wait_timeout_while(self.time.lock().unwrap(), timeout, |time| time.wake_ups.is_empty()).unwrap();
+
+        ! result.timed_out()
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> ManualClock {
+        ManualClock::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.started + self.elapsed()
+    }
+
+    fn local_now(&self) -> DateTime<Local> {
+        self.started_local + chrono::Duration::from_std(self.elapsed()).unwrap()
+    }
+
+    fn sleep(&self, duration : Duration) {
+        let mut time = self.time.lock().unwrap();
+        let wake_up = time.elapsed + duration;
+        time.wake_ups.push(wake_up);
+        self.cvar.notify_all();
+
+        let mut time = self.cvar.wait_while(time, |time| time.elapsed < wake_up).unwrap();
+        let index = time.wake_ups.iter().position(|other| *other == wake_up).unwrap();
+        time.wake_ups.remove(index);
+        self.cvar.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::thread;
+
+    #[test]
+    fn manual_sleeps_last_until_advanced() {
+        let clock = Arc::new(ManualClock::new());
+        let clock_clone = Arc::clone(&clock);
+        let sleeper = thread::spawn(move || clock_clone.sleep(Duration::from_secs(60)));
+
+        assert!(clock.wait_until_asleep(Duration::from_secs(5)));
+        clock.advance(Duration::from_secs(59));
+        assert!(! sleeper.is_finished());
+
+        clock.advance(Duration::from_secs(1)); // Returns once it's awake
+        sleeper.join().unwrap();
+    }
+
+    #[test]
+    fn manual_time_only_passes_when_advanced() {
+        let started_local = Local.with_ymd_and_hms(2026, 10, 18, 7, 0, 0).unwrap();
+        let clock = ManualClock::starting_at(started_local);
+        let started = clock.now();
+
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(clock.now(), started);
+        assert_eq!(clock.local_now(), started_local);
+
+        clock.advance(Duration::from_secs(90 * 60));
+        assert_eq!(clock.now() - started, Duration::from_secs(90 * 60));
+        assert_eq!(clock.local_now(), Local.with_ymd_and_hms(2026, 10, 18, 8, 30, 0).unwrap());
+    }
+
+    #[test]
+    fn nobody_asleep_times_out() {
+        let clock = ManualClock::new();
+        let started = Instant::now();
+
+        assert!(! clock.wait_until_asleep(Duration::from_millis(50)));
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+}
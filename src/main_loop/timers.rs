@@ -0,0 +1,222 @@
+extern crate sdl2;
+
+// SDL libs
+#[cfg(feature = "text")]
+use sdl2::render::Canvas;
+#[cfg(feature = "text")]
+use sdl2::pixels::Color;
+#[cfg(feature = "text")]
+use sdl2::render::TextureQuery;
+#[cfg(feature = "text")]
+use sdl2::rect::Rect;
+#[cfg(feature = "text")]
+use sdl2::render::TextureCreator;
+#[cfg(feature = "text")]
+use sdl2::ttf::Font;
+
+use std::time::{Duration, Instant};
+use std::sync::{Arc, Mutex};
+
+use crate::config::parse_duration;
+use super::{as_string, ClockHandle};
+
+const INTENT_TIMER : &str = "timer"; // timer [name] duration
+const INTENT_TIMERS : &str = "timers"; // lists the active timers
+const INTENT_CANCEL : &str = "cancel"; // cancel name
+
+#[cfg(feature = "text")]
+const OVERLAY_PADDING : i32 = 5;
+
+/// A named countdown running in the background
+pub struct Timer {
+    pub name : String,
+    pub ends_at : Instant,
+}
+
+/// Set of named timers running at the same time. Timers keep counting down (as told by the
+/// given clock) while BMO shows other faces, and are taken out of the set once they expire.
+pub struct Timers {
+    clock : ClockHandle,
+    timers : Vec<Timer>,
+    started : u32, // How many timers have been started so far, used for naming the unnamed ones
+}
+
+impl Timers {
+    pub fn new(clock : ClockHandle) -> Timers {
+        Timers { clock, timers : Vec::new(), started : 0 }
+    }
+
+    /// Start a new timer. If no name is provided, one is generated. Starting a timer
+    /// with the name of an already running one restarts it. Returns the timer's name.
+    pub fn start(&mut self, name : Option<&str>, duration : Duration) -> String {
+        self.started += 1;
+
+        let name = match name {
+            Some(name) => name.to_owned(),
+            None => format!("timer{}", self.started),
+        };
+
+        self.cancel(&name);
+        self.timers.push(Timer { name : name.clone(), ends_at : self.clock.now() + duration });
+
+        name
+    }
+
+    /// Cancel the timer with the given name. Returns false if there wasn't any.
+    pub fn cancel(&mut self, name : &str) -> bool {
+        let count = self.timers.len();
+        self.timers.retain(|timer| timer.name != name);
+
+        count != self.timers.len()
+    }
+
+    /// Names and remaining time of the active timers, the ones ending first going first
+    pub fn remaining(&self) -> Vec<(String, Duration)> {
+        let now = self.clock.now();
+        let mut remaining : Vec<(String, Duration)> = self.timers.iter()
+                                    .map(|timer| (timer.name.clone(), timer.ends_at.saturating_duration_since(now)))
+                                    .collect();
+        remaining.sort_by_key(|(_, duration)| *duration);
+
+        remaining
+    }
+
+    /// Remove the timers which have already finished, returning their names
+    pub fn take_expired(&mut self) -> Vec<String> {
+        let now = self.clock.now();
+        let mut expired = Vec::new();
+
+        self.timers.retain(|timer| {
+            if timer.ends_at <= now {
+                expired.push(timer.name.clone());
+                false
+            } else {
+                true
+            }
+        });
+
+        expired
+    }
+}
+
+// Thread-safe Timers instance
+pub type TimersMutex = Arc<Mutex<Timers>>;
+
+/// Handle the timer commands, which don't change BMO's face:
+/// - "timer [name] duration" starts a timer, such as "timer pasta 10m" or "timer 90 seconds".
+/// - "timers" lists the active timers.
+/// - "cancel name" cancels the timer with the given name.
+///
+/// Returns the reply to be sent back to the client, or None if the intent isn't a timer command.
+pub fn handle_command(intent : &str, args : &str, timers : &TimersMutex) -> Option<String> {
+    let mut timers = timers.lock().unwrap();
+
+    let reply = match intent {
+        INTENT_TIMER => {
+            // The name is optional, so try to parse everything as a duration first
+            let (name, duration) = match parse_duration(args) {
+                Some(duration) => (None, Some(duration)),
+                None => match args.find(char::is_whitespace) {
+                    Some(i) => (Some(&args[..i]), parse_duration(&args[i..])),
+                    None => (None, None),
+                },
+            };
+
+            match duration {
+                Some(duration) => format!("started {} {}", timers.start(name, duration), as_string(duration, false)),
+                None => format!("error: couldn't read a duration from '{}'", args),
+            }
+        }
+        INTENT_TIMERS => {
+            let remaining = timers.remaining();
+
+            if remaining.is_empty() {
+                "no timers".to_owned()
+            } else {
+                remaining.iter()
+                        .map(|(name, duration)| format!("{} {}", name, as_string(*duration, false)))
+                        .collect::<Vec<String>>()
+                        .join("\n")
+            }
+        }
+        INTENT_CANCEL => {
+            if timers.cancel(args) {
+                format!("cancelled {}", args)
+            } else {
+                format!("error: no timer named '{}'", args)
+            }
+        }
+        _ => return None,
+    };
+
+    println!("{} {}: {}", intent, args, reply);
+
+    Some(reply)
+}
+
+/// Draws the name and remaining time of each active timer on the top-left corner
+/// of the canvas, on top of whatever is being shown.
+#[cfg(feature = "text")]
+pub fn draw_overlay<T: sdl2::render::RenderTarget, U>(canvas : &mut Canvas<T>,
+                                                       font : &Font,
+                                                       texture_creator : &TextureCreator<U>,
+                                                       timers : &[(String, Duration)]) -> Result<(), String> {
+    let mut y = OVERLAY_PADDING;
+
+    for (name, remaining) in timers {
+        // render a surface, and convert it to a texture bound to the canvas
+        let surface = font
+            .render(&format!("{} {}", name, as_string(*remaining, false)))
+            .blended(Color::RGBA(0, 0, 0, 255))
+            .map_err(|e| e.to_string())?;
+        let texture = texture_creator
+            .create_texture_from_surface(&surface)
+            .map_err(|e| e.to_string())?;
+
+        let TextureQuery { width, height, .. } = texture.query();
+
+        // Draw a backdrop first, so that the text can be read on top of any face
+        canvas.set_draw_color(Color::RGBA(128, 230, 209, 255));
+        canvas.fill_rect(Rect::new(0, y - OVERLAY_PADDING, width + 2 * OVERLAY_PADDING as u32, height + OVERLAY_PADDING as u32))?;
+        canvas.copy(&texture, None, Some(Rect::new(OVERLAY_PADDING, y, width, height)))?;
+
+        y += height as i32 + OVERLAY_PADDING;
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::main_loop::ManualClock;
+
+    #[test]
+    fn timers_expire_exactly_when_due() {
+        let clock = Arc::new(ManualClock::new());
+        let mut timers = Timers::new(Arc::clone(&clock) as ClockHandle);
+        timers.start(Some("pasta"), Duration::from_secs(60 * 60));
+        timers.start(None, Duration::from_secs(90 * 60));
+
+        clock.advance(Duration::from_secs(60 * 60) - Duration::from_millis(1));
+        assert!(timers.take_expired().is_empty());
+        assert_eq!(timers.remaining()[0], ("pasta".to_owned(), Duration::from_millis(1)));
+
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(timers.take_expired(), vec!["pasta"]);
+        assert_eq!(timers.remaining(), vec![("timer2".to_owned(), Duration::from_secs(30 * 60))]);
+    }
+
+    #[test]
+    fn timer_commands_are_replied_to() {
+        let clock = Arc::new(ManualClock::new());
+        let timers : TimersMutex = Arc::new(Mutex::new(Timers::new(Arc::clone(&clock) as ClockHandle)));
+
+        assert_eq!(handle_command("timer", "tea 4m", &timers).unwrap(), "started tea 00:04:00");
+        clock.advance(Duration::from_secs(90));
+        assert_eq!(handle_command("timers", "", &timers).unwrap(), "tea 00:02:30");
+        assert_eq!(handle_command("cancel", "tea", &timers).unwrap(), "cancelled tea");
+        assert_eq!(handle_command("sing", "", &timers), None);
+    }
+}
@@ -1,110 +1,332 @@
 extern crate sdl2;
-extern crate openweathermap;
 
 // SDL libs
+#[cfg(feature = "text")]
 use sdl2::render::Canvas;
+#[cfg(feature = "text")]
 use sdl2::pixels::Color;
+#[cfg(feature = "text")]
 use sdl2::render::TextureQuery;
+#[cfg(feature = "text")]
+use sdl2::image::LoadTexture;
+#[cfg(feature = "text")]
 use sdl2::rect::Rect;
+#[cfg(feature = "text")]
 use sdl2::render::TextureCreator;
-use super::StateMutex;
+#[cfg(feature = "text")]
+use super::{Screen, StateMutex};
+#[cfg(feature = "text")]
 use std::sync::{Mutex, Arc, Condvar};
+#[cfg(feature = "text")]
+use std::collections::HashMap;
+use std::time::Duration;
 
-use openweathermap::blocking::weather;
+use crate::config::Settings;
+#[cfg(feature = "text")]
+use super::weather_cache::WeatherCacheMutex;
+#[cfg(feature = "text")]
+use super::{draw_text, get_centered_rect};
 
+#[cfg(feature = "text")]
 const INTENT_DONE : &str = "done";
+#[cfg(feature = "text")]
+const INTENT_NEXT : &str = "next"; // Shows the next favourite location
+#[cfg(feature = "text")]
+const INTENT_PREVIOUS : &str = "previous";
 
-/// Hijacks an SDL context and displays the weather
-pub fn show_weather<T: crate::main_loop::sdl2::render::RenderTarget, U>(res_width : u32, res_height : u32,
-                                                                    key : &str, loc : &str, country : &str, canvas : &mut Canvas<T>, 
-                                                                    ttf_context : &sdl2::ttf::Sdl2TtfContext, 
-                                                                    texture_creator : &TextureCreator<U>, 
-                                                                    state: StateMutex, 
-                                                                    new_intent_available : Arc<(Mutex<bool>,Condvar)>) 
-                                                                    -> Result<(), String> {
-    let mut parsed_intent = "".to_owned();
+#[cfg(feature = "text")]
+const FORECAST_DAYS : usize = 4; // How many days (after today) are shown in the forecast
+
+/// How the weather is shown, read from the settings
+pub struct WeatherSettings {
+    pub units : String, // weather_units, either metric (the default), imperial or standard
+    pub refresh : Duration, // weather_refresh, how often the weather is fetched in the background
+    pub favourites : Vec<String>, // weather_favourites, locations cycled through after the home one, one per line
+}
+
+impl WeatherSettings {
+    pub fn from_settings(settings : &Settings) -> Result<WeatherSettings, String> {
+        Ok(WeatherSettings {
+            units : settings.get_or("weather_units", "metric"),
+            refresh : settings.parse_duration_or("weather_refresh", Duration::from_secs(10 * 60))?,
+            favourites : settings.get_list("weather_favourites"),
+        })
+    }
+
+    #[cfg(feature = "text")]
+    fn temperature_unit(&self) -> &str {
+        match self.units.as_str() {
+            "imperial" => "°F",
+            "standard" => "K",
+            _ => "°C",
+        }
+    }
 
-    let (lock, cvar) = &*new_intent_available;
+    #[cfg(feature = "text")]
+    fn speed_unit(&self) -> &str {
+        match self.units.as_str() {
+            "imperial" => "mph",
+            _ => "m/s",
+        }
+    }
+}
+
+/// Intent whose face is shown as the icon of an OpenWeatherMap condition code
+/// (https://openweathermap.org/weather-conditions)
+pub fn condition_intent(code : u64) -> &'static str {
+    match code {
+        200..=299 => "weather_thunderstorm",
+        300..=399 | 500..=599 => "weather_rain",
+        600..=699 => "weather_snow",
+        700..=799 => "weather_mist",
+        800 => "weather_clear",
+        _ => "weather_clouds",
+    }
+}
 
-    // Load the font
-    let mut font = ttf_context.load_font("assets/font.ttf", 30)?;
+/// Hijacks an SDL context and displays the latest weather report in the cache until INTENT_DONE is
+/// received, picking up the reports refreshed in the background meanwhile.
+///
+/// The weather is shown at the location in the intent's arguments ("weather Madrid ES"), or at the
+/// home one otherwise. INTENT_NEXT and INTENT_PREVIOUS cycle through it and the favourite locations.
+///
+/// The current conditions are shown on top, with the temperature next to the condition's icon,
+/// and the forecast for the next days at the bottom. The icons are the faces of the weather_*
+/// intents in faces.txt (see condition_intent), and are left out if there isn't one. If the last
+/// refresh failed, the report is marked as stale since the time it was fetched.
+#[cfg(feature = "text")]
+pub fn show_weather<T: crate::main_loop::sdl2::render::RenderTarget, U>(screen : &mut Screen<T, U>,
+                                                                    cache : &WeatherCacheMutex,
+                                                                    settings : &WeatherSettings,
+                                                                    args : &str,
+                                                                    intent_faces : &HashMap<String, Vec<Vec<u8>>>,
+                                                                    state: StateMutex,
+                                                                    new_intent_available : Arc<(Mutex<bool>,Condvar)>)
+                                                                    -> Result<(), String> {
+    // Load the fonts
+    let ttf_context = screen.ttf_context;
+    let mut font = ttf_context.load_font(screen.font_path, 128)?;
     font.set_style(sdl2::ttf::FontStyle::BOLD);
+    let mut small_font = ttf_context.load_font(screen.font_path, 30)?;
+    small_font.set_style(sdl2::ttf::FontStyle::BOLD);
 
-    let weather = match weather(format!("{},{}", loc, country).as_str(), "metric", "en", key) {
-        Ok(current) => current,
-        Err(e) => return Err(e),
-    };
+    let (res_width, res_height) = (screen.res_width, screen.res_height);
+    let degrees = settings.temperature_unit();
 
-    while parsed_intent != INTENT_DONE {
-        // render a surface, and convert it to a texture bound to the canvas
-        let surface = font
-            .render(format!("weather in {}: {}", weather.name, weather.weather[0].description).as_str())
-            .blended(Color::RGBA(0, 0, 0, 255))
-            .map_err(|e| e.to_string())?;
-        let texture = texture_creator
-            .create_texture_from_surface(&surface)
-            .map_err(|e| e.to_string())?;
+    // The location asked for goes first, then the home one and the favourites
+    let mut locations : Vec<String> = vec![cache.lock().unwrap().home().to_owned()];
+    locations.extend(settings.favourites.iter().cloned());
+    if ! args.is_empty() {
+        locations.retain(|location| location != args);
+        locations.insert(0, args.to_owned());
+    }
+    let mut shown = 0;
 
+    loop {
+        let (canvas, texture_creator) = (&mut screen.canvas, &screen.texture_creator);
         canvas.set_draw_color(Color::RGBA(128, 230, 209, 1));
         canvas.clear();
 
-        let TextureQuery { width, height, .. } = texture.query();
+        let location = &locations[shown];
+        let mut cache = cache.lock().unwrap();
+        cache.want(location);
 
-        // From https://github.com/Rust-SDL2/rust-sdl2/blob/master/examples/ttf-demo.rs
-        // If the example text is too big for the screen, downscale it (and center it regardless)
-        let padding = 5;
-        let target = get_centered_rect(
-            res_width,
-            res_height,
-            width,
-            height,
-            res_width - padding,
-            res_height - padding,
-        );
+        if let Some(report) = cache.report(location) {
+            let weather = &report.current;
+            let condition = weather.weather.first();
 
-        canvas.copy(&texture, None, Some(target))?;
-        canvas.present();
+            // The screen is split in eighths: the place and conditions go on the top one, the icon and
+            // temperature on the next four, the details below them and the forecast on the last two
+            let row = res_height / 8;
+            let half = res_width / 2;
 
-        // Wait while there isn't a new intent for auxiliary functions
-        let mut new = cvar.wait_while(lock.lock().unwrap(), |new| !*new).unwrap();
-        *new = false;
+            let mut title = format!("{}: {}", weather.name, condition.map(|c| c.description.as_str()).unwrap_or_default());
+            if let Some(fetched_at) = cache.stale_since(location) {
+                title.push_str(&format!(" (stale since {})", fetched_at.format("%H:%M")));
+            }
+            draw_text(canvas, texture_creator, &small_font, &title, Rect::new(0, 0, res_width, row))?;
 
-        parsed_intent = state.lock().unwrap().current_intent.clone();
-    }   
+            if let Some(condition) = condition {
+                draw_icon(canvas, texture_creator, intent_faces, condition.id, Rect::new(0, row as i32, half, row * 4))?;
+            }
+            draw_text(canvas, texture_creator, &font, &format!("{:.0}{}", weather.main.temp, degrees),
+                      Rect::new(half as i32, row as i32, half, row * 3))?;
+            draw_text(canvas, texture_creator, &small_font, &format!("feels like {:.0}{}", weather.main.feels_like, degrees),
+                      Rect::new(half as i32, row as i32 * 4, half, row))?;
 
-    Ok(())
-}
+            draw_text(canvas, texture_creator, &small_font,
+                      &format!("min {:.0}{} max {:.0}{}  humidity {:.0}%  wind {:.1} {}",
+                               weather.main.temp_min, degrees, weather.main.temp_max, degrees,
+                               weather.main.humidity, weather.wind.speed, settings.speed_unit()),
+                      Rect::new(0, row as i32 * 5, res_width, row))?;
+
+            // One column per day, skipping today's
+            let column = res_width / FORECAST_DAYS as u32;
 
+            for (i, day) in report.forecast.iter().skip(1).take(FORECAST_DAYS).enumerate() {
+                let x = (i as u32 * column) as i32;
 
-// https://github.com/Rust-SDL2/rust-sdl2/blob/master/examples/ttf-demo.rs
-// handle the annoying Rect i32
-macro_rules! rect(
-    ($x:expr, $y:expr, $w:expr, $h:expr) => (
-        Rect::new($x as i32, $y as i32, $w as u32, $h as u32)
-    )
-);
-
-// https://github.com/Rust-SDL2/rust-sdl2/blob/master/examples/ttf-demo.rs
-// Scale fonts to a reasonable size when they're too big (though they might look less smooth)
-fn get_centered_rect(res_width : u32, res_height : u32, rect_width: u32, rect_height: u32, cons_width: u32, cons_height: u32) -> Rect {
-    let wr = rect_width as f32 / cons_width as f32;
-    let hr = rect_height as f32 / cons_height as f32;
-
-    let (w, h) = if wr > 1f32 || hr > 1f32 {
-        if wr > hr {
-            println!("Scaling down! The text will look worse!");
-            let h = (rect_height as f32 / wr) as i32;
-            (cons_width as i32, h)
+                draw_icon(canvas, texture_creator, intent_faces, day.condition.id, Rect::new(x, row as i32 * 6, column, row))?;
+                draw_text(canvas, texture_creator, &small_font,
+                          &format!("{} {:.0}/{:.0}{}", day.date.format("%a"), day.temp_min, day.temp_max, degrees),
+                          Rect::new(x, row as i32 * 7, column, row))?;
+            }
         } else {
-            println!("Scaling down! The text will look worse!");
-            let w = (rect_width as f32 / hr) as i32;
-            (w, cons_height as i32)
+            // Nothing fetched yet, tell why (if we know it)
+            let message = match cache.error(location) {
+                Some(e) => format!("no weather in {}: {}", location, e),
+                None => format!("fetching the weather in {}...", location),
+            };
+            draw_text(canvas, texture_creator, &small_font, &message, Rect::new(0, 0, res_width, res_height))?;
+        }
+
+        drop(cache);
+        screen.present();
+        screen.ring_due()?;
+        if screen.ringer.quit.get() {
+            return Ok(());
+        }
+
+        // Wait for a short time for a new intent, redrawing the screen otherwise to show the
+        // reports refreshed in the background
+        let (lock, cvar) = &*new_intent_available;
+        let (mut new, _) = cvar.wait_timeout_while(lock.lock().unwrap(), Duration::from_secs(1), |new| !*new).unwrap();
+
+        if *new {
+            *new = false;
+            drop(new);
+
+            let parsed_intent = state.lock().unwrap().current_intent.clone();
+
+            match parsed_intent.as_str() {
+                INTENT_NEXT => shown = (shown + 1) % locations.len(),
+                INTENT_PREVIOUS => shown = (shown + locations.len() - 1) % locations.len(),
+                INTENT_DONE => break,
+                _ => { }, // No matching intent found
+            }
         }
-    } else {
-        (rect_width as i32, rect_height as i32)
+    }
+
+    Ok(())
+}
+
+// Draw the face of a condition code's intent (if there is one) centered in the given area,
+// keeping its aspect ratio
+#[cfg(feature = "text")]
+fn draw_icon<T: sdl2::render::RenderTarget, U>(canvas : &mut Canvas<T>,
+                                               texture_creator : &TextureCreator<U>,
+                                               intent_faces : &HashMap<String, Vec<Vec<u8>>>,
+                                               code : u64, area : Rect) -> Result<(), String> {
+    let face = match intent_faces.get(condition_intent(code)).and_then(|faces| faces.first()) {
+        Some(face) => face,
+        None => return Ok(()),
     };
 
-    let cx = (res_width as i32 - w) / 2;
-    let cy = (res_height as i32 - h) / 2;
-    rect!(cx, cy, w, h)
+    let texture = texture_creator.load_texture_bytes(face)?;
+    let TextureQuery { width, height, .. } = texture.query();
+
+    // Scale it to fit the area, whether it's bigger or smaller
+    let scale = (area.width() as f32 / width as f32).min(area.height() as f32 / height as f32);
+    let (width, height) = ((width as f32 * scale) as u32, (height as f32 * scale) as u32);
+
+    let mut target = get_centered_rect(area.width(), area.height(), width, height, area.width(), area.height());
+    target.offset(area.x(), area.y());
+
+    canvas.copy(&texture, None, Some(target))
+}
+
+#[cfg(all(test, feature = "text"))]
+mod tests {
+    use super::*;
+    use crate::main_loop::State;
+    use crate::main_loop::places::Places;
+    use crate::main_loop::weather_cache::{refresh, WeatherCache};
+    use crate::main_loop::weather_provider::FixtureProvider;
+    use crate::main_loop::{offscreen_canvas, AudioHandle, Capture, CaptureMutex, ClockHandle, Events, NullAudio, Ringer, SystemClock};
+    use std::cell::Cell;
+    use crate::main_loop::alarms::{AlarmSettings, Alarms};
+    use crate::main_loop::timers::Timers;
+    use sdl2::surface::Surface;
+
+    const FIXTURES : &str = "tests/fixtures/weather";
+    const FONT : &str = "tests/fixtures/font.ttf";
+
+    // Render the weather mode once on an offscreen canvas, after refreshing the weather at the
+    // given location, with "done" already waiting
+    fn render(location : &str) -> Result<Surface<'static>, String> {
+        let cache : WeatherCacheMutex = Arc::new(Mutex::new(WeatherCache::new(location, &[])));
+        refresh(&FixtureProvider::new(FIXTURES), &mut Places::load(""), location, &cache, &SystemClock);
+
+        let ttf_context = sdl2::ttf::init().unwrap();
+        let canvas = offscreen_canvas(320, 240)?;
+        let texture_creator = canvas.texture_creator();
+
+        let state : StateMutex = Arc::new(Mutex::new(State::new()));
+        state.lock().unwrap().current_intent = INTENT_DONE.to_owned();
+        let new_intent_available = Arc::new((Mutex::new(true), Condvar::new()));
+
+        let clock : ClockHandle = Arc::new(SystemClock);
+        let capture : CaptureMutex = Arc::new(Mutex::new(Capture::from_settings(&Settings::default(), clock.clone())));
+
+        // Nothing's due, so nothing rings
+        let audio : AudioHandle = Arc::new(NullAudio::new(clock.clone()));
+        let sdl_context = sdl2::init()?;
+        let ringer = Ringer { sdl_context : &sdl_context, settings : &AlarmSettings::from_settings(&Settings::default()).unwrap(),
+                              alarms : &Arc::new(Mutex::new(Alarms::load("", Duration::from_secs(60), clock.clone()))),
+                              timers : &Arc::new(Mutex::new(Timers::new(clock.clone()))), audio : &audio,
+                              events : &Arc::new(Events::new(clock.clone())), quit : Cell::new(false) };
+
+        let mut screen = Screen { res_width : 320, res_height : 240, canvas, texture_creator, ttf_context : &ttf_context, font_path : FONT,
+                                  capture : &capture, clock : clock.as_ref(), ringer : &ringer };
+
+        show_weather(&mut screen, &cache, &WeatherSettings::from_settings(&Settings::default()).unwrap(), "", &HashMap::new(),
+                     state, new_intent_available)?;
+
+        Ok(screen.canvas.into_surface())
+    }
+
+    // Is any (black) text drawn inside the given area? Nothing else is black
+    fn has_text(surface : &Surface, area : Rect) -> bool {
+        let pitch = surface.pitch() as usize;
+        let pixels = surface.without_lock().unwrap();
+
+        (area.top()..area.bottom()).any(|y| (area.left()..area.right()).any(|x| {
+            let pixel = y as usize * pitch + x as usize * 4;
+            pixels[pixel..pixel + 3] == [0, 0, 0]
+        }))
+    }
+
+    #[test]
+    fn renders_the_weather_report() {
+        let surface = render("Zaragoza,ES").unwrap();
+
+        // The title, the temperature (on the right half, below the title), the details and
+        // the forecast each take their own rows of the 240 pixels
+        assert!(has_text(&surface, Rect::new(0, 0, 320, 30)), "title");
+        assert!(has_text(&surface, Rect::new(160, 30, 160, 90)), "temperature");
+        assert!(! has_text(&surface, Rect::new(0, 30, 160, 120)), "no icon faces, so nothing on the left");
+        assert!(has_text(&surface, Rect::new(0, 150, 320, 30)), "details");
+        assert!(has_text(&surface, Rect::new(0, 210, 320, 30)), "forecast");
+    }
+
+    #[test]
+    fn shows_the_provider_errors() {
+        let surface = render("Nowhere,XX").unwrap();
+
+        // A single line of text is drawn in the middle of the screen instead
+        assert!(has_text(&surface, Rect::new(0, 90, 320, 60)));
+        assert!(! has_text(&surface, Rect::new(0, 0, 320, 80)));
+        assert!(! has_text(&surface, Rect::new(0, 160, 320, 80)));
+    }
+
+    #[test]
+    fn condition_codes_map_to_intents() {
+        assert_eq!(condition_intent(211), "weather_thunderstorm");
+        assert_eq!(condition_intent(301), "weather_rain");
+        assert_eq!(condition_intent(500), "weather_rain");
+        assert_eq!(condition_intent(601), "weather_snow");
+        assert_eq!(condition_intent(741), "weather_mist");
+        assert_eq!(condition_intent(800), "weather_clear");
+        assert_eq!(condition_intent(804), "weather_clouds");
+    }
 }
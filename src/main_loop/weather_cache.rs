@@ -0,0 +1,277 @@
+extern crate chrono;
+extern crate openweathermap;
+
+use chrono::{DateTime, Local};
+use openweathermap::CurrentWeather;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{Clock, ClockHandle};
+use super::places::Places;
+use super::weather_provider::{DailyForecast, Location, WeatherProvider};
+
+/// Weather at a location as fetched in a single refresh
+pub struct WeatherReport {
+    pub current : CurrentWeather,
+    pub forecast : Vec<DailyForecast>,
+    pub fetched_at : DateTime<Local>,
+}
+
+// Last good report of a location, and why the last refresh failed (if it did)
+#[derive(Default)]
+struct CachedWeather {
+    report : Option<WeatherReport>,
+    error : Option<String>,
+}
+
+/// Last good weather report of each location asked for, kept up to date in the background so
+/// that the weather screen never has to wait for the network.
+///
+/// The home location is the one provided at launch, which is refreshed from the start along
+/// with the favourite ones, and kept up to date for as long as BMO runs. The rest are added
+/// when they're asked for (see want), and only kept up to date while they're still being asked
+/// for and fetching them works.
+pub struct WeatherCache {
+    home : String,
+    kept : Vec<String>, // The home location and the favourite ones
+    locations : HashMap<String, CachedWeather>,
+    wanted : Vec<String>, // Locations asked for which haven't been fetched yet
+    asked : HashSet<String>, // Locations asked for since the last refresh
+}
+
+impl WeatherCache {
+    pub fn new(home : &str, favourites : &[String]) -> WeatherCache {
+        let mut kept = vec![home.to_owned()];
+        kept.extend(favourites.iter().filter(|favourite| *favourite != home).cloned());
+
+        WeatherCache { home : home.to_owned(), wanted : kept.clone(), kept, locations : HashMap::new(), asked : HashSet::new() }
+    }
+
+    pub fn home(&self) -> &str {
+        &self.home
+    }
+
+    pub fn report(&self, location : &str) -> Option<&WeatherReport> {
+        self.locations.get(location).and_then(|cached| cached.report.as_ref())
+    }
+
+    /// Error of the last refresh of the location, if it failed
+    pub fn error(&self, location : &str) -> Option<&str> {
+        self.locations.get(location).and_then(|cached| cached.error.as_deref())
+    }
+
+    /// When the location's report was fetched, if the last refresh failed and it's therefore out of date
+    pub fn stale_since(&self, location : &str) -> Option<DateTime<Local>> {
+        match self.locations.get(location) {
+            Some(CachedWeather { report : Some(report), error : Some(_) }) => Some(report.fetched_at),
+            _ => None,
+        }
+    }
+
+    /// Ask for the weather at a location, which is fetched right away if it wasn't before
+    pub fn want(&mut self, location : &str) {
+        if ! self.locations.contains_key(location) && ! self.wanted.iter().any(|wanted| wanted == location) {
+            self.wanted.push(location.to_owned());
+        }
+
+        if ! self.asked.contains(location) {
+            self.asked.insert(location.to_owned());
+        }
+    }
+
+    // Locations to refresh once the interval has passed: the kept ones, and the rest which have
+    // been asked for since the last time, unless their last refresh failed. The other ones are
+    // dropped, so that they aren't refreshed forever.
+    fn take_due(&mut self) -> Vec<String> {
+        let asked = std::mem::take(&mut self.asked);
+        let kept = &self.kept;
+        self.locations.retain(|location, cached| kept.contains(location) || (asked.contains(location) && cached.error.is_none()));
+
+        let mut due = self.kept.clone();
+        due.extend(self.locations.keys().filter(|location| ! kept.contains(location)).cloned());
+        for wanted in self.wanted.drain(..) {
+            if ! due.contains(&wanted) {
+                due.push(wanted);
+            }
+        }
+
+        due
+    }
+
+    // Store the result of refreshing a location
+    fn store(&mut self, location : &str, fetched : Result<WeatherReport, String>) {
+        let cached = self.locations.entry(location.to_owned()).or_default();
+
+        match fetched {
+            Ok(report) => {
+                cached.report = Some(report);
+                cached.error = None;
+            }
+            Err(e) => {
+                eprintln!("Couldn't refresh the weather in {}: {}", location, e);
+                cached.error = Some(e);
+            }
+        }
+    }
+}
+
+// Thread-safe WeatherCache instance
+pub type WeatherCacheMutex = Arc<Mutex<WeatherCache>>;
+
+/// Fetch the weather at the given location and store it in the cache. If it fails, the previous
+/// report is kept and the error is recorded instead.
+///
+/// The home location is fetched by its name, while the rest are looked up in the places first
+/// ("Madrid ES") and fetched by their coordinates. The provider is called without holding the lock, so the weather screen isn't
+/// blocked meanwhile. The report is timestamped with the given clock.
+pub fn refresh(provider : &dyn WeatherProvider, places : &mut Places, location : &str, cache : &WeatherCacheMutex, clock : &dyn Clock) {
+    let home = cache.lock().unwrap().home == location;
+
+    let fetched = if home {
+        fetch(provider, &Location::Name(location), clock)
+    } else {
+        places.resolve(provider, location).and_then(|place| fetch(provider, &Location::Place(&place), clock))
+    };
+
+    cache.lock().unwrap().store(location, fetched);
+}
+
+fn fetch(provider : &dyn WeatherProvider, location : &Location, clock : &dyn Clock) -> Result<WeatherReport, String> {
+    let current = provider.current(location)?;
+
+    // The forecast is a nice to have, keep the current weather anyway
+    let forecast = provider.forecast(location).unwrap_or_else(|e| {
+        eprintln!("Couldn't get the weather forecast: {}", e);
+        Vec::new()
+    });
+
+    Ok(WeatherReport { current, forecast, fetched_at : clock.local_now() })
+}
+
+/// Keep refreshing the weather in the cache in a background thread, at the given interval of the
+/// given clock (see WeatherCache for which locations are). The locations asked for meanwhile are
+/// fetched right away.
+pub fn spawn_refresher(provider : Box<dyn WeatherProvider>, mut places : Places, interval : Duration,
+                       clock : ClockHandle, cache : WeatherCacheMutex) {
+    thread::spawn(move || {
+        let mut last_refresh : Option<Instant> = None;
+
+        loop {
+            let locations : Vec<String> = {
+                let mut cache = cache.lock().unwrap();
+
+                let now = clock.now();
+
+                if last_refresh.map_or(true, |last_refresh| now - last_refresh >= interval) {
+                    last_refresh = Some(now);
+
+                    cache.take_due()
+                } else {
+                    cache.wanted.drain(..).collect()
+                }
+            };
+
+            for location in locations {
+                refresh(provider.as_ref(), &mut places, &location, &cache, clock.as_ref());
+            }
+
+            // Polled in real time, since only the main loop is meant to sleep on the clock
+            thread::sleep(Duration::from_millis(500));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::main_loop::weather_provider::FixtureProvider;
+    use crate::main_loop::{ManualClock, SystemClock};
+    use chrono::TimeZone;
+
+    const FIXTURES : &str = "tests/fixtures/weather";
+
+    // Refresh a location with the fixtures, without storing the places looked up
+    fn refresh_fixture(location : &str, cache : &WeatherCacheMutex) {
+        refresh(&FixtureProvider::new(FIXTURES), &mut Places::load(""), location, cache, &SystemClock);
+    }
+
+    #[test]
+    fn refreshing_stores_the_report() {
+        let cache : WeatherCacheMutex = Arc::new(Mutex::new(WeatherCache::new("Zaragoza,ES", &[])));
+        refresh_fixture("Zaragoza,ES", &cache);
+
+        let cache = cache.lock().unwrap();
+        assert_eq!(cache.report("Zaragoza,ES").unwrap().current.name, "Zaragoza");
+        assert_eq!(cache.report("Zaragoza,ES").unwrap().forecast.len(), 3);
+        assert!(cache.error("Zaragoza,ES").is_none());
+        assert!(cache.stale_since("Zaragoza,ES").is_none());
+    }
+
+    #[test]
+    fn reports_are_timestamped_with_the_clock() {
+        let clock = ManualClock::starting_at(Local.with_ymd_and_hms(2026, 10, 18, 7, 0, 0).unwrap());
+        let cache : WeatherCacheMutex = Arc::new(Mutex::new(WeatherCache::new("Zaragoza,ES", &[])));
+        clock.advance(Duration::from_secs(90));
+        refresh(&FixtureProvider::new(FIXTURES), &mut Places::load(""), "Zaragoza,ES", &cache, &clock);
+
+        assert_eq!(cache.lock().unwrap().report("Zaragoza,ES").unwrap().fetched_at, Local.with_ymd_and_hms(2026, 10, 18, 7, 1, 30).unwrap());
+    }
+
+    #[test]
+    fn failed_refreshes_keep_the_last_report() {
+        let cache : WeatherCacheMutex = Arc::new(Mutex::new(WeatherCache::new("Zaragoza,ES", &[])));
+        refresh_fixture("Zaragoza,ES", &cache);
+        refresh(&FixtureProvider::new("tests/fixtures/missing"), &mut Places::load(""), "Zaragoza,ES", &cache, &SystemClock);
+
+        let cache = cache.lock().unwrap();
+        assert_eq!(cache.report("Zaragoza,ES").unwrap().current.name, "Zaragoza");
+        assert!(cache.error("Zaragoza,ES").is_some());
+        assert_eq!(cache.stale_since("Zaragoza,ES"), Some(cache.report("Zaragoza,ES").unwrap().fetched_at));
+    }
+
+    #[test]
+    fn failed_first_refreshes_are_not_stale() {
+        let cache : WeatherCacheMutex = Arc::new(Mutex::new(WeatherCache::new("Nowhere,XX", &[])));
+        refresh_fixture("Nowhere,XX", &cache);
+
+        let cache = cache.lock().unwrap();
+        assert!(cache.report("Nowhere,XX").is_none());
+        assert_eq!(cache.error("Nowhere,XX"), Some("city not found"));
+        assert!(cache.stale_since("Nowhere,XX").is_none());
+    }
+
+    #[test]
+    fn other_locations_are_geocoded() {
+        let cache : WeatherCacheMutex = Arc::new(Mutex::new(WeatherCache::new("Zaragoza,ES", &[])));
+        cache.lock().unwrap().want("Madrid ES");
+        refresh_fixture("Madrid ES", &cache);
+        refresh_fixture("Atlantis", &cache);
+
+        let cache = cache.lock().unwrap();
+        assert_eq!(cache.report("Madrid ES").unwrap().current.name, "Madrid");
+        assert_eq!(cache.error("Atlantis"), Some("Couldn't find Atlantis"));
+    }
+
+    #[test]
+    fn only_the_locations_still_asked_for_are_refreshed() {
+        let cache : WeatherCacheMutex = Arc::new(Mutex::new(WeatherCache::new("Zaragoza,ES", &["Madrid ES".to_owned()])));
+        assert_eq!(cache.lock().unwrap().take_due(), vec!["Zaragoza,ES", "Madrid ES"]);
+
+        for location in ["Zaragoza,ES", "Madrid ES", "Madrid,ES", "Atlantis"] {
+            cache.lock().unwrap().want(location);
+        }
+        let wanted : Vec<String> = cache.lock().unwrap().wanted.drain(..).collect();
+        for location in wanted {
+            refresh_fixture(&location, &cache);
+        }
+        assert_eq!(cache.lock().unwrap().take_due(), vec!["Zaragoza,ES", "Madrid ES", "Madrid,ES"]); // Atlantis failed
+
+        // Nobody asked for anything else since
+        let mut cache = cache.lock().unwrap();
+        assert_eq!(cache.take_due(), vec!["Zaragoza,ES", "Madrid ES"]);
+        assert!(cache.report("Madrid,ES").is_none());
+    }
+}
@@ -0,0 +1,362 @@
+extern crate chrono;
+extern crate openweathermap;
+extern crate reqwest;
+extern crate serde;
+extern crate serde_json;
+
+use chrono::{DateTime, NaiveDate, Timelike};
+use openweathermap::{CurrentWeather, Weather};
+use serde::Deserialize;
+
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config::Settings;
+
+/// Source of weather reports, so that the weather mode doesn't depend on how they're fetched.
+/// They're fetched from a background thread (see weather_cache), hence Send.
+pub trait WeatherProvider : Send {
+    /// Current weather at the given location
+    fn current(&self, location : &Location) -> Result<CurrentWeather, String>;
+
+    /// Expected weather for the next days at the given location, one entry per day
+    fn forecast(&self, location : &Location) -> Result<Vec<DailyForecast>, String>;
+
+    /// Look up a place by its name, optionally followed by a comma and its country code ("Madrid,ES")
+    fn geocode(&self, query : &str) -> Result<Place, String>;
+}
+
+/// Place found by geocoding
+#[derive(Clone, Debug, PartialEq)]
+pub struct Place {
+    pub name : String,
+    pub country : String,
+    pub lat : f64,
+    pub lon : f64,
+}
+
+impl Place {
+    /// Location of the place, as taken by the providers ("Madrid,ES")
+    pub fn location(&self) -> String {
+        format!("{},{}", self.name, self.country)
+    }
+}
+
+/// Where the weather is fetched for
+pub enum Location<'a> {
+    /// A city name optionally followed by a comma and its country code ("Zaragoza,ES"), as
+    /// given at launch
+    Name(&'a str),
+    /// A place found by geocoding, fetched by its coordinates
+    Place(&'a Place),
+}
+
+impl Location<'_> {
+    /// Name of the location, or of the place ("Madrid,ES")
+    pub fn name(&self) -> String {
+        match self {
+            Location::Name(name) => (*name).to_owned(),
+            Location::Place(place) => place.location(),
+        }
+    }
+}
+
+/// Expected weather for a day, in the location's local time
+pub struct DailyForecast {
+    pub date : NaiveDate,
+    pub temp_min : f64,
+    pub temp_max : f64,
+    pub condition : Weather, // Around midday
+}
+
+/// Build the weather provider chosen in the settings:
+/// - **weather_provider** : Either openweathermap (the default) or fixture.
+/// - **weather_url** : Base URL of the OpenWeatherMap API (http://api.openweathermap.org by default),
+///   which can point to a local stand-in.
+/// - **weather_fixtures** : Folder with the canned responses used by the fixture provider
+///   (see FixtureProvider).
+/// - **weather_units** : Either metric (the default), imperial or standard.
+/// - **weather_lang** : Language of the descriptions (en by default), as an OpenWeatherMap language code.
+///
+/// Returns None if OpenWeatherMap is chosen but there is no API key.
+pub fn from_settings(settings : &Settings, api_key : Option<String>) -> Option<Box<dyn WeatherProvider>> {
+    match settings.get_or("weather_provider", "openweathermap").as_str() {
+        "fixture" => Some(Box::new(FixtureProvider::new(&settings.get_or("weather_fixtures", "assets/weather")))),
+        _ => api_key.map(|api_key| Box::new(OpenWeatherMap::new(&settings.get_or("weather_url", "http://api.openweathermap.org"),
+                                                                &api_key,
+                                                                &settings.get_or("weather_units", "metric"),
+                                                                &settings.get_or("weather_lang", "en"))) as Box<dyn WeatherProvider>),
+    }
+}
+
+/// Fetches the weather from the OpenWeatherMap API
+pub struct OpenWeatherMap {
+    base_url : String,
+    api_key : String,
+    units : String,
+    lang : String,
+    client : reqwest::blocking::Client,
+}
+
+impl OpenWeatherMap {
+    pub fn new(base_url : &str, api_key : &str, units : &str, lang : &str) -> OpenWeatherMap {
+        OpenWeatherMap {
+            base_url : base_url.trim_end_matches('/').to_owned(),
+            api_key : api_key.to_owned(),
+            units : units.to_owned(),
+            lang : lang.to_owned(),
+            client : reqwest::blocking::Client::new(),
+        }
+    }
+
+    // GET one of the API's endpoints ("data/2.5/weather") with the given query, returning the response's body
+    fn get(&self, endpoint : &str, query : &[(&str, &str)]) -> Result<String, String> {
+        self.client.get(format!("{}/{}", self.base_url, endpoint))
+                   .query(query)
+                   .query(&[("appid", &self.api_key)])
+                   .send()
+                   .and_then(|response| response.text())
+                   .map_err(|e| e.to_string())
+    }
+
+    fn get_weather(&self, endpoint : &str, location : &Location) -> Result<String, String> {
+        match location {
+            Location::Name(name) => self.get(endpoint, &[("q", name), ("units", &self.units), ("lang", &self.lang)]),
+            Location::Place(place) => self.get(endpoint, &[("lat", &place.lat.to_string()), ("lon", &place.lon.to_string()),
+                                                           ("units", &self.units), ("lang", &self.lang)]),
+        }
+    }
+}
+
+impl WeatherProvider for OpenWeatherMap {
+    fn current(&self, location : &Location) -> Result<CurrentWeather, String> {
+        parse_current(&self.get_weather("data/2.5/weather", location)?)
+    }
+
+    fn forecast(&self, location : &Location) -> Result<Vec<DailyForecast>, String> {
+        parse_forecast(&self.get_weather("data/2.5/forecast", location)?)
+    }
+
+    fn geocode(&self, query : &str) -> Result<Place, String> {
+        parse_places(query, &self.get("geo/1.0/direct", &[("q", query), ("limit", "1")])?)
+    }
+}
+
+/// Returns canned responses stored on disk, so that the weather mode can be used without a network.
+///
+/// The current weather for a location is read from "location.json" inside the provider's folder
+/// ("Zaragoza,ES.json", or the place's name and country code if it was geocoded), falling back to "weather.json" if there is no file for it. In the same way,
+/// its forecast is read from "location.forecast.json", falling back to "forecast.json", and the
+/// places found by geocoding from "query.geo.json", falling back to "geo.json". The files have
+/// the same contents as the OpenWeatherMap API's responses, including the error ones.
+pub struct FixtureProvider {
+    folder : PathBuf,
+}
+
+impl FixtureProvider {
+    pub fn new(folder : &str) -> FixtureProvider {
+        FixtureProvider { folder : PathBuf::from(folder) }
+    }
+
+    // Read the given fixture, or the default one if it doesn't exist
+    fn read(&self, file : &str, default : &str) -> Result<String, String> {
+        let path = self.folder.join(file);
+        let path = if path.exists() { path } else { self.folder.join(default) };
+
+        fs::read_to_string(&path).map_err(|e| format!("Couldn't read {}: {}", path.display(), e))
+    }
+}
+
+impl WeatherProvider for FixtureProvider {
+    fn current(&self, location : &Location) -> Result<CurrentWeather, String> {
+        parse_current(&self.read(&format!("{}.json", location.name()), "weather.json")?)
+    }
+
+    fn forecast(&self, location : &Location) -> Result<Vec<DailyForecast>, String> {
+        parse_forecast(&self.read(&format!("{}.forecast.json", location.name()), "forecast.json")?)
+    }
+
+    fn geocode(&self, query : &str) -> Result<Place, String> {
+        parse_places(query, &self.read(&format!("{}.geo.json", query), "geo.json")?)
+    }
+}
+
+// Parse an OpenWeatherMap current weather response
+fn parse_current(body : &str) -> Result<CurrentWeather, String> {
+    parse_response(body)
+}
+
+// 5 day / 3 hour forecast response, only with the fields we need
+#[derive(Deserialize)]
+struct ForecastResponse {
+    list : Vec<ForecastEntry>,
+    city : ForecastCity,
+}
+
+#[derive(Deserialize)]
+struct ForecastEntry {
+    dt : i64,
+    main : ForecastMain,
+    weather : Vec<Weather>,
+}
+
+#[derive(Deserialize)]
+struct ForecastMain {
+    temp_min : f64,
+    temp_max : f64,
+}
+
+#[derive(Deserialize)]
+struct ForecastCity {
+    timezone : i64, // Shift in seconds from UTC
+}
+
+// Parse an OpenWeatherMap forecast response, grouping its 3-hour entries by day
+fn parse_forecast(body : &str) -> Result<Vec<DailyForecast>, String> {
+    let response : ForecastResponse = parse_response(body)?;
+    let mut days : Vec<DailyForecast> = Vec::new();
+    let mut midday_distance = 24; // How far (in hours) the current day's condition is from midday
+
+    for mut entry in response.list {
+        let local = match DateTime::from_timestamp(entry.dt + response.city.timezone, 0) {
+            Some(local) => local.naive_utc(),
+            None => continue,
+        };
+        let distance = (local.hour() as i32 - 12).unsigned_abs();
+        let condition = match entry.weather.pop() {
+            Some(condition) => condition,
+            None => continue,
+        };
+
+        match days.last_mut() {
+            Some(day) if day.date == local.date() => {
+                day.temp_min = day.temp_min.min(entry.main.temp_min);
+                day.temp_max = day.temp_max.max(entry.main.temp_max);
+
+                if distance < midday_distance {
+                    day.condition = condition;
+                    midday_distance = distance;
+                }
+            }
+            _ => {
+                days.push(DailyForecast { date : local.date(), temp_min : entry.main.temp_min,
+                                          temp_max : entry.main.temp_max, condition });
+                midday_distance = distance;
+            }
+        }
+    }
+
+    Ok(days)
+}
+
+// Place in a geocoding response, only with the fields we need
+#[derive(Deserialize)]
+struct GeocodingEntry {
+    name : String,
+    country : String,
+    lat : f64,
+    lon : f64,
+}
+
+// Parse an OpenWeatherMap geocoding response, returning its first place
+fn parse_places(query : &str, body : &str) -> Result<Place, String> {
+    let mut places : Vec<GeocodingEntry> = parse_response(body)?;
+
+    if places.is_empty() {
+        return Err(format!("Couldn't find {}", query));
+    }
+
+    let place = places.swap_remove(0);
+    Ok(Place { name : place.name, country : place.country, lat : place.lat, lon : place.lon })
+}
+
+// Parse an OpenWeatherMap response, turning the error ones ({"cod":"404","message":"city not found"})
+// into their message
+fn parse_response<T : serde::de::DeserializeOwned>(body : &str) -> Result<T, String> {
+    serde_json::from_str::<T>(body).map_err(|e| {
+        match serde_json::from_str::<serde_json::Value>(body).ok().and_then(|value| value["message"].as_str().map(|m| m.to_owned())) {
+            Some(message) => message,
+            None => format!("Couldn't parse the weather response: {}", e),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURES : &str = "tests/fixtures/weather";
+
+    #[test]
+    fn fixture_provider_reads_the_location_file() {
+        let weather = FixtureProvider::new(FIXTURES).current(&Location::Name("Zaragoza,ES")).unwrap();
+
+        assert_eq!(weather.name, "Zaragoza");
+        assert_eq!(weather.weather[0].description, "clear sky");
+    }
+
+    #[test]
+    fn fixture_provider_falls_back_to_the_default_file() {
+        let weather = FixtureProvider::new(FIXTURES).current(&Location::Name("Anywhere,XX")).unwrap();
+
+        assert_eq!(weather.name, "Zaragoza");
+    }
+
+    #[test]
+    fn error_responses_become_their_message() {
+        let error = FixtureProvider::new(FIXTURES).current(&Location::Name("Nowhere,XX")).err().unwrap();
+
+        assert_eq!(error, "city not found");
+    }
+
+    #[test]
+    fn forecasts_are_grouped_by_day() {
+        let forecast = FixtureProvider::new(FIXTURES).forecast(&Location::Name("Zaragoza,ES")).unwrap();
+
+        assert_eq!(forecast.len(), 3);
+        assert_eq!(forecast[1].date, NaiveDate::from_ymd_opt(2026, 10, 19).unwrap());
+        assert_eq!(forecast[1].temp_min, 11.2);
+        assert_eq!(forecast[1].temp_max, 22.5);
+        assert_eq!(forecast[1].condition.main, "Rain"); // The midday one
+    }
+
+    #[test]
+    fn places_are_geocoded() {
+        let place = FixtureProvider::new(FIXTURES).geocode("Madrid,ES").unwrap();
+
+        assert_eq!(place.location(), "Madrid,ES");
+        assert_eq!(place.lat, 40.4167);
+        assert_eq!(FixtureProvider::new(FIXTURES).geocode("Atlantis").err().unwrap(), "Couldn't find Atlantis");
+    }
+
+    #[test]
+    fn missing_fixtures_are_errors() {
+        assert!(FixtureProvider::new("tests/fixtures/missing").current(&Location::Name("Zaragoza,ES")).is_err());
+    }
+
+    #[test]
+    fn geocoded_places_are_fetched_by_their_coordinates() {
+        use std::io::{BufRead, BufReader, Write};
+        use std::net::TcpListener;
+
+        // Stand-in for the API, answering a single request with the fixture
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = String::new();
+            BufReader::new(&stream).read_line(&mut request).unwrap();
+
+            let body = fs::read_to_string(format!("{}/weather.json", FIXTURES)).unwrap();
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body).unwrap();
+            request
+        });
+
+        let madrid = Place { name : "Madrid".to_owned(), country : "ES".to_owned(), lat : 40.4167, lon : -3.7033 };
+        OpenWeatherMap::new(&url, "key", "metric", "en").current(&Location::Place(&madrid)).unwrap();
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("GET /data/2.5/weather?lat=40.4167&lon=-3.7033&"), "{}", request);
+        assert!(! request.contains("q="), "{}", request);
+    }
+}
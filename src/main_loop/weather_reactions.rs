@@ -0,0 +1,257 @@
+extern crate chrono;
+
+use chrono::{DateTime, Local, NaiveDateTime, NaiveTime};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+
+use crate::config::parse_assets;
+use super::weather::condition_intent;
+use super::weather_cache::WeatherReport;
+
+/// File mapping the weather to intents, optional
+pub const WEATHER_REACTIONS_FILE : &str = "weather_reactions.txt";
+
+// Something the weather can be, as written in the file
+enum Condition {
+    Kind(String), // clear, clouds, rain, snow, thunderstorm or mist
+    Above(f64), // above 30, temperature in the weather_units
+    Below(f64), // below 5
+}
+
+// When an intent is shown
+enum Trigger {
+    Default(Condition), // default when rain, instead of the default face while it holds
+    Starts(Condition), // starts rain, once, when it didn't hold on the previous report
+    Drops(f64), // drops 5, once, when the temperature drops that much from the previous report
+    At(NaiveTime, Option<Condition>), // at 08:00 [when rain], every day at that time (if it holds)
+}
+
+// Weather as seen on a report, what the conditions are checked against
+struct Observed {
+    kind : String,
+    temp : f64,
+    fetched_at : DateTime<Local>,
+}
+
+/// Intents shown in reaction to the weather, read from WEATHER_REACTIONS_FILE.
+///
+/// # Format
+/// Each intent is enclosed between [...], followed by the situations in which it's shown, one per line:
+/// - **default when condition** : Shown instead of the default face while the condition holds.
+///   If several intents match, the first one in alphabetical order is used.
+/// - **starts condition** : Announced when the condition starts holding (rain starts).
+/// - **drops degrees** : Announced when the temperature drops at least that much between reports.
+/// - **at hh:mm [when condition]** : Announced every day at that time, if the condition holds.
+///
+/// The conditions are the kind of weather (clear, clouds, rain, snow, thunderstorm or mist),
+/// "above degrees" or "below degrees".
+///
+/// # Example file
+/// [rainy]
+/// default when rain
+///
+/// [umbrella]
+/// at 08:00 when rain
+/// starts rain
+pub struct WeatherReactions {
+    rules : Vec<(String, Trigger)>, // Intent and when it's shown
+    observed : Option<Observed>, // Latest report
+    last_update : NaiveDateTime, // When the announcements were last checked
+}
+
+impl WeatherReactions {
+    /// Read the reactions from the given file, if it exists. Files which can't be read, and
+    /// lines which can't be parsed, are errors.
+    pub fn load(path : &str, now : NaiveDateTime) -> Result<WeatherReactions, String> {
+        let mut rules = Vec::new();
+
+        let entries = match parse_assets(path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(format!("Couldn't load {}: {}", path, e)),
+        };
+        let mut intents : Vec<&String> = entries.keys().filter(|intent| ! intent.is_empty()).collect();
+        intents.sort();
+
+        for intent in intents {
+            for line in entries[intent].iter() {
+                let trigger = parse_trigger(line.trim())
+                    .ok_or(format!("Invalid weather reaction for {} in {}: {}", intent, path, line.trim()))?;
+                rules.push((intent.clone(), trigger));
+            }
+        }
+
+        Ok(WeatherReactions { rules, observed : None, last_update : now })
+    }
+
+    /// Intent to be shown instead of the default face with the latest report, if any
+    pub fn default_intent(&self) -> Option<&str> {
+        let observed = self.observed.as_ref()?;
+
+        self.rules.iter().find(|(_, trigger)| match trigger {
+            Trigger::Default(condition) => holds(condition, observed),
+            _ => false,
+        }).map(|(intent, _)| intent.as_str())
+    }
+
+    /// Check the latest report, returning the intents to be announced since the last update
+    pub fn update(&mut self, report : Option<&WeatherReport>, now : NaiveDateTime) -> Vec<String> {
+        let mut announced = Vec::new();
+
+        // Changes between reports
+        if let Some(report) = report {
+            if self.observed.as_ref().map_or(true, |observed| observed.fetched_at != report.fetched_at) {
+                let current = Observed {
+                    kind : condition_intent(report.current.weather.first().map_or(800, |c| c.id)).trim_start_matches("weather_").to_owned(),
+                    temp : report.current.main.temp,
+                    fetched_at : report.fetched_at,
+                };
+
+                if let Some(ref previous) = self.observed {
+                    for (intent, trigger) in self.rules.iter() {
+                        let fires = match trigger {
+                            Trigger::Starts(condition) => holds(condition, &current) && ! holds(condition, previous),
+                            Trigger::Drops(degrees) => previous.temp - current.temp >= *degrees,
+                            _ => false,
+                        };
+
+                        if fires { announced.push(intent.clone()); }
+                    }
+                }
+
+                self.observed = Some(current);
+            }
+        }
+
+        // Times of the day passed since the last update
+        for (intent, trigger) in self.rules.iter() {
+            if let Trigger::At(time, condition) = trigger {
+                let today = now.date().and_time(*time);
+                let passed = self.last_update < today && today <= now;
+
+                let holds = match (condition, &self.observed) {
+                    (None, _) => true,
+                    (Some(condition), Some(observed)) => holds(condition, observed),
+                    (Some(_), None) => false,
+                };
+
+                if passed && holds { announced.push(intent.clone()); }
+            }
+        }
+
+        self.last_update = now;
+
+        announced
+    }
+}
+
+// Parse a line of WEATHER_REACTIONS_FILE
+fn parse_trigger(line : &str) -> Option<Trigger> {
+    let words : Vec<&str> = line.split_whitespace().collect();
+
+    match words[..] {
+        ["default", "when", ref condition @ ..] => Some(Trigger::Default(parse_condition(condition)?)),
+        ["starts", ref condition @ ..] => Some(Trigger::Starts(parse_condition(condition)?)),
+        ["drops", degrees] => Some(Trigger::Drops(degrees.parse().ok()?)),
+        ["at", time] => Some(Trigger::At(NaiveTime::parse_from_str(time, "%H:%M").ok()?, None)),
+        ["at", time, "when", ref condition @ ..] => Some(Trigger::At(NaiveTime::parse_from_str(time, "%H:%M").ok()?,
+                                                                     Some(parse_condition(condition)?))),
+        _ => None,
+    }
+}
+
+fn parse_condition(words : &[&str]) -> Option<Condition> {
+    match *words {
+        ["above", degrees] => Some(Condition::Above(degrees.parse().ok()?)),
+        ["below", degrees] => Some(Condition::Below(degrees.parse().ok()?)),
+        [kind] if ["clear", "clouds", "rain", "snow", "thunderstorm", "mist"].contains(&kind) => Some(Condition::Kind(kind.to_owned())),
+        _ => None,
+    }
+}
+
+fn holds(condition : &Condition, observed : &Observed) -> bool {
+    match condition {
+        Condition::Kind(kind) => observed.kind == *kind,
+        Condition::Above(degrees) => observed.temp > *degrees,
+        Condition::Below(degrees) => observed.temp < *degrees,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::main_loop::weather_provider::{FixtureProvider, Location, WeatherProvider};
+    use chrono::NaiveDate;
+
+    const FIXTURES : &str = "tests/fixtures/weather";
+
+    fn at(hour : u32, min : u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 10, 18).unwrap().and_hms_opt(hour, min, 0).unwrap()
+    }
+
+    fn reactions(rules : &[(&str, &str)]) -> WeatherReactions {
+        WeatherReactions {
+            rules : rules.iter().map(|(intent, line)| (intent.to_string(), parse_trigger(line).unwrap())).collect(),
+            observed : None,
+            last_update : at(7, 0),
+        }
+    }
+
+    // Report of the weather fixture fetched at the given time, with the given condition code and temperature
+    fn report(code : u64, temp : f64, fetched_at : NaiveDateTime) -> WeatherReport {
+        let mut current = FixtureProvider::new(FIXTURES).current(&Location::Name("Zaragoza,ES")).unwrap();
+        current.weather[0].id = code;
+        current.main.temp = temp;
+
+        WeatherReport { current, forecast : Vec::new(), fetched_at : fetched_at.and_local_timezone(Local).unwrap() }
+    }
+
+    #[test]
+    fn default_faces_follow_the_weather() {
+        let mut reactions = reactions(&[("hot", "default when above 30"), ("rainy", "default when rain")]);
+        assert_eq!(reactions.default_intent(), None);
+
+        reactions.update(Some(&report(500, 20.0, at(7, 1))), at(7, 1));
+        assert_eq!(reactions.default_intent(), Some("rainy"));
+
+        reactions.update(Some(&report(800, 35.0, at(7, 2))), at(7, 2));
+        assert_eq!(reactions.default_intent(), Some("hot"));
+    }
+
+    #[test]
+    fn changes_are_announced_once() {
+        let mut reactions = reactions(&[("umbrella", "starts rain"), ("cold", "drops 5")]);
+
+        assert!(reactions.update(Some(&report(800, 20.0, at(7, 1))), at(7, 1)).is_empty()); // Nothing to compare with
+        assert_eq!(reactions.update(Some(&report(500, 14.0, at(7, 2))), at(7, 2)), vec!["umbrella", "cold"]);
+        assert!(reactions.update(Some(&report(501, 13.0, at(7, 3))), at(7, 3)).is_empty()); // Still raining
+    }
+
+    #[test]
+    fn times_of_the_day_are_announced_if_the_condition_holds() {
+        let mut reactions = reactions(&[("umbrella", "at 08:00 when rain"), ("morning", "at 08:00")]);
+
+        assert!(reactions.update(Some(&report(800, 20.0, at(7, 59))), at(7, 59)).is_empty());
+        assert_eq!(reactions.update(None, at(8, 5)), vec!["morning"]); // Not raining
+        assert!(reactions.update(None, at(8, 10)).is_empty()); // Only once
+    }
+
+    #[test]
+    fn invalid_lines_are_rejected() {
+        assert!(parse_trigger("default when sunny").is_none());
+        assert!(parse_trigger("at 8am").is_none());
+        assert!(parse_trigger("drops a lot").is_none());
+    }
+
+    #[test]
+    fn invalid_files_are_errors() {
+        let path = std::env::temp_dir().join(format!("bmos_reactions_{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        assert!(WeatherReactions::load(path, at(7, 0)).unwrap().rules.is_empty()); // Not there yet
+
+        std::fs::write(path, "[umbrella]\nstarts rain\nat 8am\n").unwrap();
+        let error = WeatherReactions::load(path, at(7, 0)).err();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(error, Some(format!("Invalid weather reaction for umbrella in {}: at 8am", path)));
+    }
+}
@@ -0,0 +1,179 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::features;
+use crate::main_loop::{self, AudioHandle, ClockHandle, Events, EventsHandle, SystemClock};
+
+const RES_WIDTH : u32 = 320;
+const RES_HEIGHT : u32 = 240;
+
+/// BMO, ready to be run. It's put together with Server::builder:
+///
+/// ```no_run
+/// use bmos_server::{Config, Server};
+///
+/// Server::builder()
+///     .config(Config::load(".")?)
+///     .bind("192.168.1.15", 2300)
+///     .resolution(800, 600)
+///     .run()?;
+/// # Ok::<(), String>(())
+/// ```
+///
+/// Local clients can connect through a Unix domain socket instead, either along with TCP or
+/// without any network port at all:
+///
+/// ```no_run
+/// use bmos_server::{Config, Server};
+///
+/// Server::builder()
+///     .config(Config::load(".")?)
+///     .unix_socket("/run/bmo/bmo.sock")
+///     .run()?;
+/// # Ok::<(), String>(())
+/// ```
+pub struct Server {
+    pub(crate) config : Config,
+    pub(crate) address : Option<(String, u16)>, // None to not listen on TCP
+    pub(crate) unix_socket : Option<PathBuf>,
+    pub(crate) res_width : u32,
+    pub(crate) res_height : u32,
+    pub(crate) api_key : Option<String>,
+    pub(crate) location : Option<String>,
+    pub(crate) country : Option<String>,
+    pub(crate) clock : ClockHandle,
+    pub(crate) audio : Option<AudioHandle>, // None to choose it from the audio setting
+    pub(crate) events : EventsHandle,
+}
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    /// Listen for intents and show BMO until Escape is pressed, or until the client disconnects
+    /// with the exit_on_disconnect setting (see main_loop::run)
+    pub fn run(self) -> Result<(), String> {
+        main_loop::run(self)
+    }
+}
+
+/// Builder for a Server. Only the configuration and something to listen on (an address, unless
+/// BMO was built without the tcp feature, or a Unix socket) are required, everything else has a
+/// default:
+/// - A 320x240 resolution.
+/// - No weather location nor OpenWeather API key.
+/// - The real time (SystemClock).
+/// - The audio backend chosen in the audio setting.
+/// - Events of its own, which nobody outside of BMO subscribes to.
+#[derive(Default)]
+pub struct ServerBuilder {
+    config : Option<Config>,
+    address : Option<(String, u16)>,
+    unix_socket : Option<PathBuf>,
+    resolution : Option<(u32, u32)>,
+    api_key : Option<String>,
+    location : Option<String>,
+    country : Option<String>,
+    clock : Option<ClockHandle>,
+    audio : Option<AudioHandle>,
+    events : Option<EventsHandle>,
+}
+
+impl ServerBuilder {
+    /// Configuration files to use, usually loaded with Config::load
+    pub fn config(mut self, config : Config) -> ServerBuilder {
+        self.config = Some(config);
+        self
+    }
+
+    /// Address and port to listen for intents on
+    pub fn bind(mut self, address : &str, port : u16) -> ServerBuilder {
+        self.address = Some((address.to_owned(), port));
+        self
+    }
+
+    /// Path of a Unix domain socket to listen for intents on, with the same protocol as TCP.
+    /// Its permissions are taken from the unix_socket_mode setting (see intent_receiver::bind_unix).
+    pub fn unix_socket(mut self, path : impl AsRef<Path>) -> ServerBuilder {
+        self.unix_socket = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Size of the window (or offscreen surface) BMO is drawn on
+    pub fn resolution(mut self, width : u32, height : u32) -> ServerBuilder {
+        self.resolution = Some((width, height));
+        self
+    }
+
+    /// OpenWeather API key, needed by the openweather weather provider
+    pub fn api_key(mut self, api_key : &str) -> ServerBuilder {
+        self.api_key = Some(api_key.to_owned());
+        self
+    }
+
+    /// Location (city...) whose weather is shown, along with its country code if needed
+    pub fn location(mut self, location : &str, country : Option<&str>) -> ServerBuilder {
+        self.location = Some(location.to_owned());
+        self.country = country.map(|country| country.to_owned());
+        self
+    }
+
+    /// Clock telling the time, such as a ManualClock for driving BMO from tests
+    pub fn clock(mut self, clock : ClockHandle) -> ServerBuilder {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Audio backend to play the audio tracks with, instead of the one in the audio setting
+    pub fn audio(mut self, audio : AudioHandle) -> ServerBuilder {
+        self.audio = Some(audio);
+        self
+    }
+
+    /// Where BMO publishes what it does, so that it can be subscribed to before it runs
+    /// (see Events::subscribe)
+    pub fn events(mut self, events : EventsHandle) -> ServerBuilder {
+        self.events = Some(events);
+        self
+    }
+
+    /// Put the server together, failing if anything required is missing or if anything asked
+    /// for needs a feature BMO wasn't built with
+    pub fn build(self) -> Result<Server, String> {
+        let config = self.config.ok_or("No configuration was provided")?;
+        config.validate()?;
+
+        match (&self.address, &self.unix_socket) {
+            (Some((address, port)), _) => features::require("tcp", &format!("Listening on {}:{}", address, port))?,
+            (None, None) if features::enabled("tcp") => return Err("No address nor Unix socket to listen on was provided".to_owned()),
+            (None, _) => { } // Nothing to listen with over TCP anyway
+        }
+        if let Some(path) = &self.unix_socket {
+            features::require("unix", &format!("Listening on {}", path.display()))?;
+        }
+        if self.api_key.is_some() || self.location.is_some() {
+            features::require("weather", "Showing the weather")?;
+        }
+
+        let (res_width, res_height) = self.resolution.unwrap_or((RES_WIDTH, RES_HEIGHT));
+        let clock = self.clock.unwrap_or_else(|| Arc::new(SystemClock));
+        let events = self.events.unwrap_or_else(|| Arc::new(Events::new(Arc::clone(&clock))));
+
+        Ok(Server {
+            config, res_width, res_height, clock, events,
+            address : self.address,
+            unix_socket : self.unix_socket,
+            api_key : self.api_key,
+            location : self.location,
+            country : self.country,
+            audio : self.audio,
+        })
+    }
+
+    /// Build the server and run it right away
+    pub fn run(self) -> Result<(), String> {
+        self.build()?.run()
+    }
+}
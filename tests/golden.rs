@@ -0,0 +1,299 @@
+// Golden-image regression tests: BMO is launched in-process on an offscreen surface, paced by a
+// manual clock and with its audio captured, and driven through a TCP client just like bmOS_client would. Each screen is
+// captured with the screenshot command and compared with its golden PNG in tests/golden.
+//
+// Frames without a golden fail the test. Running it with BMO_BLESS_GOLDENS set stores the frames
+// rendered as the goldens instead, replacing those which exist (after an intended change in how
+// a screen looks), so that they can be reviewed and committed.
+
+// Every screen is visited, and BMO is driven through TCP
+#![cfg(all(feature = "text", feature = "weather", feature = "tcp"))]
+
+use sdl2::image::{LoadSurface, SaveSurface};
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::surface::Surface;
+
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bmos_server::main_loop::Played;
+use bmos_server::{CaptureAudio, Config, ManualClock, Server};
+
+const FIXTURES : &str = "tests/fixtures/golden";
+const GOLDENS : &str = "tests/golden";
+const BLESS : &str = "BMO_BLESS_GOLDENS";
+
+const RES_WIDTH : u32 = 320;
+const RES_HEIGHT : u32 = 240;
+
+const TICK : Duration = Duration::from_millis(100); // What the main loop and the chronometer sleep on each frame
+const TIMEOUT : Duration = Duration::from_secs(10); // Real time given to BMO to do anything asked for
+
+const TOLERANCE : u8 = 16; // Difference allowed on each channel of a pixel (font hinting, scaling...)
+const MAX_DIFFERENT : f64 = 0.005; // Ratio of the pixels allowed to differ beyond the tolerance
+
+// BMO running in the background, along with the client driving it
+struct Bmo {
+    clock : Arc<ManualClock>,
+    audio : Arc<CaptureAudio>,
+    stream : TcpStream,
+    replies : BufReader<TcpStream>,
+    frames : PathBuf, // Folder where the frames captured are stored (the screenshots setting)
+    mismatches : Vec<String>,
+}
+
+impl Bmo {
+    // Launch BMO with the fixtures' configuration, and connect to it
+    fn launch() -> Bmo {
+        let config = Config::load(FIXTURES).unwrap();
+        let frames = PathBuf::from(config.settings.get_or("screenshots", "screenshots"));
+
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port(); // Any free one
+        let clock = Arc::new(ManualClock::new());
+        let audio_path = env::temp_dir().join(format!("bmos_golden_audio_{}.wav", std::process::id()));
+        let audio = Arc::new(CaptureAudio::new(audio_path.to_str().unwrap(), Arc::clone(&clock) as _));
+
+        let server = Server::builder().config(config)
+                                      .bind("127.0.0.1", port)
+                                      .resolution(RES_WIDTH, RES_HEIGHT)
+                                      .location("Zaragoza", Some("ES"))
+                                      .clock(Arc::clone(&clock) as _)
+                                      .audio(Arc::clone(&audio) as _)
+                                      .build().unwrap();
+
+        thread::spawn(move || server.run().unwrap());
+
+        let stream = connect(port);
+        let replies = BufReader::new(stream.try_clone().unwrap());
+
+        Bmo { clock, audio, stream, replies, frames, mismatches : Vec::new() }
+    }
+
+    // Send an intent, which isn't replied to
+    fn send(&mut self, line : &str) {
+        writeln!(self.stream, "{}", line).unwrap();
+    }
+
+    // Send a command and wait for its reply
+    fn command(&mut self, line : &str) -> String {
+        self.send(line);
+
+        let mut reply = String::new();
+        self.replies.read_line(&mut reply).unwrap();
+        reply.trim_end().to_owned()
+    }
+
+    // Stopwatch status, along with its laps (see stopwatch::handle_command). The volume is asked
+    // for right after it, to tell where its lines end.
+    fn stopwatch_status(&mut self) -> String {
+        self.send("stopwatch status");
+        self.send("volume");
+
+        let mut status = Vec::new();
+        loop {
+            let mut line = String::new();
+            self.replies.read_line(&mut line).unwrap();
+            if line.starts_with("volume ") {
+                return status.join("\n");
+            }
+            status.push(line.trim_end().to_owned());
+        }
+    }
+
+    // Wait until the stopwatch has the given status. The stopwatch is redrawn in real time, so
+    // this is how each of its intents is known to be handled before sending the next one.
+    fn wait_for_stopwatch(&mut self, status : &str) {
+        let started = Instant::now();
+
+        while self.stopwatch_status() != status {
+            assert!(started.elapsed() < TIMEOUT, "The stopwatch didn't become {:?}", status);
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    // Let some time pass at once: wait until BMO goes to sleep and wake it up once it has passed
+    fn fast_forward(&mut self, duration : Duration) {
+        assert!(self.clock.wait_until_asleep(TIMEOUT), "BMO didn't go to sleep");
+        self.clock.advance(duration);
+    }
+
+    // Let a frame pass
+    fn tick(&mut self) {
+        self.fast_forward(TICK);
+    }
+
+    // Compare the first frame presented after doing something with its golden
+    fn expect_frame(&mut self, name : &str, action : impl FnOnce(&mut Bmo)) {
+        let path = self.frames.join(format!("{}.png", name));
+        let _ = fs::remove_file(&path); // Left by a previous run, it would be taken for the frame
+        assert_eq!(self.command(&format!("screenshot {}.png", name)), format!("screenshot {}", path.display()));
+
+        action(self);
+
+        if let Err(e) = compare(name, &wait_for_png(&path)) {
+            self.mismatches.push(e);
+        }
+    }
+
+    // Wait until the given amount of clips have played (looping ones are written down once
+    // they stop, which they notice in real time), returning them
+    fn wait_for_played(&self, count : usize) -> Vec<Played> {
+        let started = Instant::now();
+
+        while self.audio.played().len() < count && started.elapsed() < TIMEOUT {
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        self.audio.played()
+    }
+}
+
+// Connect to BMO once it's listening
+fn connect(port : u16) -> TcpStream {
+    let started = Instant::now();
+
+    loop {
+        match TcpStream::connect(("127.0.0.1", port)) {
+            Ok(stream) => return stream,
+            Err(e) if started.elapsed() > TIMEOUT => panic!("Couldn't connect to BMO: {}", e),
+            Err(_) => thread::sleep(Duration::from_millis(50)),
+        }
+    }
+}
+
+// Wait until a screenshot has been stored, and read it back
+fn wait_for_png(path : &Path) -> Surface<'static> {
+    let started = Instant::now();
+
+    loop {
+        // It might be found while it's still being written, so try again until it can be read
+        if let Ok(surface) = Surface::from_file(path) {
+            return surface.convert_format(PixelFormatEnum::RGB24).unwrap();
+        }
+
+        assert!(started.elapsed() < TIMEOUT, "No frame was presented for {}", path.display());
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+// Compare a frame with its golden, storing it as the golden instead if blessing was asked for
+fn compare(name : &str, frame : &Surface) -> Result<(), String> {
+    let golden_path = Path::new(GOLDENS).join(format!("{}.png", name));
+
+    if env::var_os(BLESS).is_some() {
+        fs::create_dir_all(GOLDENS).unwrap();
+        frame.save(&golden_path).unwrap();
+        println!("Stored {} as the golden for {}", golden_path.display(), name);
+        return Ok(());
+    }
+    if ! golden_path.exists() {
+        return Err(format!("{}: there's no golden, run the tests with {} set to store it as {}", name, BLESS, golden_path.display()));
+    }
+
+    let golden = Surface::from_file(&golden_path)?.convert_format(PixelFormatEnum::RGB24)?;
+
+    if golden.size() != frame.size() {
+        return Err(format!("{}: the frame is {:?}, but its golden is {:?}", name, frame.size(), golden.size()));
+    }
+
+    let different = different_pixels(frame, &golden);
+    let ratio = different as f64 / (frame.width() * frame.height()) as f64;
+
+    if ratio > MAX_DIFFERENT {
+        return Err(format!("{}: {} pixels ({:.2}%) differ from {}", name, different, ratio * 100.0, golden_path.display()));
+    }
+
+    Ok(())
+}
+
+// Amount of pixels of two RGB24 surfaces of the same size which differ beyond the tolerance
+fn different_pixels(frame : &Surface, golden : &Surface) -> usize {
+    let row_length = frame.width() as usize * 3;
+
+    frame.with_lock(|frame_pixels| golden.with_lock(|golden_pixels| {
+        (0..frame.height() as usize).map(|row| {
+            let frame_row = &frame_pixels[row * frame.pitch() as usize..][..row_length];
+            let golden_row = &golden_pixels[row * golden.pitch() as usize..][..row_length];
+
+            frame_row.chunks(3).zip(golden_row.chunks(3))
+                     .filter(|(a, b)| a.iter().zip(b.iter()).any(|(a, b)| a.abs_diff(*b) > TOLERANCE))
+                     .count()
+        }).sum()
+    }))
+}
+
+#[test]
+fn every_screen_matches_its_golden() {
+    let mut bmo = Bmo::launch();
+
+    bmo.expect_frame("default", |bmo| bmo.tick());
+
+    // Timers running in the background are listed on top of the face
+    assert_eq!(bmo.command("timer tea 3m"), "started tea 00:03:00");
+    assert_eq!(bmo.command("timer eggs 10m"), "started eggs 00:10:00");
+    bmo.expect_frame("timers_overlay", |bmo| bmo.tick());
+    assert_eq!(bmo.command("cancel tea"), "cancelled tea");
+    assert_eq!(bmo.command("cancel eggs"), "cancelled eggs");
+
+    // Stopwatch, with the latest laps below the elapsed time
+    bmo.send("stopwatch");
+    bmo.expect_frame("stopwatch", |bmo| bmo.tick());
+    bmo.send("start");
+    bmo.wait_for_stopwatch("running 00:00:00.00");
+    bmo.clock.advance(Duration::from_millis(62_500));
+    bmo.send("lap");
+    bmo.wait_for_stopwatch("running 00:01:02.50\nlap 1 00:01:02.50 00:01:02.50");
+    bmo.clock.advance(Duration::from_secs(30));
+    bmo.send("lap");
+    bmo.wait_for_stopwatch("running 00:01:32.50\nlap 1 00:01:02.50 00:01:02.50\nlap 2 00:00:30.00 00:01:32.50");
+    bmo.send("stop");
+    bmo.wait_for_stopwatch("stopped 00:01:32.50\nlap 1 00:01:02.50 00:01:02.50\nlap 2 00:00:30.00 00:01:32.50");
+    bmo.expect_frame("stopwatch_laps", |_| { });
+    bmo.send("done");
+    bmo.tick(); // Once it's back to the faces
+
+    // Chronometer entry, with a step (3secmore)
+    bmo.send("chronometer");
+    bmo.expect_frame("chronometer_entry", |bmo| bmo.tick());
+    bmo.expect_frame("chronometer_entry_step", |bmo| bmo.send("3secmore"));
+
+    // Countdown, starting at 00:00:03
+    bmo.expect_frame("chronometer_countdown", |bmo| bmo.send("done"));
+
+    // It reaches zero on the 30th tick, and the alarm goes off
+    for _ in 0..29 {
+        bmo.tick();
+    }
+    bmo.expect_frame("chronometer_alarm", |bmo| bmo.tick());
+    assert_eq!(bmo.command("dismiss"), "dismissed alarm");
+    bmo.tick(); // For the alarm to notice
+
+    // An hour-long timer goes off right after the hour has passed. Its name is too long to fit,
+    // so it's scaled down.
+    assert_eq!(bmo.command("timer spaghetti_carbonara_for_four 1h"), "started spaghetti_carbonara_for_four 01:00:00");
+    bmo.fast_forward(Duration::from_secs(60 * 60) - TICK);
+    assert_eq!(bmo.command("timers"), "spaghetti_carbonara_for_four 00:00:00");
+    bmo.expect_frame("timer_alarm", |bmo| bmo.tick());
+    assert_eq!(bmo.command("timers"), "no timers");
+    assert_eq!(bmo.command("dismiss"), "dismissed spaghetti_carbonara_for_four");
+    bmo.tick();
+
+    // Weather report from the fixtures
+    bmo.send("weather");
+    bmo.expect_frame("weather", |bmo| bmo.tick());
+    bmo.send("done");
+
+    // Both alarms rang until they were dismissed
+    let played = bmo.wait_for_played(2);
+    assert_eq!(played.iter().map(|played| (played.clip.as_str(), played.looping)).collect::<Vec<_>>(),
+               vec![("tests/fixtures/golden/silence.wav", true), ("tests/fixtures/golden/silence.wav", true)]);
+
+    assert!(bmo.mismatches.is_empty(), "Frames differing from their goldens (stored in {}):\n{}",
+            bmo.frames.display(), bmo.mismatches.join("\n"));
+}